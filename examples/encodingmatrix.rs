@@ -0,0 +1,37 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+extern crate mri;
+extern crate num;
+
+use std::rc::Rc;
+
+use mri::EncodingField;
+use mri::EncodingMatrix;
+use mri::KSpace;
+use mri::RFSensitivity;
+use mri::SpatialDims;
+use num::Complex;
+
+fn main() {
+    let fov = SpatialDims::TwoD(0.2, 0.2);
+    let dims = SpatialDims::TwoD(4, 4);
+
+    let ks = KSpace::cartesian(fov.clone(), dims.clone());
+
+    let fx = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap()));
+    let fy = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap()));
+
+    let rf = vec![RFSensitivity::new(vec![Complex::new(1.0, 0.0); dims.product()])];
+
+    let e = EncodingMatrix::new(ks, vec![fx, fy], rf, fov, dims);
+
+    // single voxel in the center of the FOV
+    let mut img = vec![Complex::new(0.0, 0.0); e.num_voxels()];
+    img[10] = Complex::new(1.0, 0.0);
+
+    println!("{:?}", e.apply(&img));
+}
@@ -7,6 +7,8 @@
 
 extern crate mri;
 
+use std::rc::Rc;
+
 use mri::EncodingField;
 use mri::KSpace;
 use mri::LocalKSpace;
@@ -17,20 +19,16 @@ fn main() {
     let fov: f64 = 0.2;
     let nx: usize = 4;
 
-    let linx = &|pos: &SpatialDims<f64>| pos.x().unwrap();
-    let dlinx = &|_pos: &SpatialDims<f64>| SpatialDims::TwoD(1.0, 0.0);
-    let mut fx = EncodingField::new(&linx);
-    fx.derivative(&dlinx);
+    let mut fx = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap()));
+    fx.derivative(Rc::new(|_pos: &SpatialDims<f64>| SpatialDims::TwoD(1.0, 0.0)));
 
-    let liny = &|pos: &SpatialDims<f64>| pos.y().unwrap();
-    let dliny = &|_pos: &SpatialDims<f64>| SpatialDims::TwoD(0.0, 1.0);
-    let mut fy = EncodingField::new(&liny);
-    fy.derivative(&dliny);
+    let mut fy = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap()));
+    fy.derivative(Rc::new(|_pos: &SpatialDims<f64>| SpatialDims::TwoD(0.0, 1.0)));
 
     let ks = KSpace::cartesian(SpatialDims::TwoD(fov, fov), SpatialDims::TwoD(nx, nx));
     println!("{:?}", ks);
 
-    let lk = LocalKSpace::new(ks, vec![fx, fy]);
+    let lk = LocalKSpace::new(&ks, &[fx, fy]);
     let localk = lk.at(&SpatialDims::TwoD(0.1, -0.1));
 
     println!("{:?}", localk);
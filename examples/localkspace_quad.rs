@@ -7,6 +7,7 @@
 
 extern crate mri;
 
+use mri::sphericalharmonics::{index, num_harmonics};
 use mri::EncodingField;
 use mri::KSpace;
 use mri::LocalKSpace;
@@ -17,23 +18,20 @@ fn main() {
     let fov: f64 = 0.2;
     let nx: usize = 4;
 
-    let sema = &|pos: &SpatialDims<f64>| pos.x().unwrap().powi(2) - pos.y().unwrap().powi(2);
-    let dsema = &|pos: &SpatialDims<f64>| {
-        SpatialDims::TwoD(2.0 * pos.x().unwrap(), -2.0 * pos.y().unwrap())
-    };
-    let mut fa = EncodingField::new(&sema);
-    fa.derivative(&dsema);
+    // x^2 - y^2 and 2xy
+    let scale = 2.0 / 3f64.sqrt();
+    let mut ca = vec![0.0; num_harmonics(2)];
+    ca[index(2, 2).unwrap()] = scale;
+    let fa = EncodingField::solid_harmonic_sum(&ca).unwrap();
 
-    let semb = &|pos: &SpatialDims<f64>| 2.0 * pos.x().unwrap() * pos.y().unwrap();
-    let dsemb =
-        &|pos: &SpatialDims<f64>| SpatialDims::TwoD(2.0 * pos.y().unwrap(), 2.0 * pos.x().unwrap());
-    let mut fb = EncodingField::new(&semb);
-    fb.derivative(&dsemb);
+    let mut cb = vec![0.0; num_harmonics(2)];
+    cb[index(2, -2).unwrap()] = scale;
+    let fb = EncodingField::solid_harmonic_sum(&cb).unwrap();
 
     let ks = KSpace::cartesian(SpatialDims::TwoD(fov, fov), SpatialDims::TwoD(nx, nx));
     println!("{:?}", ks);
 
-    let lk = LocalKSpace::new(ks, vec![fa, fb]);
+    let lk = LocalKSpace::new(&ks, &[fa, fb]);
     let localk = lk.at(&SpatialDims::TwoD(0.1, 0.1));
 
     println!("{:?}", localk);
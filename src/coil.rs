@@ -0,0 +1,328 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Coil sensitivities from coil geometry
+//!
+//! Coils are closed polygons of thin straight wire. Their magnetic field per unit current is
+//! computed with the quasi-static Biot-Savart law, which is exact for every straight segment.
+//! The receive sensitivity of a coil is `Bx - i By` for a main field along z. Positions are in
+//! m and fields in T/A.
+
+use error::{check_grid, MriError, Result};
+use num::Complex;
+use rf::{RFSensitivity, RFSensitivityArray};
+use std::f64::consts::PI;
+use SpatialDims;
+
+/// Vacuum permeability (T m / A)
+pub const MU_0: f64 = 4.0e-7 * PI;
+/// Default number of straight segments approximating a circular loop
+pub const DEFAULT_LOOP_SEGMENTS: usize = 64;
+
+/// Shape of a single loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopShape {
+    /// circular loop with the given radius
+    Circular(f64),
+    /// rectangular loop with the given width and height
+    Rectangular(f64, f64),
+}
+
+/// A closed loop of wire
+#[derive(Debug, Clone)]
+pub struct Coil {
+    /// corners of the polygon, the last corner is connected to the first
+    points: Vec<[f64; 3]>,
+}
+
+impl Coil {
+    /// Create a coil from the corners of a closed polygon, in the direction of the current
+    pub fn new(points: Vec<[f64; 3]>) -> Result<Self> {
+        if points.len() < 3 {
+            return Err(MriError::InvalidParameter(format!(
+                "a coil needs at least 3 corners, found {}",
+                points.len()
+            )));
+        }
+        Ok(Coil { points })
+    }
+
+    /// Circular loop around `center` in the plane perpendicular to `normal`. The current flows
+    /// counterclockwise when looking against `normal`.
+    pub fn circular(center: [f64; 3], normal: [f64; 3], radius: f64) -> Result<Self> {
+        Coil::circular_segments(center, normal, radius, DEFAULT_LOOP_SEGMENTS)
+    }
+
+    /// Circular loop approximated by `segments` straight segments
+    pub fn circular_segments(
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        segments: usize,
+    ) -> Result<Self> {
+        check_length("radius", radius)?;
+        if segments < 3 {
+            return Err(MriError::InvalidParameter(format!(
+                "a circular loop needs at least 3 segments, found {}",
+                segments
+            )));
+        }
+        let (u, v) = plane_basis(normal, None)?;
+        Coil::new(
+            (0..segments)
+                .map(|i| {
+                    let (s, c) = (2.0 * PI * (i as f64) / (segments as f64)).sin_cos();
+                    add(center, add(scale(u, radius * c), scale(v, radius * s)))
+                }).collect(),
+        )
+    }
+
+    /// Rectangular loop around `center` in the plane perpendicular to `normal`. The edges of
+    /// length `height` are parallel to the projection of `up` onto that plane.
+    pub fn rectangular(
+        center: [f64; 3],
+        normal: [f64; 3],
+        up: [f64; 3],
+        width: f64,
+        height: f64,
+    ) -> Result<Self> {
+        check_length("width", width)?;
+        check_length("height", height)?;
+        let (u, v) = plane_basis(normal, Some(up))?;
+        let (w2, h2) = (0.5 * width, 0.5 * height);
+        Coil::new(
+            [(1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0)]
+                .iter()
+                .map(|&(a, b)| add(center, add(scale(u, a * w2), scale(v, b * h2))))
+                .collect(),
+        )
+    }
+
+    /// Array of `num_coils` loops, equally spaced around a cylinder of radius `radius` along z.
+    ///
+    /// The loops are centered at height `z`, lie in the tangential planes of the cylinder and
+    /// their normals point away from the axis. Rectangular loops have their width along the
+    /// circumference and their height along z.
+    pub fn cylinder_array(
+        num_coils: usize,
+        radius: f64,
+        z: f64,
+        shape: LoopShape,
+    ) -> Result<Vec<Coil>> {
+        check_length("cylinder radius", radius)?;
+        if num_coils == 0 {
+            return Err(MriError::InvalidParameter(
+                "number of coils must be positive".to_string(),
+            ));
+        }
+        (0..num_coils)
+            .map(|i| {
+                let (s, c) = (2.0 * PI * (i as f64) / (num_coils as f64)).sin_cos();
+                let center = [radius * c, radius * s, z];
+                let normal = [c, s, 0.0];
+                match shape {
+                    LoopShape::Circular(r) => Coil::circular(center, normal, r),
+                    LoopShape::Rectangular(w, h) => {
+                        Coil::rectangular(center, normal, [0.0, 0.0, 1.0], w, h)
+                    }
+                }
+            }).collect()
+    }
+
+    /// Return the corners of the polygon
+    pub fn points(&self) -> &[[f64; 3]] {
+        &self.points
+    }
+
+    /// Magnetic field per unit current at `r`. Positions on the wire result in zero.
+    pub fn field_at(&self, r: [f64; 3]) -> [f64; 3] {
+        let n = self.points.len();
+        (0..n).fold([0.0; 3], |b, i| {
+            add(b, segment_field(self.points[i], self.points[(i + 1) % n], r))
+        })
+    }
+
+    /// Receive sensitivity at `r`
+    pub fn sensitivity_at(&self, r: [f64; 3]) -> Complex<f64> {
+        let b = self.field_at(r);
+        Complex::new(b[0], -b[1])
+    }
+
+    /// Receive sensitivity at the points of a grid with `dims` voxels covering `fov`, as given
+    /// by `SpatialDims::grid`. Missing dimensions are zero.
+    pub fn sensitivity(
+        &self,
+        fov: &SpatialDims<f64>,
+        dims: &SpatialDims<usize>,
+    ) -> Result<RFSensitivity> {
+        check_grid(fov, dims)?;
+        Ok(RFSensitivity::new(
+            fov.grid(dims)
+                .iter()
+                .map(|pos| self.sensitivity_at(position(pos)))
+                .collect(),
+        ))
+    }
+}
+
+/// Receive sensitivities of several coils on a grid with `dims` voxels covering `fov`
+pub fn sensitivities(
+    coils: &[Coil],
+    fov: &SpatialDims<f64>,
+    dims: &SpatialDims<usize>,
+) -> Result<RFSensitivityArray> {
+    let mut out = RFSensitivityArray::new();
+    for c in coils {
+        out.push(c.sensitivity(fov, dims)?);
+    }
+    Ok(out)
+}
+
+/// Field of a straight segment from `a` to `b` per unit current at `r` (Hanson and Hirshman,
+/// "Compact expressions for the Biot-Savart fields of a filamentary segment", Phys. Plasmas
+/// 9(10), 2002)
+fn segment_field(a: [f64; 3], b: [f64; 3], r: [f64; 3]) -> [f64; 3] {
+    let d = sub(b, a);
+    let r1 = sub(r, a);
+    let (ri, rf) = (norm(r1), norm(sub(r, b)));
+    let l = norm(d);
+    let sum = ri + rf;
+    let den = ri * rf * (sum * sum - l * l);
+    if den <= 1e-30 {
+        return [0.0; 3];
+    }
+    scale(cross(d, r1), MU_0 / (4.0 * PI) * 2.0 * sum / den)
+}
+
+/// Orthonormal basis `(u, v)` of the plane perpendicular to `normal` with `u x v = normal`. If
+/// `up` is given, `v` is its projection onto the plane.
+fn plane_basis(normal: [f64; 3], up: Option<[f64; 3]>) -> Result<([f64; 3], [f64; 3])> {
+    let ln = norm(normal);
+    if !(ln > 0.0 && ln.is_finite()) {
+        return Err(MriError::InvalidParameter("coil normal must not be zero".to_string()));
+    }
+    let n = scale(normal, 1.0 / ln);
+    let up = up.unwrap_or(if n[2].abs() < 0.9 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    });
+    let v = sub(up, scale(n, dot(up, n)));
+    let lv = norm(v);
+    if lv <= 1e-12 * norm(up) {
+        return Err(MriError::InvalidParameter(
+            "coil orientation must not be parallel to the normal".to_string(),
+        ));
+    }
+    let v = scale(v, 1.0 / lv);
+    Ok((cross(v, n), v))
+}
+
+/// Check that a length is positive and finite
+fn check_length(name: &str, x: f64) -> Result<()> {
+    if x > 0.0 && x.is_finite() {
+        Ok(())
+    } else {
+        Err(MriError::InvalidParameter(format!("{} {}", name, x)))
+    }
+}
+
+/// Position in 3D, missing dimensions are zero
+fn position(pos: &SpatialDims<f64>) -> [f64; 3] {
+    let mut r = [0.0; 3];
+    for (ri, p) in r.iter_mut().zip(pos.clone()) {
+        *ri = p;
+    }
+    r
+}
+
+/// `a + b`
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+/// `a - b`
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// `s a`
+fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+/// dot product
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// cross product
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Euclidean norm
+fn norm(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circular_loop_on_axis() {
+        // B = mu_0 R^2 / (2 (R^2 + z^2)^(3/2)) along the normal
+        let radius = 0.05;
+        let center = [0.01, -0.02, 0.03];
+        let normal = [0.0, 0.6, 0.8];
+        let coil = Coil::circular_segments(center, normal, radius, 2048).unwrap();
+        let coarse = Coil::circular(center, normal, radius).unwrap();
+        for &z in &[-0.1, 0.0, 0.02, 0.07] {
+            let r = add(center, scale(normal, z));
+            let expected = MU_0 * radius * radius / (2.0 * (radius * radius + z * z).powf(1.5));
+            for (c, tol) in &[(&coil, 1e-5), (&coarse, 2e-3)] {
+                let b = c.field_at(r);
+                let along = dot(b, normal);
+                assert!((along / expected - 1.0).abs() < *tol, "{} vs {}", along, expected);
+                assert!(norm(sub(b, scale(normal, along))) < 1e-9 * expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rectangular_loop_center() {
+        // B = 2 mu_0 sqrt(w^2 + h^2) / (pi w h) in the center
+        let (w, h) = (0.08, 0.03);
+        let coil = Coil::rectangular([0.0; 3], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0], w, h).unwrap();
+        let b = coil.field_at([0.0; 3]);
+        let expected = 2.0 * MU_0 * (w * w + h * h).sqrt() / (PI * w * h);
+        assert!((b[2] / expected - 1.0).abs() < 1e-12);
+        assert!(b[0].abs() < 1e-12 * expected && b[1].abs() < 1e-12 * expected);
+    }
+
+    #[test]
+    fn sensitivity_is_transverse_field() {
+        let coil = Coil::circular([0.1, 0.0, 0.0], [1.0, 0.0, 0.0], 0.04).unwrap();
+        let r = [0.02, 0.01, -0.03];
+        let b = coil.field_at(r);
+        assert_eq!(coil.sensitivity_at(r), Complex::new(b[0], -b[1]));
+    }
+
+    #[test]
+    fn invalid_geometry() {
+        assert!(Coil::new(vec![[0.0; 3], [1.0, 0.0, 0.0]]).is_err());
+        assert!(Coil::circular([0.0; 3], [0.0; 3], 0.1).is_err());
+        assert!(Coil::circular([0.0; 3], [0.0, 0.0, 1.0], -0.1).is_err());
+        assert!(Coil::rectangular([0.0; 3], [0.0, 0.0, 1.0], [0.0, 0.0, 2.0], 0.1, 0.1).is_err());
+        assert!(Coil::cylinder_array(0, 0.1, 0.0, LoopShape::Circular(0.02)).is_err());
+    }
+}
@@ -0,0 +1,890 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Compressed sensing
+//!
+//! Solves `min_x 1/2 |E x - y|^2 + lambda R(x)`, where `E` is the encoding matrix, `y` the
+//! multi-coil k-space data and `R` a sparsity promoting regularizer:
+//!
+//! * the L1 norm of the detail coefficients of an orthonormal Daubechies wavelet transform with
+//!   periodic boundaries, see Lustig et al., "Sparse MRI: the application of compressed sensing
+//!   for rapid MR imaging", MRM 58(6), 2007
+//! * isotropic or anisotropic total variation with forward differences
+//!
+//! The problem is solved with FISTA (Beck and Teboulle, "A fast iterative shrinkage-thresholding
+//! algorithm for linear inverse problems", SIAM J. Imaging Sci. 2(1), 2009), which computes the
+//! proximal operator of the total variation iteratively, or with ADMM (Boyd et al., "Distributed
+//! optimization and statistical learning via the alternating direction method of multipliers",
+//! Found. Trends Mach. Learn. 3(1), 2011), which solves its linear subproblem with the conjugate
+//! gradient method.
+
+use encodingmatrix::EncodingMatrix;
+use error::{MriError, Result};
+use linalg::{dot, norm_sqr};
+use num::Complex;
+use rf::RFSensitivityArray;
+use std::rc::Rc;
+use EncodingField;
+use KSpaceThings;
+use SpatialDims;
+
+/// Called after every iteration with the iteration number, the current image and the relative
+/// change `|x_k - x_{k-1}| / |x_k|`
+pub type CsCallback = Rc<dyn Fn(usize, &[Complex<f64>], f64)>;
+
+/// Default maximum number of iterations
+pub const DEFAULT_MAX_ITER: usize = 100;
+/// Default relative change at which the iterations are stopped
+pub const DEFAULT_TOLERANCE: f64 = 1e-4;
+/// Number of power iterations estimating the largest eigenvalue of `E^H E`
+const POWER_ITER: usize = 20;
+/// Number of inner iterations of the proximal operator of the total variation (FISTA)
+const TV_PROX_ITER: usize = 20;
+/// Number of conjugate gradient iterations of the linear subproblem (ADMM)
+const ADMM_CG_ITER: usize = 10;
+
+/// Orthonormal Daubechies wavelets with 1 to 4 vanishing moments (low pass filters)
+const DAUBECHIES: [&[f64]; 4] = [
+    &[0.707_106_781_186_547_5, 0.707_106_781_186_547_5],
+    &[
+        0.482_962_913_144_534_1,
+        0.836_516_303_737_807_9,
+        0.224_143_868_042_013_4,
+        -0.129_409_522_551_260_4,
+    ],
+    &[
+        0.332_670_552_950_082_6,
+        0.806_891_509_311_092_5,
+        0.459_877_502_118_491_5,
+        -0.135_011_020_010_254_6,
+        -0.085_441_273_882_026_7,
+        0.035_226_291_885_709_5,
+    ],
+    &[
+        0.230_377_813_308_896_4,
+        0.714_846_570_552_915_4,
+        0.630_880_767_929_858_7,
+        -0.027_983_769_416_859_9,
+        -0.187_034_811_719_093_1,
+        0.030_841_381_835_560_7,
+        0.032_883_011_666_885_2,
+        -0.010_597_401_785_069_0,
+    ],
+];
+
+/// Sparsity promoting regularizer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Regularizer {
+    /// L1 norm of the detail coefficients of a Daubechies wavelet with the given number of
+    /// vanishing moments (1 to 4, 1 is the Haar wavelet)
+    Wavelet(usize),
+    /// isotropic total variation, the L1 norm of the magnitude of the gradient
+    IsotropicTv,
+    /// anisotropic total variation, the L1 norm of the gradient
+    AnisotropicTv,
+}
+
+/// Optimization algorithm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsAlgorithm {
+    /// fast iterative shrinkage-thresholding
+    Fista,
+    /// alternating direction method of multipliers
+    Admm,
+}
+
+/// Compressed sensing reconstruction
+pub struct CompressedSensing<T>
+where
+    T: KSpaceThings,
+{
+    /// encoding matrix
+    e: EncodingMatrix<T>,
+    /// sparsifying transform
+    transform: Transform,
+    /// algorithm
+    algorithm: CsAlgorithm,
+    /// regularization weight
+    lambda: f64,
+    /// ADMM penalty relative to the largest eigenvalue of `E^H E`
+    rho: f64,
+    /// maximum number of iterations
+    max_iter: usize,
+    /// relative change tolerance
+    tol: f64,
+    /// per-iteration callback
+    callback: Option<CsCallback>,
+}
+
+impl<T: KSpaceThings> CompressedSensing<T> {
+    /// Create a reconstruction for trajectory `k`, encoding fields `psi` and coil sensitivities
+    /// `rf` on a grid of `dims` voxels covering `fov`
+    pub fn new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+        regularizer: Regularizer,
+    ) -> Self {
+        CompressedSensing::try_new(k, psi, rf, fov, dims, regularizer)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a reconstruction, failing if the trajectory, fields, sensitivities and grid do not
+    /// fit together or the regularizer is invalid
+    pub fn try_new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+        regularizer: Regularizer,
+    ) -> Result<Self> {
+        let e = EncodingMatrix::try_new(k, psi, rf.array, fov, dims)?;
+        CompressedSensing::from_encoding_matrix(e, regularizer)
+    }
+
+    /// Create a reconstruction for an existing encoding matrix, failing if the regularizer is
+    /// invalid
+    pub fn from_encoding_matrix(e: EncodingMatrix<T>, regularizer: Regularizer) -> Result<Self> {
+        let transform = Transform::new(regularizer, &e.dimensions())?;
+        Ok(CompressedSensing {
+            e,
+            transform,
+            algorithm: CsAlgorithm::Fista,
+            lambda: 0.0,
+            rho: 1.0,
+            max_iter: DEFAULT_MAX_ITER,
+            tol: DEFAULT_TOLERANCE,
+            callback: None,
+        })
+    }
+
+    /// Set the regularization weight
+    pub fn lambda(&mut self, lambda: f64) -> &mut Self {
+        assert!(lambda >= 0.0);
+        self.lambda = lambda;
+        self
+    }
+
+    /// Set the algorithm (default FISTA)
+    pub fn algorithm(&mut self, algorithm: CsAlgorithm) -> &mut Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the ADMM penalty parameter, relative to the largest eigenvalue of `E^H E`
+    pub fn rho(&mut self, rho: f64) -> &mut Self {
+        assert!(rho > 0.0);
+        self.rho = rho;
+        self
+    }
+
+    /// Set the maximum number of iterations
+    pub fn max_iter(&mut self, max_iter: usize) -> &mut Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Set the relative change at which the iterations are stopped
+    pub fn tolerance(&mut self, tol: f64) -> &mut Self {
+        self.tol = tol;
+        self
+    }
+
+    /// Set a callback which is called after every iteration
+    pub fn callback(&mut self, callback: CsCallback) -> &mut Self {
+        self.callback = Some(callback);
+        self
+    }
+
+    /// Return the encoding matrix
+    pub fn encoding_matrix(&self) -> &EncodingMatrix<T> {
+        &self.e
+    }
+
+    /// Reconstruct an image from multi-coil k-space data (`[coil][sample]`, with the samples in
+    /// the order of `KSpaceThings::samples`)
+    pub fn solve(&self, data: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+        let b = self.e.apply_adjoint(data);
+        if norm_sqr(&b) == 0.0 {
+            return b;
+        }
+        let lipschitz = self.max_eigenvalue();
+        match self.algorithm {
+            CsAlgorithm::Fista => self.fista(&b, lipschitz),
+            CsAlgorithm::Admm => self.admm(&b, lipschitz),
+        }
+    }
+
+    /// Apply `E^H E`
+    fn normal(&self, x: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        self.e.apply_adjoint(&self.e.apply(x))
+    }
+
+    /// Largest eigenvalue of `E^H E` (power iteration, with a safety margin)
+    fn max_eigenvalue(&self) -> f64 {
+        let n = self.e.num_voxels();
+        let mut x = vec![Complex::new(1.0 / (n as f64).sqrt(), 0.0); n];
+        let mut lambda = 0.0;
+        for _ in 0..POWER_ITER {
+            let y = self.normal(&x);
+            lambda = norm_sqr(&y).sqrt();
+            if lambda == 0.0 {
+                return 1.0;
+            }
+            x = y.iter().map(|v| v / lambda).collect();
+        }
+        1.1 * lambda
+    }
+
+    /// Call the callback and check the stopping criterion
+    fn step(&self, iter: usize, x: &[Complex<f64>], x_old: &[Complex<f64>]) -> bool {
+        let diff: f64 = x
+            .iter()
+            .zip(x_old.iter())
+            .map(|(a, b)| (a - b).norm_sqr())
+            .sum();
+        let norm = norm_sqr(x);
+        let change = if norm > 0.0 { (diff / norm).sqrt() } else { 0.0 };
+        if let Some(ref callback) = self.callback {
+            callback(iter, x, change);
+        }
+        change < self.tol
+    }
+
+    /// FISTA with step `1 / lipschitz`
+    fn fista(&self, b: &[Complex<f64>], lipschitz: f64) -> Vec<Complex<f64>> {
+        let n = b.len();
+        let mut x = vec![Complex::new(0.0, 0.0); n];
+        let mut z = x.clone();
+        let mut t: f64 = 1.0;
+        let mut dual = None;
+        for iter in 0..self.max_iter {
+            let v: Vec<Complex<f64>> = z
+                .iter()
+                .zip(self.normal(&z).iter().zip(b.iter()))
+                .map(|(zi, (ai, bi))| zi - (ai - bi) / lipschitz)
+                .collect();
+            let x_new = self.transform.prox(&v, self.lambda / lipschitz, &mut dual);
+            let t_new = 0.5 * (1.0 + (1.0 + 4.0 * t * t).sqrt());
+            let w = (t - 1.0) / t_new;
+            z = x_new
+                .iter()
+                .zip(x.iter())
+                .map(|(a, c)| a + (a - c) * w)
+                .collect();
+            t = t_new;
+            let done = self.step(iter, &x_new, &x);
+            x = x_new;
+            if done {
+                break;
+            }
+        }
+        x
+    }
+
+    /// ADMM for `Psi x = z` with penalty `rho * lipschitz`
+    fn admm(&self, b: &[Complex<f64>], lipschitz: f64) -> Vec<Complex<f64>> {
+        let rho = self.rho * lipschitz;
+        let n = b.len();
+        let mut x = vec![Complex::new(0.0, 0.0); n];
+        let mut z = self.transform.forward(&x);
+        let mut u = z.clone();
+        for iter in 0..self.max_iter {
+            // x = (E^H E + rho Psi^H Psi)^-1 (E^H y + rho Psi^H (z - u))
+            let zu: Vec<Vec<Complex<f64>>> = z
+                .iter()
+                .zip(u.iter())
+                .map(|(zc, uc)| zc.iter().zip(uc.iter()).map(|(a, c)| a - c).collect())
+                .collect();
+            let rhs: Vec<Complex<f64>> = b
+                .iter()
+                .zip(self.transform.adjoint(&zu).iter())
+                .map(|(bi, ai)| bi + ai * rho)
+                .collect();
+            let x_new = self.cg(&rhs, x.clone(), rho);
+
+            // z = shrink(Psi x + u), u += Psi x - z
+            let px = self.transform.forward(&x_new);
+            let pu: Vec<Vec<Complex<f64>>> = px
+                .iter()
+                .zip(u.iter())
+                .map(|(pc, uc)| pc.iter().zip(uc.iter()).map(|(a, c)| a + c).collect())
+                .collect();
+            z = self.transform.shrink(&pu, self.lambda / rho);
+            for ((uc, pc), zc) in u.iter_mut().zip(px.iter()).zip(z.iter()) {
+                for ((ui, pi), zi) in uc.iter_mut().zip(pc.iter()).zip(zc.iter()) {
+                    *ui += pi - zi;
+                }
+            }
+
+            let done = self.step(iter, &x_new, &x);
+            x = x_new;
+            if done {
+                break;
+            }
+        }
+        x
+    }
+
+    /// Conjugate gradient for `(E^H E + rho Psi^H Psi) x = rhs`, starting at `x`
+    fn cg(&self, rhs: &[Complex<f64>], mut x: Vec<Complex<f64>>, rho: f64) -> Vec<Complex<f64>> {
+        let op = |v: &[Complex<f64>]| -> Vec<Complex<f64>> {
+            let pv = self.transform.adjoint(&self.transform.forward(v));
+            self.normal(v)
+                .iter()
+                .zip(pv.iter())
+                .map(|(a, c)| a + c * rho)
+                .collect()
+        };
+        let ax = op(&x);
+        let mut r: Vec<Complex<f64>> = rhs.iter().zip(ax.iter()).map(|(a, c)| a - c).collect();
+        let mut p = r.clone();
+        let mut rr = norm_sqr(&r);
+        for _ in 0..ADMM_CG_ITER {
+            if rr == 0.0 {
+                break;
+            }
+            let q = op(&p);
+            let pq = dot(&p, &q).re;
+            if !(pq > 0.0 && pq.is_finite()) {
+                break;
+            }
+            let alpha = rr / pq;
+            for ((xi, pi), (ri, qi)) in x.iter_mut().zip(p.iter()).zip(r.iter_mut().zip(q.iter())) {
+                *xi += pi * alpha;
+                *ri -= qi * alpha;
+            }
+            let rr_new = norm_sqr(&r);
+            let beta = rr_new / rr;
+            for (pi, ri) in p.iter_mut().zip(r.iter()) {
+                *pi = ri + *pi * beta;
+            }
+            rr = rr_new;
+        }
+        x
+    }
+}
+
+/// Sparsifying transform `Psi` with coefficients `[component][index]`
+#[derive(Debug, Clone)]
+enum Transform {
+    /// orthonormal wavelet transform with a single component
+    Wavelet {
+        /// wavelet
+        wavelet: WaveletTransform,
+        /// threshold weight of every coefficient (zero for the approximation coefficients)
+        weights: Vec<f64>,
+    },
+    /// finite differences with one component per dimension
+    Tv {
+        /// number of voxels along every dimension
+        n: Vec<usize>,
+        /// isotropic
+        isotropic: bool,
+    },
+}
+
+impl Transform {
+    fn new(regularizer: Regularizer, dims: &SpatialDims<usize>) -> Result<Self> {
+        let n: Vec<usize> = dims.clone().into_iter().collect();
+        match regularizer {
+            Regularizer::Wavelet(order) => {
+                if !(1..=DAUBECHIES.len()).contains(&order) {
+                    return Err(MriError::InvalidParameter(format!(
+                        "Daubechies wavelets with {} vanishing moments are not supported",
+                        order
+                    )));
+                }
+                let wavelet = WaveletTransform::new(DAUBECHIES[order - 1], n)?;
+                let weights = wavelet.weights();
+                Ok(Transform::Wavelet { wavelet, weights })
+            }
+            Regularizer::IsotropicTv => Ok(Transform::Tv { n, isotropic: true }),
+            Regularizer::AnisotropicTv => Ok(Transform::Tv {
+                n,
+                isotropic: false,
+            }),
+        }
+    }
+
+    /// `Psi x`
+    fn forward(&self, x: &[Complex<f64>]) -> Vec<Vec<Complex<f64>>> {
+        match *self {
+            Transform::Wavelet { ref wavelet, .. } => vec![wavelet.forward(x)],
+            Transform::Tv { ref n, .. } => gradient(x, n),
+        }
+    }
+
+    /// `Psi^H c`
+    fn adjoint(&self, c: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+        match *self {
+            Transform::Wavelet { ref wavelet, .. } => wavelet.inverse(&c[0]),
+            Transform::Tv { ref n, .. } => gradient_adjoint(c, n),
+        }
+    }
+
+    /// Soft thresholding of the coefficients, jointly over the components for isotropic TV
+    fn shrink(&self, c: &[Vec<Complex<f64>>], t: f64) -> Vec<Vec<Complex<f64>>> {
+        let shrink = |v: Complex<f64>, t: f64| {
+            let a = v.norm();
+            if a > t {
+                v * ((a - t) / a)
+            } else {
+                Complex::new(0.0, 0.0)
+            }
+        };
+        match *self {
+            Transform::Wavelet { ref weights, .. } => vec![c[0]
+                .iter()
+                .zip(weights.iter())
+                .map(|(&v, w)| shrink(v, t * w))
+                .collect()],
+            Transform::Tv { isotropic: true, .. } => {
+                let scale: Vec<f64> = group_norm(c)
+                    .iter()
+                    .map(|&a| if a > t { (a - t) / a } else { 0.0 })
+                    .collect();
+                c.iter()
+                    .map(|cd| cd.iter().zip(scale.iter()).map(|(v, s)| v * s).collect())
+                    .collect()
+            }
+            Transform::Tv {
+                isotropic: false, ..
+            } => c
+                .iter()
+                .map(|cd| cd.iter().map(|&v| shrink(v, t)).collect())
+                .collect(),
+        }
+    }
+
+    /// Proximal operator of `t R` at `v`. For total variation, `dual` holds the dual variable of
+    /// the previous call as a warm start.
+    fn prox(
+        &self,
+        v: &[Complex<f64>],
+        t: f64,
+        dual: &mut Option<Vec<Vec<Complex<f64>>>>,
+    ) -> Vec<Complex<f64>> {
+        match *self {
+            Transform::Wavelet { ref wavelet, .. } => {
+                wavelet.inverse(&self.shrink(&[wavelet.forward(v)], t)[0])
+            }
+            Transform::Tv { ref n, isotropic } => {
+                if t == 0.0 {
+                    return v.to_vec();
+                }
+                tv_prox(v, t, n, isotropic, dual)
+            }
+        }
+    }
+}
+
+/// Proximal operator of `t TV` at `v` with the fast gradient projection of Beck and Teboulle,
+/// "Fast gradient-based algorithms for constrained total variation image denoising and
+/// deblurring problems", IEEE TIP 18(11), 2009
+fn tv_prox(
+    v: &[Complex<f64>],
+    t: f64,
+    n: &[usize],
+    isotropic: bool,
+    dual: &mut Option<Vec<Vec<Complex<f64>>>>,
+) -> Vec<Complex<f64>> {
+    let zero = Complex::new(0.0, 0.0);
+    let mut p = dual
+        .take()
+        .unwrap_or_else(|| vec![vec![zero; v.len()]; n.len()]);
+    let mut q = p.clone();
+    let mut s: f64 = 1.0;
+    let step = 1.0 / (4.0 * (n.len() as f64) * t);
+    let primal = |q: &[Vec<Complex<f64>>]| -> Vec<Complex<f64>> {
+        v.iter()
+            .zip(gradient_adjoint(q, n).iter())
+            .map(|(a, c)| a - c * t)
+            .collect()
+    };
+    for _ in 0..TV_PROX_ITER {
+        let g = gradient(&primal(&q), n);
+        let mut p_new: Vec<Vec<Complex<f64>>> = q
+            .iter()
+            .zip(g.iter())
+            .map(|(qd, gd)| qd.iter().zip(gd.iter()).map(|(a, c)| a + c * step).collect())
+            .collect();
+        // project onto the unit ball of the dual norm
+        if isotropic {
+            let norms = group_norm(&p_new);
+            for pd in &mut p_new {
+                for (x, a) in pd.iter_mut().zip(norms.iter()) {
+                    *x /= a.max(1.0);
+                }
+            }
+        } else {
+            for x in p_new.iter_mut().flat_map(|pd| pd.iter_mut()) {
+                *x /= x.norm().max(1.0);
+            }
+        }
+        let s_new = 0.5 * (1.0 + (1.0 + 4.0 * s * s).sqrt());
+        let w = (s - 1.0) / s_new;
+        q = p_new
+            .iter()
+            .zip(p.iter())
+            .map(|(a, c)| a.iter().zip(c.iter()).map(|(x, y)| x + (x - y) * w).collect())
+            .collect();
+        p = p_new;
+        s = s_new;
+    }
+    let x = primal(&p);
+    *dual = Some(p);
+    x
+}
+
+/// Magnitude over the components of every coefficient
+fn group_norm(c: &[Vec<Complex<f64>>]) -> Vec<f64> {
+    (0..c[0].len())
+        .map(|i| c.iter().map(|cd| cd[i].norm_sqr()).sum::<f64>().sqrt())
+        .collect()
+}
+
+/// Stride of dimension `d` (first dimension fastest)
+fn stride(n: &[usize], d: usize) -> usize {
+    n[..d].iter().product()
+}
+
+/// Forward differences along every dimension, zero at the last voxel
+fn gradient(x: &[Complex<f64>], n: &[usize]) -> Vec<Vec<Complex<f64>>> {
+    (0..n.len())
+        .map(|d| {
+            let s = stride(n, d);
+            (0..x.len())
+                .map(|i| {
+                    if (i / s) % n[d] + 1 < n[d] {
+                        x[i + s] - x[i]
+                    } else {
+                        Complex::new(0.0, 0.0)
+                    }
+                }).collect()
+        }).collect()
+}
+
+/// Adjoint of `gradient` (negative divergence)
+fn gradient_adjoint(g: &[Vec<Complex<f64>>], n: &[usize]) -> Vec<Complex<f64>> {
+    let len = g[0].len();
+    let mut out = vec![Complex::new(0.0, 0.0); len];
+    for (d, gd) in g.iter().enumerate() {
+        let s = stride(n, d);
+        for i in 0..len {
+            if (i / s) % n[d] + 1 < n[d] {
+                out[i + s] += gd[i];
+                out[i] -= gd[i];
+            }
+        }
+    }
+    out
+}
+
+/// Separable orthonormal wavelet transform with periodic boundaries
+#[derive(Debug, Clone)]
+struct WaveletTransform {
+    /// low pass filter
+    h: Vec<f64>,
+    /// high pass filter
+    g: Vec<f64>,
+    /// number of voxels along every dimension
+    n: Vec<usize>,
+    /// number of decomposition levels along every dimension
+    levels: Vec<usize>,
+}
+
+impl WaveletTransform {
+    /// Transform with as many levels along every dimension as it can be halved while it is at
+    /// least as long as the filter, failing if no dimension can be decomposed
+    fn new(h: &[f64], n: Vec<usize>) -> Result<Self> {
+        let len = h.len();
+        let g = (0..len)
+            .map(|k| if k % 2 == 0 { h[len - 1 - k] } else { -h[len - 1 - k] })
+            .collect();
+        let levels: Vec<usize> = n
+            .iter()
+            .map(|&ni| {
+                let mut levels = 0;
+                while (ni >> levels) % 2 == 0 && (ni >> levels) >= len.max(2) {
+                    levels += 1;
+                }
+                levels
+            }).collect();
+        if levels.iter().all(|&l| l == 0) {
+            return Err(MriError::InvalidParameter(format!(
+                "grid {:?} cannot be decomposed with a wavelet filter of length {}",
+                n, len
+            )));
+        }
+        Ok(WaveletTransform {
+            h: h.to_vec(),
+            g,
+            n,
+            levels,
+        })
+    }
+
+    /// Threshold weights, zero for the approximation coefficients of the coarsest level
+    fn weights(&self) -> Vec<f64> {
+        let total: usize = self.n.iter().product();
+        (0..total)
+            .map(|mut i| {
+                let approx = self.n.iter().zip(self.levels.iter()).all(|(&ni, &l)| {
+                    let j = i % ni;
+                    i /= ni;
+                    j < ni >> l
+                });
+                if approx {
+                    0.0
+                } else {
+                    1.0
+                }
+            }).collect()
+    }
+
+    /// Size of the block of approximation coefficients transformed at `level`
+    fn block(&self, level: usize) -> Vec<usize> {
+        self.n
+            .iter()
+            .zip(self.levels.iter())
+            .map(|(&ni, &l)| ni >> level.min(l))
+            .collect()
+    }
+
+    /// Lines of length `m` along dimension `d` within the block of size `block` at the origin,
+    /// given by their first index
+    fn lines(&self, block: &[usize], d: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        for (e, &b) in block.iter().enumerate() {
+            if e == d {
+                continue;
+            }
+            let s = stride(&self.n, e);
+            starts = starts
+                .iter()
+                .flat_map(|&st| (0..b).map(move |j| st + j * s))
+                .collect();
+        }
+        starts
+    }
+
+    fn forward(&self, x: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        let mut c = x.to_vec();
+        let max_level = self.levels.iter().cloned().max().unwrap_or(0);
+        for level in 0..max_level {
+            let block = self.block(level);
+            for d in (0..self.n.len()).filter(|&d| level < self.levels[d]) {
+                let (m, s) = (block[d], stride(&self.n, d));
+                for start in self.lines(&block, d) {
+                    let line: Vec<Complex<f64>> = (0..m).map(|j| c[start + j * s]).collect();
+                    for i in 0..m / 2 {
+                        let (mut a, mut b) = (Complex::new(0.0, 0.0), Complex::new(0.0, 0.0));
+                        for (k, (h, g)) in self.h.iter().zip(self.g.iter()).enumerate() {
+                            let v = line[(2 * i + k) % m];
+                            a += v * h;
+                            b += v * g;
+                        }
+                        c[start + i * s] = a;
+                        c[start + (m / 2 + i) * s] = b;
+                    }
+                }
+            }
+        }
+        c
+    }
+
+    fn inverse(&self, c: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        let mut x = c.to_vec();
+        let max_level = self.levels.iter().cloned().max().unwrap_or(0);
+        for level in (0..max_level).rev() {
+            let block = self.block(level);
+            for d in (0..self.n.len()).rev().filter(|&d| level < self.levels[d]) {
+                let (m, s) = (block[d], stride(&self.n, d));
+                for start in self.lines(&block, d) {
+                    let mut line = vec![Complex::new(0.0, 0.0); m];
+                    for i in 0..m / 2 {
+                        let a = x[start + i * s];
+                        let b = x[start + (m / 2 + i) * s];
+                        for (k, (h, g)) in self.h.iter().zip(self.g.iter()).enumerate() {
+                            line[(2 * i + k) % m] += a * h + b * g;
+                        }
+                    }
+                    for (j, v) in line.into_iter().enumerate() {
+                        x[start + j * s] = v;
+                    }
+                }
+            }
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::Rng;
+    use rf::RFSensitivity;
+    use std::cell::Cell;
+    use undersampling::{SamplingPattern, Undersampling};
+    use KSpace;
+
+    /// Deterministic pseudo random complex numbers with normally distributed parts
+    fn random(n: usize, seed: u64) -> Vec<Complex<f64>> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| Complex::new(rng.normal(), rng.normal())).collect()
+    }
+
+    fn max_diff(a: &[Complex<f64>], b: &[Complex<f64>]) -> f64 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(x, y)| (x - y).norm())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn wavelet_perfect_reconstruction() {
+        for (order, h) in DAUBECHIES.iter().enumerate() {
+            // the high pass filter is orthogonal to the low pass filter
+            let wavelet = WaveletTransform::new(h, vec![32]).unwrap();
+            let hg: f64 = wavelet.h.iter().zip(wavelet.g.iter()).map(|(a, b)| a * b).sum();
+            assert!(hg.abs() < 1e-12);
+            for n in &[vec![32], vec![16, 12], vec![8, 16, 4]] {
+                let wavelet = WaveletTransform::new(h, n.clone()).unwrap();
+                let x = random(n.iter().product(), order as u64);
+                let c = wavelet.forward(&x);
+                // orthonormal
+                assert!((norm_sqr(&c) - norm_sqr(&x)).abs() < 1e-9 * norm_sqr(&x));
+                assert!(max_diff(&wavelet.inverse(&c), &x) < 1e-12, "{} {:?}", order + 1, n);
+            }
+        }
+    }
+
+    #[test]
+    fn wavelet_levels() {
+        // the length 8 filter decomposes 12 once and 4 not at all
+        let wavelet = WaveletTransform::new(DAUBECHIES[3], vec![16, 12, 4]).unwrap();
+        assert_eq!(wavelet.levels, vec![2, 1, 0]);
+        let weights = wavelet.weights();
+        assert_eq!(weights.iter().filter(|&&w| w == 0.0).count(), 4 * 6 * 4);
+
+        // the approximation coefficients of a constant image are zero weighted
+        let x = vec![Complex::new(1.0, 0.0); 16 * 12 * 4];
+        let c = wavelet.forward(&x);
+        for (ci, w) in c.iter().zip(weights.iter()) {
+            assert!(*w == 0.0 || ci.norm() < 1e-12);
+        }
+
+        assert!(WaveletTransform::new(DAUBECHIES[3], vec![6, 4]).is_err());
+        assert!(Transform::new(Regularizer::Wavelet(4), &SpatialDims::TwoD(6, 4)).is_err());
+        assert!(Transform::new(Regularizer::Wavelet(1), &SpatialDims::OneD(3)).is_err());
+        assert!(Transform::new(Regularizer::Wavelet(5), &SpatialDims::OneD(16)).is_err());
+    }
+
+    #[test]
+    fn gradient_adjoint_identity() {
+        for n in &[vec![7], vec![5, 4], vec![5, 4, 3]] {
+            let len = n.iter().product();
+            let x = random(len, 1);
+            let g: Vec<Vec<Complex<f64>>> =
+                (0..n.len()).map(|d| random(len, 2 + d as u64)).collect();
+            let gx = gradient(&x, n);
+            let lhs: Complex<f64> = gx.iter().zip(g.iter()).map(|(a, b)| dot(a, b)).sum();
+            let rhs = dot(&x, &gradient_adjoint(&g, n));
+            assert!((lhs - rhs).norm() < 1e-12 * lhs.norm().max(1.0));
+        }
+    }
+
+    /// Undersampled single coil problem of a piecewise constant phantom with its image
+    fn phantom(regularizer: Regularizer) -> (CompressedSensing<KSpace>, Vec<Complex<f64>>) {
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(12, 12);
+        let (k, _) = KSpace::cartesian(fov.clone(), dims.clone())
+            .undersample(
+                &dims,
+                Undersampling::new(SamplingPattern::VariableDensity { power: 2.0 })
+                    .acceleration(2.5)
+                    .calibration(SpatialDims::TwoD(4, 4)),
+            ).unwrap();
+        let psi = vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap())),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap())),
+        ];
+        let mut rf = RFSensitivityArray::new();
+        rf.push(RFSensitivity::new(vec![Complex::new(1.0, 0.0); dims.product()]));
+        let img = (0..dims.product())
+            .map(|i| {
+                let (x, y) = (i % 12, i / 12);
+                let mut v = 0.0;
+                if (2..10).contains(&x) && (2..11).contains(&y) {
+                    v += 1.0;
+                }
+                if (5..8).contains(&x) && (4..7).contains(&y) {
+                    v += 0.5;
+                }
+                Complex::new(v, 0.0)
+            }).collect();
+        (
+            CompressedSensing::new(k, psi, rf, fov, dims, regularizer),
+            img,
+        )
+    }
+
+    fn error(x: &[Complex<f64>], img: &[Complex<f64>]) -> f64 {
+        let diff: Vec<Complex<f64>> = x.iter().zip(img.iter()).map(|(a, b)| a - b).collect();
+        (norm_sqr(&diff) / norm_sqr(img)).sqrt()
+    }
+
+    #[test]
+    fn regularization_helps() {
+        for &regularizer in &[
+            Regularizer::Wavelet(1),
+            Regularizer::IsotropicTv,
+            Regularizer::AnisotropicTv,
+        ] {
+            for &algorithm in &[CsAlgorithm::Fista, CsAlgorithm::Admm] {
+                let (mut cs, img) = phantom(regularizer);
+                let data = cs.encoding_matrix().apply(&img);
+                cs.algorithm(algorithm).tolerance(0.0).max_iter(30);
+                let plain = error(&cs.solve(&data), &img);
+                let regularized = error(&cs.lambda(5.0).solve(&data), &img);
+                assert!(
+                    regularized < 0.8 * plain,
+                    "{:?} {:?}: {} vs {}",
+                    regularizer,
+                    algorithm,
+                    regularized,
+                    plain
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tolerance_stops_early() {
+        for &algorithm in &[CsAlgorithm::Fista, CsAlgorithm::Admm] {
+            let (mut cs, img) = phantom(Regularizer::IsotropicTv);
+            let data = cs.encoding_matrix().apply(&img);
+            let iterations = Rc::new(Cell::new(0));
+            let counter = iterations.clone();
+            cs.algorithm(algorithm)
+                .lambda(5.0)
+                .max_iter(40)
+                .callback(Rc::new(move |iter, _, _| counter.set(iter + 1)));
+
+            cs.tolerance(1e-2).solve(&data);
+            let early = iterations.get();
+            assert!(early > 1 && early < 40, "{:?}: {}", algorithm, early);
+
+            cs.tolerance(0.0).solve(&data);
+            assert_eq!(iterations.get(), 40);
+        }
+    }
+}
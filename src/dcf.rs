@@ -0,0 +1,717 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Density compensation
+//!
+//! All functions return one weight per sample, in the order of `KSpaceThings::samples`.
+
+use error::{MriError, Result};
+use num::Complex;
+use nufft::Nufft;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use KSample;
+use KSpaceThings;
+use SpatialDims;
+
+/// Number of vertices of the polygon bounding the outermost Voronoi cells (2D)
+const BOUNDARY_VERTICES: usize = 64;
+/// Number of faces of the polyhedron bounding the outermost Voronoi cells (3D), in addition to
+/// the faces of a cube
+const BOUNDARY_FACES: usize = 64;
+
+/// A point in 3D
+type Point = [f64; 3];
+/// A convex polyhedron given by its faces, each a convex polygon
+type Polyhedron = Vec<Vec<Point>>;
+
+/// Analytic ramp weights of center-out or center-crossing radial trajectories.
+///
+/// The weight of a sample is `|k|^(d - 1)` for a `d`-dimensional trajectory. Samples in the center
+/// of k-space are weighted as if they covered a disc (sphere) with half the radial sample
+/// spacing, estimated as the smallest nonzero `|k|`.
+pub fn ramp<T: KSpaceThings>(k: &T) -> Vec<f64> {
+    let d = k.num_channels();
+    let radius: Vec<f64> = k.samples().iter().map(|s| norm(s)).collect();
+    let dk = radius
+        .iter()
+        .cloned()
+        .filter(|&r| r > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    let center = match d {
+        0 | 1 => 1.0,
+        2 => dk / 4.0,
+        _ => dk * dk / 12.0,
+    };
+    radius
+        .iter()
+        .map(|&r| {
+            if r > 0.0 {
+                r.powi(d as i32 - 1)
+            } else {
+                center
+            }
+        }).collect()
+}
+
+/// Voronoi cell sizes (lengths in 1D, areas in 2D, volumes in 3D) of the samples.
+///
+/// Cells at the outer edge of k-space are limited to half the median sample spacing beyond the
+/// outermost sample. Samples at identical positions share their cell equally.
+pub fn voronoi<T: KSpaceThings>(k: &T) -> Result<Vec<f64>> {
+    let samples = k.samples();
+
+    // group repeated samples
+    let mut unique: Vec<KSample> = vec![];
+    let mut lookup: HashMap<Vec<u64>, usize> = HashMap::new();
+    let index: Vec<usize> = samples
+        .iter()
+        .map(|s| {
+            let key: Vec<u64> = s.iter().map(|x| (x + 0.0).to_bits()).collect();
+            *lookup.entry(key).or_insert_with(|| {
+                unique.push(s.clone());
+                unique.len() - 1
+            })
+        }).collect();
+    let mut count = vec![0usize; unique.len()];
+    for &i in &index {
+        count[i] += 1;
+    }
+
+    let cells = match k.num_channels() {
+        1 => voronoi_1d(&unique),
+        2 => voronoi_2d(&unique),
+        3 => voronoi_3d(&unique),
+        d => {
+            return Err(MriError::InvalidParameter(format!(
+                "Voronoi weights are not implemented for {}D trajectories",
+                d
+            )))
+        }
+    };
+    Ok(index
+        .iter()
+        .map(|&i| cells[i] / (count[i] as f64))
+        .collect())
+}
+
+/// Iterative density compensation after Pipe and Menon, "Sampling density compensation in MRI:
+/// rationale and an iterative numerical solution", MRM 41(1), 1999.
+///
+/// The convolution with the gridding kernel is computed on the oversampled grid of a NUFFT for
+/// an image of `dims` voxels covering `fov`. The weights are scaled such that an isolated sample
+/// has a weight of (about) one.
+pub fn pipe_menon<T: KSpaceThings>(
+    k: &T,
+    fov: SpatialDims<f64>,
+    dims: SpatialDims<usize>,
+    iterations: usize,
+) -> Result<Vec<f64>> {
+    let nufft = Nufft::try_new(k, fov, dims)?;
+    let mut w = vec![Complex::new(1.0, 0.0); nufft.num_samples()];
+    for _ in 0..iterations {
+        let conv = nufft.interpolate(&nufft.spread(&w));
+        for (wi, ci) in w.iter_mut().zip(conv.iter()) {
+            if ci.re > 0.0 {
+                *wi /= ci.re;
+            }
+        }
+    }
+    let scale = (0..nufft.num_samples())
+        .map(|i| nufft.self_weight(i))
+        .sum::<f64>()
+        / (nufft.num_samples() as f64);
+    Ok(w.iter().map(|x| x.re * scale).collect())
+}
+
+/// Euclidean norm
+fn norm(x: &[f64]) -> f64 {
+    x.iter().map(|a| a * a).sum::<f64>().sqrt()
+}
+
+/// Median of the distances of `n` points to their nearest neighbours, using the given neighbour
+/// candidates and distance
+fn median_spacing(
+    n: usize,
+    neighbours: &dyn Fn(usize) -> Vec<usize>,
+    distance: &dyn Fn(usize, usize) -> f64,
+) -> f64 {
+    let mut d: Vec<f64> = (0..n)
+        .map(|i| {
+            neighbours(i)
+                .iter()
+                .filter(|&&j| j != i)
+                .map(|&j| distance(i, j))
+                .fold(f64::INFINITY, f64::min)
+        }).filter(|x| x.is_finite())
+        .collect();
+    if d.is_empty() {
+        return 1.0;
+    }
+    d.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    d[d.len() / 2]
+}
+
+/// Voronoi cell lengths of unique 1D samples
+fn voronoi_1d(samples: &[KSample]) -> Vec<f64> {
+    let n = samples.len();
+    if n < 2 {
+        return vec![1.0; n];
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| samples[a][0].partial_cmp(&samples[b][0]).unwrap());
+    let x: Vec<f64> = order.iter().map(|&i| samples[i][0]).collect();
+    let mut out = vec![0.0; n];
+    for (j, &i) in order.iter().enumerate() {
+        let left = if j > 0 { x[j] - x[j - 1] } else { x[1] - x[0] };
+        let right = if j + 1 < n {
+            x[j + 1] - x[j]
+        } else {
+            x[n - 1] - x[n - 2]
+        };
+        out[i] = 0.5 * (left + right);
+    }
+    out
+}
+
+/// Voronoi cell areas of unique 2D samples
+fn voronoi_2d(samples: &[KSample]) -> Vec<f64> {
+    let n = samples.len();
+    let points: Vec<(f64, f64)> = samples.iter().map(|s| (s[0], s[1])).collect();
+    if n < 2 {
+        return vec![1.0; n];
+    }
+
+    // bucket grid for neighbour search, about one point per bucket
+    let (mut xmin, mut xmax, mut ymin, mut ymax) = (
+        f64::INFINITY,
+        -f64::INFINITY,
+        f64::INFINITY,
+        -f64::INFINITY,
+    );
+    for p in &points {
+        xmin = xmin.min(p.0);
+        xmax = xmax.max(p.0);
+        ymin = ymin.min(p.1);
+        ymax = ymax.max(p.1);
+    }
+    let extent = (xmax - xmin).max(ymax - ymin).max(1e-12);
+    let nb = ((n as f64).sqrt().ceil() as usize).max(1);
+    let h = extent / (nb as f64) * (1.0 + 1e-9);
+    let bucket = |p: (f64, f64)| {
+        (
+            (((p.0 - xmin) / h) as usize).min(nb - 1),
+            (((p.1 - ymin) / h) as usize).min(nb - 1),
+        )
+    };
+    let mut buckets: Vec<Vec<usize>> = vec![vec![]; nb * nb];
+    for (i, &p) in points.iter().enumerate() {
+        let (bx, by) = bucket(p);
+        buckets[by * nb + bx].push(i);
+    }
+    // points in the square ring of buckets at Chebyshev distance `r` around `(bx, by)`
+    let ring = |bx: usize, by: usize, r: usize| -> Vec<usize> {
+        let r = r as i64;
+        let mut out = vec![];
+        for y in (by as i64 - r)..=(by as i64 + r) {
+            for x in (bx as i64 - r)..=(bx as i64 + r) {
+                let on_ring = (y - by as i64).abs() == r || (x - bx as i64).abs() == r;
+                if on_ring && x >= 0 && y >= 0 && (x as usize) < nb && (y as usize) < nb {
+                    out.extend(&buckets[y as usize * nb + x as usize]);
+                }
+            }
+        }
+        out
+    };
+
+    let spacing = median_spacing(
+        n,
+        &|i| {
+            let (bx, by) = bucket(points[i]);
+            let mut out = ring(bx, by, 0);
+            out.extend(ring(bx, by, 1));
+            out
+        },
+        &|i, j| (points[i].0 - points[j].0).hypot(points[i].1 - points[j].1),
+    );
+    let radius = points.iter().map(|p| p.0.hypot(p.1)).fold(0.0, f64::max) + 0.5 * spacing;
+    let boundary: Vec<(f64, f64)> = (0..BOUNDARY_VERTICES)
+        .map(|i| {
+            let phi = 2.0 * PI * (i as f64) / (BOUNDARY_VERTICES as f64);
+            (radius * phi.cos(), radius * phi.sin())
+        }).collect();
+
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            let mut cell = boundary.clone();
+            let (bx, by) = bucket(p);
+            let mut r = 0;
+            loop {
+                for j in ring(bx, by, r) {
+                    if j != i {
+                        cell = clip(&cell, p, points[j]);
+                    }
+                }
+                // points in further rings are at least r * h away and cannot cut the cell
+                let reach = cell
+                    .iter()
+                    .map(|v| (v.0 - p.0).hypot(v.1 - p.1))
+                    .fold(0.0, f64::max);
+                if (r as f64) * h > 2.0 * reach || r > nb {
+                    break;
+                }
+                r += 1;
+            }
+            area(&cell)
+        }).collect()
+}
+
+/// Voronoi cell volumes of unique 3D samples
+fn voronoi_3d(samples: &[KSample]) -> Vec<f64> {
+    let n = samples.len();
+    let points: Vec<Point> = samples.iter().map(|s| [s[0], s[1], s[2]]).collect();
+    if n < 2 {
+        return vec![1.0; n];
+    }
+
+    // bucket grid for neighbour search, about one point per bucket
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [-f64::INFINITY; 3];
+    for p in &points {
+        for d in 0..3 {
+            min[d] = min[d].min(p[d]);
+            max[d] = max[d].max(p[d]);
+        }
+    }
+    let extent = (0..3).map(|d| max[d] - min[d]).fold(1e-12, f64::max);
+    let nb = ((n as f64).cbrt().ceil() as usize).max(1);
+    let h = extent / (nb as f64) * (1.0 + 1e-9);
+    let bucket = |p: &Point| -> [usize; 3] {
+        let mut b = [0; 3];
+        for d in 0..3 {
+            b[d] = (((p[d] - min[d]) / h) as usize).min(nb - 1);
+        }
+        b
+    };
+    let mut buckets: Vec<Vec<usize>> = vec![vec![]; nb * nb * nb];
+    for (i, p) in points.iter().enumerate() {
+        let b = bucket(p);
+        buckets[(b[2] * nb + b[1]) * nb + b[0]].push(i);
+    }
+    // points in the shell of buckets at Chebyshev distance `r` around `b`
+    let shell = |b: [usize; 3], r: usize| -> Vec<usize> {
+        let r = r as i64;
+        let mut out = vec![];
+        for z in (b[2] as i64 - r)..=(b[2] as i64 + r) {
+            for y in (b[1] as i64 - r)..=(b[1] as i64 + r) {
+                for x in (b[0] as i64 - r)..=(b[0] as i64 + r) {
+                    let on_shell = (x - b[0] as i64).abs() == r
+                        || (y - b[1] as i64).abs() == r
+                        || (z - b[2] as i64).abs() == r;
+                    let inside = [x, y, z].iter().all(|&c| c >= 0 && (c as usize) < nb);
+                    if on_shell && inside {
+                        out.extend(&buckets[((z as usize) * nb + y as usize) * nb + x as usize]);
+                    }
+                }
+            }
+        }
+        out
+    };
+
+    let spacing = median_spacing(
+        n,
+        &|i| {
+            let b = bucket(&points[i]);
+            let mut out = shell(b, 0);
+            out.extend(shell(b, 1));
+            out
+        },
+        &|i, j| distance_3d(&points[i], &points[j]),
+    );
+    let radius = points
+        .iter()
+        .map(|p| distance_3d(p, &[0.0; 3]))
+        .fold(0.0, f64::max)
+        + 0.5 * spacing;
+    let margin = 0.5 * spacing;
+    let boundary = bounding_polyhedron(
+        [min[0] - margin, min[1] - margin, min[2] - margin],
+        [max[0] + margin, max[1] + margin, max[2] + margin],
+        radius,
+    );
+
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let mut cell = boundary.clone();
+            let mut reach = radius + distance_3d(p, &[0.0; 3]);
+            let b = bucket(p);
+            let mut r = 0;
+            loop {
+                // nearest points first, they shrink the cell the most
+                let mut candidates: Vec<(f64, usize)> = shell(b, r)
+                    .into_iter()
+                    .filter(|&j| j != i)
+                    .map(|j| (distance_3d(p, &points[j]), j))
+                    .collect();
+                candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                for (d, j) in candidates {
+                    // points further than twice the farthest vertex cannot cut the cell
+                    if d > 2.0 * reach {
+                        break;
+                    }
+                    if !clip_3d(&mut cell, p, &points[j]) {
+                        continue;
+                    }
+                    reach = cell
+                        .iter()
+                        .flat_map(|f| f.iter())
+                        .map(|v| distance_3d(v, p))
+                        .fold(0.0, f64::max);
+                }
+                // points in further shells are at least r * h away
+                if (r as f64) * h > 2.0 * reach || r > nb {
+                    break;
+                }
+                r += 1;
+            }
+            volume(&cell, p)
+        }).collect()
+}
+
+/// Euclidean distance of two points in 3D
+fn distance_3d(a: &Point, b: &Point) -> f64 {
+    (0..3).map(|d| (a[d] - b[d]).powi(2)).sum::<f64>().sqrt()
+}
+
+/// Box from `lo` to `hi` cut by the planes tangent to a sphere of the given radius around the
+/// origin in `BOUNDARY_FACES` directions on a Fibonacci lattice
+fn bounding_polyhedron(lo: Point, hi: Point, radius: f64) -> Polyhedron {
+    let c = |x: f64, y: f64, z: f64| {
+        let corner = |d: usize, s: f64| if s < 0.0 { lo[d] } else { hi[d] };
+        [corner(0, x), corner(1, y), corner(2, z)]
+    };
+    let mut poly = vec![
+        vec![c(-1., -1., -1.), c(-1., 1., -1.), c(1., 1., -1.), c(1., -1., -1.)],
+        vec![c(-1., -1., 1.), c(1., -1., 1.), c(1., 1., 1.), c(-1., 1., 1.)],
+        vec![c(-1., -1., -1.), c(1., -1., -1.), c(1., -1., 1.), c(-1., -1., 1.)],
+        vec![c(-1., 1., -1.), c(-1., 1., 1.), c(1., 1., 1.), c(1., 1., -1.)],
+        vec![c(-1., -1., -1.), c(-1., -1., 1.), c(-1., 1., 1.), c(-1., 1., -1.)],
+        vec![c(1., -1., -1.), c(1., 1., -1.), c(1., 1., 1.), c(1., -1., 1.)],
+    ];
+    let golden = PI * (3.0 - 5f64.sqrt());
+    for i in 0..BOUNDARY_FACES {
+        let z = 1.0 - (2.0 * (i as f64) + 1.0) / (BOUNDARY_FACES as f64);
+        let rho = (1.0 - z * z).sqrt();
+        let phi = golden * (i as f64);
+        let normal = [rho * phi.cos(), rho * phi.sin(), z];
+        clip_plane(&mut poly, &normal, radius);
+    }
+    poly
+}
+
+/// Clip a convex polyhedron to the half space closer to `p` than to `q`, returning whether it
+/// changed
+fn clip_3d(poly: &mut Polyhedron, p: &Point, q: &Point) -> bool {
+    let normal = [q[0] - p[0], q[1] - p[1], q[2] - p[2]];
+    let c = 0.5 * (0..3).map(|d| normal[d] * (p[d] + q[d])).sum::<f64>();
+    clip_plane(poly, &normal, c)
+}
+
+/// Clip a convex polyhedron to the half space `normal . x <= c`, returning whether it changed
+fn clip_plane(poly: &mut Polyhedron, normal: &Point, c: f64) -> bool {
+    let side = |v: &Point| normal[0] * v[0] + normal[1] * v[1] + normal[2] * v[2] - c;
+    if poly.iter().flat_map(|f| f.iter()).all(|v| side(v) <= 0.0) {
+        return false;
+    }
+    let mut out: Polyhedron = Vec::with_capacity(poly.len() + 1);
+    let mut cap: Vec<Point> = vec![];
+    for face in poly.iter() {
+        let mut clipped = Vec::with_capacity(face.len() + 1);
+        for i in 0..face.len() {
+            let a = face[i];
+            let b = face[(i + 1) % face.len()];
+            let (sa, sb) = (side(&a), side(&b));
+            if sa <= 0.0 {
+                clipped.push(a);
+            }
+            if sa == 0.0 {
+                cap.push(a);
+            }
+            if (sa < 0.0 && sb > 0.0) || (sa > 0.0 && sb < 0.0) {
+                let t = sa / (sa - sb);
+                let v = [
+                    a[0] + t * (b[0] - a[0]),
+                    a[1] + t * (b[1] - a[1]),
+                    a[2] + t * (b[2] - a[2]),
+                ];
+                clipped.push(v);
+                cap.push(v);
+            }
+        }
+        if clipped.len() >= 3 {
+            out.push(clipped);
+        }
+    }
+    if cap.len() >= 3 {
+        out.push(order_polygon(cap, normal));
+    }
+    *poly = out;
+    true
+}
+
+/// Order the vertices of a convex polygon in a plane with the given normal by their angle around
+/// the centroid
+fn order_polygon(points: Vec<Point>, normal: &Point) -> Vec<Point> {
+    let m = points.len() as f64;
+    let mut center = [0.0; 3];
+    for p in &points {
+        for d in 0..3 {
+            center[d] += p[d] / m;
+        }
+    }
+    // orthonormal basis of the plane
+    let len = distance_3d(normal, &[0.0; 3]);
+    let nz = [normal[0] / len, normal[1] / len, normal[2] / len];
+    let helper = if nz[0].abs() < 0.9 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let u = normalize(&cross(&helper, &nz));
+    let v = cross(&nz, &u);
+    let mut keyed: Vec<(f64, Point)> = points
+        .into_iter()
+        .map(|p| {
+            let r = [p[0] - center[0], p[1] - center[1], p[2] - center[2]];
+            (dot(&r, &v).atan2(dot(&r, &u)), p)
+        }).collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    keyed.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Volume of a convex polyhedron containing `p`, as the sum of the tetrahedra between `p` and
+/// the triangles of every face
+fn volume(poly: &[Vec<Point>], p: &Point) -> f64 {
+    let rel = |v: &Point| [v[0] - p[0], v[1] - p[1], v[2] - p[2]];
+    poly.iter()
+        .map(|face| {
+            let a = rel(&face[0]);
+            (1..face.len() - 1)
+                .map(|i| dot(&a, &cross(&rel(&face[i]), &rel(&face[i + 1]))).abs() / 6.0)
+                .sum::<f64>()
+        }).sum()
+}
+
+fn dot(a: &Point, b: &Point) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &Point, b: &Point) -> Point {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: &Point) -> Point {
+    let len = dot(a, a).sqrt();
+    [a[0] / len, a[1] / len, a[2] / len]
+}
+
+/// Clip a convex polygon to the half plane closer to `p` than to `q`
+fn clip(poly: &[(f64, f64)], p: (f64, f64), q: (f64, f64)) -> Vec<(f64, f64)> {
+    let n = (q.0 - p.0, q.1 - p.1);
+    let c = 0.5 * (n.0 * (p.0 + q.0) + n.1 * (p.1 + q.1));
+    let side = |v: &(f64, f64)| n.0 * v.0 + n.1 * v.1 - c;
+    let mut out = Vec::with_capacity(poly.len() + 1);
+    for i in 0..poly.len() {
+        let a = poly[i];
+        let b = poly[(i + 1) % poly.len()];
+        let (sa, sb) = (side(&a), side(&b));
+        if sa <= 0.0 {
+            out.push(a);
+        }
+        if (sa < 0.0 && sb > 0.0) || (sa > 0.0 && sb < 0.0) {
+            let t = sa / (sa - sb);
+            out.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+        }
+    }
+    out
+}
+
+/// Area of a polygon
+fn area(poly: &[(f64, f64)]) -> f64 {
+    let n = poly.len();
+    0.5 * (0..n)
+        .map(|i| {
+            let (a, b) = (poly[i], poly[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        }).sum::<f64>()
+        .abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kspace::SpokeOrdering;
+    use KSpace;
+    use KSpaceProjections;
+
+    /// Check that `w / |k_xy|` is the same for all samples with `|k_xy|` in `(lo, hi)`
+    fn check_proportional(k: &[KSample], w: &[f64], lo: f64, hi: f64, tol: f64) {
+        let ratios: Vec<f64> = k
+            .iter()
+            .zip(w)
+            .map(|(s, &w)| (s[0].hypot(s[1]), w))
+            .filter(|&(r, _)| r > lo && r < hi)
+            .map(|(r, w)| w / r)
+            .collect();
+        assert!(ratios.len() > 10);
+        let mean = ratios.iter().sum::<f64>() / (ratios.len() as f64);
+        for r in ratios {
+            assert!((r / mean - 1.0).abs() < tol, "{} vs {}", r, mean);
+        }
+    }
+
+    #[test]
+    fn radial_weights() {
+        let (fov, samples, spokes) = (0.2, 64, 48);
+        let dk = 1.0 / fov;
+        let k = KSpaceProjections::radial(fov, samples, spokes);
+        let kmax = dk * (samples / 2) as f64;
+
+        let ramp = ramp(&k);
+        for (s, w) in k.samples().iter().zip(&ramp) {
+            assert!((w - s[0].hypot(s[1])).abs() < 1e-12);
+        }
+
+        // away from the center and the edge, the cells are annulus sectors of area
+        // `|k| dk pi / spokes`
+        let w = voronoi(&k).unwrap();
+        check_proportional(&k.samples(), &w, 2.5 * dk, kmax - 1.5 * dk, 0.02);
+        let expected = dk * PI / (spokes as f64);
+        for (s, w) in k.samples().iter().zip(&w) {
+            let r = s[0].hypot(s[1]);
+            if r > 2.5 * dk && r < kmax - 1.5 * dk {
+                assert!((w / r / expected - 1.0).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn stack_of_stars_weights() {
+        let (fov, fov_z, samples, spokes, partitions) = (0.2, 0.1, 32, 24, 6);
+        let (dk, dkz) = (1.0 / fov, 1.0 / fov_z);
+        let k = KSpaceProjections::stack_of_stars(
+            fov,
+            fov_z,
+            samples,
+            spokes,
+            partitions,
+            SpokeOrdering::Uniform,
+        );
+        let kmax = dk * (samples / 2) as f64;
+        let kz = |s: &KSample| s[2];
+        let kz_min = k.samples().iter().map(kz).fold(f64::INFINITY, f64::min);
+        let kz_max = k.samples().iter().map(kz).fold(-f64::INFINITY, f64::max);
+        let w = voronoi(&k).unwrap();
+        let (samples, w): (Vec<KSample>, Vec<f64>) = k
+            .samples()
+            .into_iter()
+            .zip(w)
+            .filter(|(s, _)| s[2] > kz_min + 0.5 * dkz && s[2] < kz_max - 0.5 * dkz)
+            .unzip();
+        check_proportional(&samples, &w, 2.5 * dk, kmax - 1.5 * dk, 0.02);
+        let expected = dk * dkz * PI / (spokes as f64);
+        for (s, w) in samples.iter().zip(&w) {
+            let r = s[0].hypot(s[1]);
+            if r > 2.5 * dk && r < kmax - 1.5 * dk {
+                assert!((w / r / expected - 1.0).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn cartesian_3d_weights() {
+        let fov = SpatialDims::ThreeD(0.2, 0.25, 0.1);
+        let k = KSpace::cartesian(fov.clone(), SpatialDims::ThreeD(6, 5, 4));
+        let w = voronoi(&k).unwrap();
+        let dk = fov.invert();
+        let samples = k.samples();
+        let min = |d: usize| samples.iter().map(|s| s[d]).fold(f64::INFINITY, f64::min);
+        let max = |d: usize| samples.iter().map(|s| s[d]).fold(-f64::INFINITY, f64::max);
+        let cell: f64 = dk.into_iter().product();
+        let mut interior = 0;
+        for (s, w) in samples.iter().zip(&w) {
+            if (0..3).all(|d| s[d] > min(d) + 1e-9 && s[d] < max(d) - 1e-9) {
+                assert!((w / cell - 1.0).abs() < 1e-9, "{} vs {}", w, cell);
+                interior += 1;
+            }
+        }
+        assert!(interior > 0);
+        assert!(w.iter().all(|&x| x > 0.0 && x.is_finite()));
+    }
+
+    #[test]
+    fn pipe_menon_invalid_grid() {
+        let k = KSpaceProjections::radial(0.2, 16, 8);
+        assert!(pipe_menon(&k, SpatialDims::OneD(0.2), SpatialDims::OneD(16), 2).is_err());
+    }
+
+    #[test]
+    fn pipe_menon_radial() {
+        // enough spokes to sample the edge of k-space azimuthally at the Nyquist rate
+        let (fov, samples, spokes) = (0.2, 32, 56);
+        let dk = 1.0 / fov;
+        let k = KSpaceProjections::radial(fov, samples, spokes);
+        let kmax = dk * (samples / 2) as f64;
+        let dims = SpatialDims::TwoD(samples, samples);
+        let w = pipe_menon(&k, SpatialDims::TwoD(fov, fov), dims, 20).unwrap();
+        assert!(w.iter().all(|&x| x > 0.0 && x.is_finite()));
+
+        // the mean weight of the samples at the same radius grows with the radius
+        let mut rings: Vec<(f64, f64)> = k
+            .samples()
+            .iter()
+            .zip(&w)
+            .map(|(s, &w)| (s[0].hypot(s[1]), w))
+            .filter(|&(r, _)| r < kmax - 2.0 * dk)
+            .collect();
+        rings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut means: Vec<(f64, f64, usize)> = vec![];
+        for (r, w) in rings {
+            match means.last_mut() {
+                Some(m) if r - m.0 < 1e-6 * dk => {
+                    m.1 += w;
+                    m.2 += 1;
+                }
+                _ => means.push((r, w, 1)),
+            }
+        }
+        let means: Vec<f64> = means.iter().map(|m| m.1 / (m.2 as f64)).collect();
+        assert!(means.len() > 10);
+        assert!(means.windows(2).all(|m| m[1] > m[0]));
+
+        // and away from the center and the edge, the weights are proportional to the ramp and
+        // the Voronoi weights
+        check_proportional(&k.samples(), &w, 2.5 * dk, kmax - 3.0 * dk, 0.03);
+        let ratio: Vec<f64> = k
+            .samples()
+            .iter()
+            .zip(w.iter().zip(voronoi(&k).unwrap()))
+            .filter(|(s, _)| (2.5 * dk..kmax - 3.0 * dk).contains(&s[0].hypot(s[1])))
+            .map(|(_, (a, b))| a / b)
+            .collect();
+        let mean = ratio.iter().sum::<f64>() / (ratio.len() as f64);
+        assert!(ratio.iter().all(|r| (r / mean - 1.0).abs() < 0.03));
+    }
+}
@@ -7,43 +7,162 @@
 
 //! Encoding fields
 
+use error::{check_dims, check_grid, MriError, Result};
+use nifti::{NiftiImage, NiftiVersion};
+use sphericalharmonics::{evaluate, index, num_harmonics};
+use std::path::Path;
 use std::rc::Rc;
 use SpatialDims;
 
+/// A scalar field evaluated at a position
+pub type FieldFn = Rc<dyn Fn(&SpatialDims<f64>) -> f64>;
+/// The gradient of a field evaluated at a position
+pub type FieldDerivFn = Rc<dyn Fn(&SpatialDims<f64>) -> SpatialDims<f64>>;
+/// Affine transformation from voxel indices to positions, as the rows `[a_x, a_y, a_z, offset]`
+/// of x, y and z
+pub type Affine = [[f64; 4]; 3];
+
+/// Central finite difference stencils
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FiniteDiffStencil {
+    /// second order accurate, 2 evaluations per dimension
+    Central2,
+    /// fourth order accurate, 4 evaluations per dimension
+    Central4,
+    /// sixth order accurate, 6 evaluations per dimension
+    Central6,
+}
+
+impl FiniteDiffStencil {
+    /// weights of `f(x + j*h) - f(x - j*h)` for `j = 1, 2, ...`
+    fn weights(&self) -> &'static [f64] {
+        match *self {
+            FiniteDiffStencil::Central2 => &[1.0 / 2.0],
+            FiniteDiffStencil::Central4 => &[2.0 / 3.0, -1.0 / 12.0],
+            FiniteDiffStencil::Central6 => &[3.0 / 4.0, -3.0 / 20.0, 1.0 / 60.0],
+        }
+    }
+}
+
+/// Default step size of finite differences
+pub const DEFAULT_FINITE_DIFF_STEP: f64 = 1e-6;
+
 /// Different kinds of encoding field derivatives
 #[derive(Clone)]
 enum EncodingFieldDerivative {
-    FiniteDiff,
-    Func(Rc<Fn(&SpatialDims<f64>) -> SpatialDims<f64>>),
+    FiniteDiff { step: f64, stencil: FiniteDiffStencil },
+    Func(FieldDerivFn),
 }
 
 /// This is a field that will be computed on the fly
 #[derive(Clone)]
 pub struct EncodingField {
     /// Field
-    field: Rc<Fn(&SpatialDims<f64>) -> f64>,
+    field: FieldFn,
     /// derivative
     derivative: EncodingFieldDerivative,
 }
 
 impl EncodingField {
     /// Constructor
-    pub fn new(field: Rc<Fn(&SpatialDims<f64>) -> f64>) -> Self {
+    pub fn new(field: FieldFn) -> Self {
         EncodingField {
             field: field.clone(),
-            derivative: EncodingFieldDerivative::FiniteDiff,
+            derivative: EncodingFieldDerivative::FiniteDiff {
+                step: DEFAULT_FINITE_DIFF_STEP,
+                stencil: FiniteDiffStencil::Central2,
+            },
         }
     }
 
     /// Set derivative of the field
-    pub fn derivative(
-        &mut self,
-        derivative: Rc<Fn(&SpatialDims<f64>) -> SpatialDims<f64>>,
-    ) -> &mut Self {
+    pub fn derivative(&mut self, derivative: FieldDerivFn) -> &mut Self {
         self.derivative = EncodingFieldDerivative::Func(derivative.clone());
         self
     }
 
+    /// Compute the derivative with central finite differences of step size `step`
+    ///
+    /// This is the default (with `DEFAULT_FINITE_DIFF_STEP` and `FiniteDiffStencil::Central2`)
+    /// if no derivative is set.
+    pub fn finite_diff(&mut self, step: f64, stencil: FiniteDiffStencil) -> &mut Self {
+        self.try_finite_diff(step, stencil)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Compute the derivative with central finite differences, failing if `step` is not positive
+    /// and finite
+    pub fn try_finite_diff(&mut self, step: f64, stencil: FiniteDiffStencil) -> Result<&mut Self> {
+        if !(step > 0.0 && step.is_finite()) {
+            return Err(MriError::InvalidParameter(format!(
+                "finite difference step {}",
+                step
+            )));
+        }
+        self.derivative = EncodingFieldDerivative::FiniteDiff { step, stencil };
+        Ok(self)
+    }
+
+    /// Real regular solid harmonic `R_lm` (see `sphericalharmonics`) with analytic derivatives.
+    ///
+    /// Missing dimensions of 1D and 2D positions are zero.
+    pub fn solid_harmonic(l: usize, m: i64) -> Result<Self> {
+        let mut coefficients = vec![0.0; num_harmonics(l)];
+        coefficients[index(l, m)?] = 1.0;
+        EncodingField::solid_harmonic_sum(&coefficients)
+    }
+
+    /// All real regular solid harmonics up to order `max_order`, ordered as in
+    /// `sphericalharmonics::index`
+    pub fn solid_harmonics(max_order: usize) -> Vec<Self> {
+        (0..num_harmonics(max_order))
+            .map(|i| {
+                let mut coefficients = vec![0.0; num_harmonics(max_order)];
+                coefficients[i] = 1.0;
+                EncodingField::solid_harmonic_sum(&coefficients).unwrap()
+            }).collect()
+    }
+
+    /// Linear combination of real regular solid harmonics with analytic derivatives.
+    ///
+    /// The coefficients are ordered as in `sphericalharmonics::index` and there has to be one
+    /// for every harmonic up to some order `L`, that is `(L + 1)^2` coefficients.
+    pub fn solid_harmonic_sum(coefficients: &[f64]) -> Result<Self> {
+        let n = (coefficients.len() as f64).sqrt().ceil().max(1.0) as usize;
+        if n * n != coefficients.len() {
+            return Err(MriError::DimensionMismatch {
+                expected: n * n,
+                found: coefficients.len(),
+            });
+        }
+        let max_order = n - 1;
+        let coefficients = coefficients.to_vec();
+        let combine = Rc::new(move |pos: &SpatialDims<f64>| {
+            evaluate(max_order, position(pos))
+                .iter()
+                .zip(coefficients.iter())
+                .fold([0.0; 4], |acc, (h, c)| {
+                    [
+                        acc[0] + c * h[0],
+                        acc[1] + c * h[1],
+                        acc[2] + c * h[2],
+                        acc[3] + c * h[3],
+                    ]
+                })
+        });
+        let deriv = combine.clone();
+        let mut field = EncodingField::new(Rc::new(move |pos: &SpatialDims<f64>| combine(pos)[0]));
+        field.derivative(Rc::new(move |pos: &SpatialDims<f64>| {
+            let d = deriv(pos);
+            match *pos {
+                SpatialDims::OneD(_) => SpatialDims::OneD(d[1]),
+                SpatialDims::TwoD(_, _) => SpatialDims::TwoD(d[1], d[2]),
+                SpatialDims::ThreeD(_, _, _) => SpatialDims::ThreeD(d[1], d[2], d[3]),
+            }
+        }));
+        Ok(field)
+    }
+
     /// Get value of field at position (x, y, z)
     pub fn at(&self, pos: &SpatialDims<f64>) -> f64 {
         (*self.field)(pos)
@@ -52,14 +171,64 @@ impl EncodingField {
     /// Get the derivative at a certain point
     pub fn deriv_at(&self, pos: &SpatialDims<f64>) -> SpatialDims<f64> {
         match self.derivative {
-            EncodingFieldDerivative::FiniteDiff => unimplemented!(),
+            EncodingFieldDerivative::FiniteDiff { step, stencil } => {
+                let d = |axis: usize| {
+                    stencil
+                        .weights()
+                        .iter()
+                        .enumerate()
+                        .map(|(j, w)| {
+                            let delta = ((j + 1) as f64) * step;
+                            w * (self.at(&shift(pos, axis, delta))
+                                - self.at(&shift(pos, axis, -delta)))
+                        }).sum::<f64>()
+                        / step
+                };
+                match *pos {
+                    SpatialDims::OneD(_) => SpatialDims::OneD(d(0)),
+                    SpatialDims::TwoD(_, _) => SpatialDims::TwoD(d(0), d(1)),
+                    SpatialDims::ThreeD(_, _, _) => SpatialDims::ThreeD(d(0), d(1), d(2)),
+                }
+            }
             EncodingFieldDerivative::Func(ref f) => f(pos),
         }
     }
 }
 
-/// todo
-#[derive(Debug)]
+/// Position in 3D, missing dimensions are zero
+fn position(pos: &SpatialDims<f64>) -> [f64; 3] {
+    let mut r = [0.0; 3];
+    for (ri, p) in r.iter_mut().zip(pos.clone()) {
+        *ri = p;
+    }
+    r
+}
+
+/// Move `pos` by `delta` along `axis`
+fn shift(pos: &SpatialDims<f64>, axis: usize, delta: f64) -> SpatialDims<f64> {
+    match (pos, axis) {
+        (&SpatialDims::OneD(x), 0) => SpatialDims::OneD(x + delta),
+        (&SpatialDims::TwoD(x, y), 0) => SpatialDims::TwoD(x + delta, y),
+        (&SpatialDims::TwoD(x, y), 1) => SpatialDims::TwoD(x, y + delta),
+        (&SpatialDims::ThreeD(x, y, z), 0) => SpatialDims::ThreeD(x + delta, y, z),
+        (&SpatialDims::ThreeD(x, y, z), 1) => SpatialDims::ThreeD(x, y + delta, z),
+        (&SpatialDims::ThreeD(x, y, z), 2) => SpatialDims::ThreeD(x, y, z + delta),
+        _ => unreachable!(),
+    }
+}
+
+/// Interpolation of discrete fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// (bi-, tri-) linear interpolation, constant beyond the outermost voxels
+    Linear,
+    /// cubic B-spline interpolation with mirrored boundaries
+    CubicBSpline,
+}
+
+/// A field sampled at the points of `SpatialDims::grid`, in the same order, or at the positions
+/// given by an affine transformation of the voxel indices
+#[derive(Debug, Clone)]
 pub struct EncodingFieldDiscrete {
     /// actual field
     pub field: Vec<f64>,
@@ -67,11 +236,78 @@ pub struct EncodingFieldDiscrete {
     dimensions: SpatialDims<usize>,
     /// field of view
     fov: SpatialDims<f64>,
+    /// transformation from voxel indices to positions
+    affine: Affine,
 }
 
 impl EncodingFieldDiscrete {
+    /// Create a field from its values on a grid with `dimensions` voxels covering `fov`
+    pub fn new(
+        field: Vec<f64>,
+        fov: SpatialDims<f64>,
+        dimensions: SpatialDims<usize>,
+    ) -> Result<Self> {
+        check_grid(&fov, &dimensions)?;
+        check_dims(dimensions.product(), field.len())?;
+        Ok(EncodingFieldDiscrete::from_grid(field, fov, dimensions))
+    }
+
+    /// Create a field from its values at the positions `affine` assigns to the voxel indices of
+    /// a grid with `dimensions` voxels. Positions only have as many dimensions as the grid, so
+    /// for 1D and 2D grids the remaining coordinates must not depend on the voxel index and are
+    /// dropped. The field of view is the number of voxels times the voxel spacing.
+    pub fn with_affine(
+        field: Vec<f64>,
+        dimensions: SpatialDims<usize>,
+        affine: Affine,
+    ) -> Result<Self> {
+        let n = dimensions.len();
+        let fov: Vec<f64> = dimensions
+            .clone()
+            .into_iter()
+            .enumerate()
+            .map(|(c, d)| column_norm(&affine, c) * (d as f64))
+            .collect();
+        let fov = SpatialDims::from_slice(&fov);
+        check_grid(&fov, &dimensions)?;
+        check_dims(dimensions.product(), field.len())?;
+        if affine.iter().flat_map(|row| row.iter()).any(|v| !v.is_finite()) {
+            return Err(MriError::InvalidParameter("affine is not finite".to_string()));
+        }
+        if (n..3).any(|r| (0..n).any(|c| affine[r][c] != 0.0)) {
+            return Err(MriError::InvalidParameter(format!(
+                "affine maps a {}D grid out of its {}D space",
+                n, n
+            )));
+        }
+        invert(&affine, n)?;
+        Ok(EncodingFieldDiscrete {
+            field,
+            dimensions,
+            fov,
+            affine,
+        })
+    }
+
+    /// Field on the grid of `SpatialDims::grid`, without checks
+    fn from_grid(field: Vec<f64>, fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Self {
+        let affine = grid_affine(&fov, &dimensions);
+        EncodingFieldDiscrete {
+            field,
+            dimensions,
+            fov,
+            affine,
+        }
+    }
+
     /// Create a linear field in x
     pub fn linear_x(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Self {
+        EncodingFieldDiscrete::try_linear_x(fov, dimensions).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a linear field in x, failing if the grid is invalid or has no x axis
+    pub fn try_linear_x(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Result<Self> {
+        check_grid(&fov, &dimensions)?;
         let mut field: Vec<f64>;
         match (&dimensions, &fov) {
             (&SpatialDims::OneD(nx), &SpatialDims::OneD(fov_x)) => {
@@ -101,22 +337,26 @@ impl EncodingFieldDiscrete {
                     }
                 }
             }
-            _ => panic!("wrong"),
+            _ => unreachable!(),
         }
 
-        EncodingFieldDiscrete {
-            field,
-            dimensions,
-            fov,
-        }
+        Ok(EncodingFieldDiscrete::from_grid(field, fov, dimensions))
     }
 
     /// Create a linear field in y
     pub fn linear_y(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Self {
+        EncodingFieldDiscrete::try_linear_y(fov, dimensions).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a linear field in y, failing if the grid is invalid or has no y axis
+    pub fn try_linear_y(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Result<Self> {
+        check_grid(&fov, &dimensions)?;
         let mut field: Vec<f64>;
         match (&dimensions, &fov) {
             (&SpatialDims::OneD(_), &SpatialDims::OneD(_)) => {
-                panic!("No y gradient in 1D problems");
+                return Err(MriError::InvalidParameter(
+                    "no y gradient in 1D problems".to_string(),
+                ));
             }
             (&SpatialDims::TwoD(nx, ny), &SpatialDims::TwoD(_, fov_y)) => {
                 field = Vec::with_capacity(nx * ny);
@@ -138,29 +378,35 @@ impl EncodingFieldDiscrete {
                     }
                 }
             }
-            _ => panic!("wrong"),
+            _ => unreachable!(),
         }
 
-        EncodingFieldDiscrete {
-            field,
-            dimensions,
-            fov,
-        }
+        Ok(EncodingFieldDiscrete::from_grid(field, fov, dimensions))
     }
 
     /// Create a linear field in z
     pub fn linear_z(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Self {
+        EncodingFieldDiscrete::try_linear_z(fov, dimensions).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a linear field in z, failing if the grid is invalid or has no z axis
+    pub fn try_linear_z(fov: SpatialDims<f64>, dimensions: SpatialDims<usize>) -> Result<Self> {
+        check_grid(&fov, &dimensions)?;
         let mut field: Vec<f64>;
         match (&dimensions, &fov) {
             (&SpatialDims::OneD(_), &SpatialDims::OneD(_)) => {
-                panic!("No z gradient in 1D problems");
+                return Err(MriError::InvalidParameter(
+                    "no z gradient in 1D problems".to_string(),
+                ));
             }
             (&SpatialDims::TwoD(_, _), &SpatialDims::TwoD(_, _)) => {
-                panic!("No z gradient in 2D problems");
+                return Err(MriError::InvalidParameter(
+                    "no z gradient in 2D problems".to_string(),
+                ));
             }
             (&SpatialDims::ThreeD(nx, ny, nz), &SpatialDims::ThreeD(_, _, fov_z)) => {
                 field = Vec::with_capacity(nx * ny * nz);
-                let step = 1.0 / (ny as f64);
+                let step = 1.0 / (nz as f64);
                 for z in 0..nz {
                     for _ in 0..ny {
                         for _ in 0..nx {
@@ -169,14 +415,10 @@ impl EncodingFieldDiscrete {
                     }
                 }
             }
-            _ => panic!("wrong"),
+            _ => unreachable!(),
         }
 
-        EncodingFieldDiscrete {
-            field,
-            dimensions,
-            fov,
-        }
+        Ok(EncodingFieldDiscrete::from_grid(field, fov, dimensions))
     }
 
     /// Return the dimensions
@@ -188,4 +430,484 @@ impl EncodingFieldDiscrete {
     pub fn fov(&self) -> SpatialDims<f64> {
         self.fov.clone()
     }
+
+    /// Return the transformation from voxel indices to positions
+    pub fn affine(&self) -> Affine {
+        self.affine
+    }
+
+    /// Read a field from a NIfTI file, see `NiftiImage::to_field`
+    pub fn read_nifti<P: AsRef<Path>>(path: P) -> Result<Self> {
+        NiftiImage::read(path)?.to_field()
+    }
+
+    /// Write the field to a NIfTI file
+    pub fn write_nifti<P: AsRef<Path>>(&self, path: P, version: NiftiVersion) -> Result<()> {
+        NiftiImage::from_field(self).write(path, version)
+    }
+
+    /// Interpolate the field, resulting in an `EncodingField` with analytic derivatives of the
+    /// interpolant
+    pub fn to_encoding_field(&self, method: Interpolation) -> Result<EncodingField> {
+        let interp = Rc::new(Interpolator::new(self, method)?);
+        let deriv = interp.clone();
+        let mut field = EncodingField::new(Rc::new(move |pos: &SpatialDims<f64>| interp.at(pos).0));
+        field.derivative(Rc::new(move |pos: &SpatialDims<f64>| {
+            let d = deriv.at(pos).1;
+            match *pos {
+                SpatialDims::OneD(_) => SpatialDims::OneD(d[0]),
+                SpatialDims::TwoD(_, _) => SpatialDims::TwoD(d[0], d[1]),
+                SpatialDims::ThreeD(_, _, _) => SpatialDims::ThreeD(d[0], d[1], d[2]),
+            }
+        }));
+        Ok(field)
+    }
+}
+
+/// Interpolant of a discrete field
+struct Interpolator {
+    /// field values (linear) or B-spline coefficients (cubic)
+    coefficients: Vec<f64>,
+    /// number of voxels along x, y and z (1 for missing dimensions)
+    n: [usize; 3],
+    /// transformation from positions to voxel indices, without the offset
+    inverse: [[f64; 3]; 3],
+    /// position of the first voxel
+    offset: [f64; 3],
+    /// number of dimensions
+    num_dims: usize,
+    /// interpolation method
+    method: Interpolation,
+}
+
+impl Interpolator {
+    /// Prepare the interpolation of a discrete field
+    fn new(field: &EncodingFieldDiscrete, method: Interpolation) -> Result<Self> {
+        check_grid(&field.fov, &field.dimensions)?;
+        check_dims(field.dimensions.product(), field.field.len())?;
+        let mut n = [1; 3];
+        for (ni, d) in n.iter_mut().zip(field.dimensions.clone()) {
+            *ni = d;
+        }
+        let num_dims = field.dimensions.len();
+        let inverse = invert(&field.affine, num_dims)?;
+        let mut offset = [0.0; 3];
+        for (o, row) in offset.iter_mut().zip(field.affine.iter()).take(num_dims) {
+            *o = row[3];
+        }
+        let mut coefficients = field.field.clone();
+        if method == Interpolation::CubicBSpline {
+            bspline_prefilter(&mut coefficients, n);
+        }
+        Ok(Interpolator {
+            coefficients,
+            n,
+            inverse,
+            offset,
+            num_dims,
+            method,
+        })
+    }
+
+    /// Value and gradient at a position. Missing dimensions of `pos` are zero.
+    fn at(&self, pos: &SpatialDims<f64>) -> (f64, [f64; 3]) {
+        let r = position(pos);
+        // taps `(index, weight, derivative of weight)` along every axis of the grid
+        let taps: Vec<Vec<(usize, f64, f64)>> = (0..3)
+            .map(|a| {
+                if a >= self.num_dims || self.n[a] == 1 {
+                    return vec![(0, 1.0, 0.0)];
+                }
+                let n = self.n[a];
+                let t: f64 = (0..self.num_dims)
+                    .map(|b| self.inverse[a][b] * (r[b] - self.offset[b]))
+                    .sum();
+                match self.method {
+                    Interpolation::Linear => linear_taps(t, n, 1.0),
+                    Interpolation::CubicBSpline => cubic_taps(t, n, 1.0),
+                }
+            }).collect();
+
+        let mut value = 0.0;
+        // gradient with respect to the voxel indices
+        let mut grad = [0.0; 3];
+        for &(iz, wz, dz) in &taps[2] {
+            for &(iy, wy, dy) in &taps[1] {
+                for &(ix, wx, dx) in &taps[0] {
+                    let c = self.coefficients[(iz * self.n[1] + iy) * self.n[0] + ix];
+                    value += c * wx * wy * wz;
+                    grad[0] += c * dx * wy * wz;
+                    grad[1] += c * wx * dy * wz;
+                    grad[2] += c * wx * wy * dz;
+                }
+            }
+        }
+        let mut world = [0.0; 3];
+        for (b, w) in world.iter_mut().enumerate().take(self.num_dims) {
+            *w = (0..self.num_dims).map(|a| grad[a] * self.inverse[a][b]).sum();
+        }
+        (value, world)
+    }
+}
+
+/// Affine of the grid of `SpatialDims::grid`, with 1 mm spacing along missing dimensions
+pub(crate) fn grid_affine(fov: &SpatialDims<f64>, dims: &SpatialDims<usize>) -> Affine {
+    let fov: Vec<f64> = fov.clone().into_iter().collect();
+    let dims: Vec<usize> = dims.clone().into_iter().collect();
+    let mut affine = [[0.0; 4]; 3];
+    for (i, row) in affine.iter_mut().enumerate() {
+        if i < dims.len() {
+            row[i] = fov[i] / (dims[i] as f64);
+            row[3] = -0.5 * fov[i];
+        } else {
+            row[i] = 1e-3;
+        }
+    }
+    affine
+}
+
+/// Length of column `c` of an affine, the voxel spacing along that axis of the grid
+fn column_norm(affine: &Affine, c: usize) -> f64 {
+    affine.iter().map(|row| row[c] * row[c]).sum::<f64>().sqrt()
+}
+
+/// Inverse of the upper left `n x n` block of an affine, padded with the identity, failing if
+/// the block is singular
+fn invert(affine: &Affine, n: usize) -> Result<[[f64; 3]; 3]> {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = if i < n && j < n {
+                affine[i][j]
+            } else if i == j {
+                1.0
+            } else {
+                0.0
+            };
+        }
+    }
+    let cof = |i: usize, j: usize| {
+        let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+        let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+        m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+    };
+    let det: f64 = (0..3).map(|j| m[0][j] * cof(0, j)).sum();
+    let scale: f64 = (0..n).map(|c| column_norm(affine, c)).product();
+    if !(det.abs() > 1e-12 * scale && det.is_finite()) {
+        return Err(MriError::InvalidParameter("singular affine".to_string()));
+    }
+    let mut inv = [[0.0; 3]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = cof(j, i) / det;
+        }
+    }
+    Ok(inv)
+}
+
+/// Linear interpolation taps at continuous index `t`, constant beyond the outermost samples
+fn linear_taps(t: f64, n: usize, scale: f64) -> Vec<(usize, f64, f64)> {
+    let inside = t >= 0.0 && t <= (n - 1) as f64;
+    let tc = t.max(0.0).min((n - 1) as f64);
+    let i = (tc.floor() as usize).min(n - 2);
+    let w = tc - (i as f64);
+    let d = if inside { scale } else { 0.0 };
+    vec![(i, 1.0 - w, -d), (i + 1, w, d)]
+}
+
+/// Cubic B-spline taps at continuous index `t` with mirrored boundaries
+fn cubic_taps(t: f64, n: usize, scale: f64) -> Vec<(usize, f64, f64)> {
+    let i = t.floor();
+    let w = t - i;
+    let w2 = w * w;
+    let w3 = w2 * w;
+    let weights = [
+        (1.0 - w).powi(3) / 6.0,
+        (4.0 - 6.0 * w2 + 3.0 * w3) / 6.0,
+        (1.0 + 3.0 * w + 3.0 * w2 - 3.0 * w3) / 6.0,
+        w3 / 6.0,
+    ];
+    let derivs = [
+        -0.5 * (1.0 - w).powi(2),
+        -2.0 * w + 1.5 * w2,
+        0.5 + w - 1.5 * w2,
+        0.5 * w2,
+    ];
+    (0..4)
+        .map(|j| {
+            let k = mirror(i as i64 - 1 + j as i64, n);
+            (k, weights[j], derivs[j] * scale)
+        }).collect()
+}
+
+/// Mirror an index into `[0, n)` (whole-sample symmetric extension)
+fn mirror(k: i64, n: usize) -> usize {
+    let period = 2 * (n as i64 - 1);
+    let k = k.rem_euclid(period);
+    if k < n as i64 {
+        k as usize
+    } else {
+        (period - k) as usize
+    }
+}
+
+/// Turn samples into cubic B-spline coefficients along every axis (Unser, "Splines: a perfect
+/// fit for signal and image processing", IEEE Signal Process. Mag. 16(6), 1999)
+fn bspline_prefilter(c: &mut [f64], n: [usize; 3]) {
+    let stride = [1, n[0], n[0] * n[1]];
+    for axis in 0..3 {
+        if n[axis] < 2 {
+            continue;
+        }
+        let mut line = vec![0.0; n[axis]];
+        let block = n[axis] * stride[axis];
+        // first element of every line along `axis`
+        let starts = (0..c.len() / block).flat_map(|b| (0..stride[axis]).map(move |i| b * block + i));
+        for start in starts {
+            for (k, l) in line.iter_mut().enumerate() {
+                *l = c[start + k * stride[axis]];
+            }
+            bspline_prefilter_line(&mut line);
+            for (k, l) in line.iter().enumerate() {
+                c[start + k * stride[axis]] = *l;
+            }
+        }
+    }
+}
+
+/// Cubic B-spline prefilter of a single line with mirrored boundaries
+fn bspline_prefilter_line(c: &mut [f64]) {
+    let n = c.len();
+    let z = 3f64.sqrt() - 2.0;
+    for x in c.iter_mut() {
+        *x *= (1.0 - z) * (1.0 - 1.0 / z);
+    }
+
+    // causal initialization
+    let mut zn = z;
+    let mut z2n = z.powi(n as i32 - 1);
+    let mut sum = c[0] + z2n * c[n - 1];
+    z2n *= z2n / z;
+    for x in c.iter().take(n - 1).skip(1) {
+        sum += (zn + z2n) * x;
+        zn *= z;
+        z2n /= z;
+    }
+    c[0] = sum / (1.0 - zn * zn);
+    for k in 1..n {
+        c[k] += z * c[k - 1];
+    }
+
+    // anticausal
+    c[n - 1] = (z / (z * z - 1.0)) * (z * c[n - 2] + c[n - 1]);
+    for k in (0..n - 1).rev() {
+        c[k] = z * (c[k + 1] - c[k]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::Rng;
+    use std::f64::consts::PI;
+
+    /// deterministic pseudo random numbers in [-1, 1)
+    fn random(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| 2.0 * rng.uniform() - 1.0).collect()
+    }
+
+    /// random field on a grid with `dims` voxels covering `fov`
+    fn random_field(fov: SpatialDims<f64>, dims: SpatialDims<usize>) -> EncodingFieldDiscrete {
+        EncodingFieldDiscrete::new(random(dims.product(), 7), fov, dims).unwrap()
+    }
+
+    #[test]
+    fn interpolation_reproduces_samples() {
+        let grids = vec![
+            (SpatialDims::OneD(0.2), SpatialDims::OneD(9)),
+            (SpatialDims::TwoD(0.2, 0.1), SpatialDims::TwoD(6, 2)),
+            (SpatialDims::ThreeD(0.2, 0.3, 0.1), SpatialDims::ThreeD(7, 5, 4)),
+        ];
+        for (fov, dims) in grids {
+            let discrete = random_field(fov.clone(), dims.clone());
+            for &method in &[Interpolation::Linear, Interpolation::CubicBSpline] {
+                let f = discrete.to_encoding_field(method).unwrap();
+                for (v, pos) in discrete.field.iter().zip(fov.grid(&dims)) {
+                    assert!((f.at(&pos) - v).abs() < 1e-12, "{:?} {:?}", method, pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_derivatives() {
+        let fov = SpatialDims::ThreeD(0.2, 0.3, 0.1);
+        let dims = SpatialDims::ThreeD(7, 5, 4);
+        let discrete = random_field(fov, dims);
+        let eps = 1e-7;
+        let pos = SpatialDims::ThreeD(0.013, -0.041, 0.007);
+        for &method in &[Interpolation::Linear, Interpolation::CubicBSpline] {
+            let f = discrete.to_encoding_field(method).unwrap();
+            let grad: Vec<f64> = f.deriv_at(&pos).into_iter().collect();
+            for (axis, g) in grad.iter().enumerate() {
+                let (plus, minus) = (f.at(&shift(&pos, axis, eps)), f.at(&shift(&pos, axis, -eps)));
+                let fd = (plus - minus) / (2.0 * eps);
+                assert!((fd - g).abs() < 1e-5 * g.abs().max(1.0), "{:?} {}", method, axis);
+            }
+        }
+    }
+
+    #[test]
+    fn cubic_interpolation_is_smooth() {
+        // the cubic B-spline interpolant of a smooth function is close to it between the samples
+        let fov = SpatialDims::OneD(1.0);
+        let dims = SpatialDims::OneD(32);
+        let g = |x: f64| (2.0 * PI * x).sin();
+        let values = fov.grid(&dims).iter().map(|p| g(p.x().unwrap())).collect();
+        let f = EncodingFieldDiscrete::new(values, fov, dims)
+            .unwrap()
+            .to_encoding_field(Interpolation::CubicBSpline)
+            .unwrap();
+        for i in 0..50 {
+            let x = -0.3 + 0.6 * (i as f64) / 50.0;
+            assert!((f.at(&SpatialDims::OneD(x)) - g(x)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn linear_z_spacing() {
+        let fov = SpatialDims::ThreeD(0.2, 0.3, 0.4);
+        let dims = SpatialDims::ThreeD(2, 3, 5);
+        let f = EncodingFieldDiscrete::linear_z(fov.clone(), dims.clone());
+        for (v, pos) in f.field.iter().zip(fov.grid(&dims)) {
+            assert!((v - pos.z().unwrap()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn missing_axes() {
+        let one = (SpatialDims::OneD(0.2), SpatialDims::OneD(4));
+        let two = (SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(4, 4));
+        assert!(EncodingFieldDiscrete::try_linear_x(one.0.clone(), one.1.clone()).is_ok());
+        assert!(matches!(
+            EncodingFieldDiscrete::try_linear_y(one.0.clone(), one.1.clone()),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            EncodingFieldDiscrete::try_linear_z(one.0, one.1),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert!(EncodingFieldDiscrete::try_linear_y(two.0.clone(), two.1.clone()).is_ok());
+        assert!(matches!(
+            EncodingFieldDiscrete::try_linear_z(two.0.clone(), two.1),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            EncodingFieldDiscrete::try_linear_z(two.0, SpatialDims::ThreeD(4, 4, 4)),
+            Err(MriError::DimensionMismatch { .. })
+        ));
+    }
+
+    /// analytic gradient of a test field
+    type Gradient = Rc<dyn Fn(&SpatialDims<f64>) -> Vec<f64>>;
+
+    /// product of sines along every axis and its gradient
+    fn sines() -> (EncodingField, Gradient) {
+        let w = [9.0, 7.0, 11.0];
+        let phase = [0.4, -0.3, 1.1];
+        let factors = move |pos: &SpatialDims<f64>| -> Vec<(f64, f64)> {
+            pos.clone()
+                .into_iter()
+                .enumerate()
+                .map(|(d, p)| {
+                    let (s, c) = (w[d] * p + phase[d]).sin_cos();
+                    (s, w[d] * c)
+                }).collect()
+        };
+        let field = EncodingField::new(Rc::new(move |pos: &SpatialDims<f64>| {
+            factors(pos).iter().map(|f| f.0).product()
+        }));
+        let gradient = Rc::new(move |pos: &SpatialDims<f64>| {
+            let f = factors(pos);
+            (0..f.len())
+                .map(|d| {
+                    f.iter()
+                        .enumerate()
+                        .map(|(i, fi)| if i == d { fi.1 } else { fi.0 })
+                        .product()
+                }).collect()
+        });
+        (field, gradient)
+    }
+
+    /// largest error of the finite differences of `field` at `pos`
+    fn stencil_error(
+        field: &mut EncodingField,
+        gradient: &dyn Fn(&SpatialDims<f64>) -> Vec<f64>,
+        pos: &SpatialDims<f64>,
+        step: f64,
+        stencil: FiniteDiffStencil,
+    ) -> f64 {
+        let fd: Vec<f64> = field
+            .finite_diff(step, stencil)
+            .deriv_at(pos)
+            .into_iter()
+            .collect();
+        fd.iter()
+            .zip(gradient(pos))
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn finite_difference_stencils() {
+        let stencils = [
+            FiniteDiffStencil::Central2,
+            FiniteDiffStencil::Central4,
+            FiniteDiffStencil::Central6,
+        ];
+        let positions = vec![
+            SpatialDims::OneD(0.031),
+            SpatialDims::TwoD(0.031, -0.047),
+            SpatialDims::ThreeD(0.031, -0.047, 0.012),
+        ];
+        for pos in positions {
+            let (mut field, gradient) = sines();
+            for &stencil in &stencils {
+                let e = stencil_error(&mut field, &*gradient, &pos, 1e-4, stencil);
+                assert!(e < 1e-5, "{:?} {:?}: {}", pos, stencil, e);
+            }
+            // higher orders are more accurate at a coarse step
+            let e: Vec<f64> = stencils
+                .iter()
+                .map(|&s| stencil_error(&mut field, &*gradient, &pos, 0.05, s))
+                .collect();
+            assert!(e[1] < 0.1 * e[0] && e[2] < 0.1 * e[1], "{:?}: {:?}", pos, e);
+        }
+
+        // the stencils are exact for polynomials up to their order
+        let mut quintic = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| {
+            pos.x().unwrap().powi(5)
+        }));
+        let quintic_gradient = |pos: &SpatialDims<f64>| vec![5.0 * pos.x().unwrap().powi(4)];
+        let pos = SpatialDims::OneD(0.3);
+        let e6 = stencil_error(&mut quintic, &quintic_gradient, &pos, 0.1, stencils[2]);
+        let e4 = stencil_error(&mut quintic, &quintic_gradient, &pos, 0.1, stencils[1]);
+        assert!(e6 < 1e-12 && e4 > 1e-4, "{} {}", e6, e4);
+    }
+
+    #[test]
+    fn invalid_finite_difference_step() {
+        let mut field = EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap()));
+        for &step in &[0.0, -1e-3, f64::INFINITY, f64::NAN] {
+            assert!(matches!(
+                field.try_finite_diff(step, FiniteDiffStencil::Central4),
+                Err(MriError::InvalidParameter(_))
+            ));
+        }
+        assert!(field
+            .try_finite_diff(1e-3, FiniteDiffStencil::Central4)
+            .is_ok());
+    }
 }
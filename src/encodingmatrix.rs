@@ -0,0 +1,414 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Encoding matrix
+//!
+//! The signal of coil `c` at k-space sample `k` is modelled as
+//!
+//! `s_c(k) = sum_r m(r) * S_c(r) * exp(-i 2 pi sum_j k_j psi_j(r))`
+//!
+//! where `m` is the image, `S_c` the sensitivity of coil `c` and `psi_j` the encoding field
+//! belonging to k-space channel `j`. The sum runs over the voxel grid defined by a field of view
+//! and a matrix size.
+//!
+//! Optionally, a B0 field map adds the off-resonance term `exp(-i 2 pi df(r) t)` with the
+//! readout time `t` of every sample, see `offresonance`.
+
+use encodingfield::EncodingFieldDiscrete;
+use error::{check_dims, check_grid, MriError, Result};
+use num::Complex;
+use offresonance::{OffResonance, OffResonanceMethod};
+use std::f64::consts::PI;
+use EncodingField;
+use KSample;
+use KSpaceThings;
+use RFSensitivity;
+use SpatialDims;
+
+/// Encoding matrix (forward model) for arbitrary encoding fields
+pub struct EncodingMatrix<T>
+where
+    T: KSpaceThings,
+{
+    /// kspace
+    k: T,
+    /// vector of encoding fields
+    psi: Vec<EncodingField>,
+    /// rf sensitivity maps
+    rf: Vec<RFSensitivity>,
+    /// field of view
+    fov: SpatialDims<f64>,
+    /// number of voxels in each dimension
+    dims: SpatialDims<usize>,
+    /// k-space samples
+    samples: Vec<KSample>,
+    /// encoding fields evaluated at the voxel positions (`[voxel][channel]`)
+    psi_grid: Vec<Vec<f64>>,
+    /// off-resonance term
+    off_resonance: Option<OffResonance>,
+}
+
+impl<T: KSpaceThings> EncodingMatrix<T> {
+    /// Create a new encoding matrix on a grid of `dims` voxels covering `fov`.
+    ///
+    /// Every k-space channel needs one encoding field and every sensitivity map needs one value
+    /// per voxel, where the voxels are ordered as in `SpatialDims::grid`.
+    pub fn new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: Vec<RFSensitivity>,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Self {
+        EncodingMatrix::try_new(k, psi, rf, fov, dims).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create an encoding matrix, failing if the trajectory, fields, sensitivities and grid do
+    /// not fit together
+    pub fn try_new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: Vec<RFSensitivity>,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Result<Self> {
+        check_dims(k.num_channels(), psi.len())?;
+        check_grid(&fov, &dims)?;
+        let num_voxels = dims.product();
+        for s in &rf {
+            check_dims(num_voxels, s.sens.len())?;
+        }
+        let psi_grid = fov
+            .grid(&dims)
+            .iter()
+            .map(|pos| psi.iter().map(|f| f.at(pos)).collect())
+            .collect();
+        let samples = k.samples();
+        Ok(EncodingMatrix {
+            k,
+            psi,
+            rf,
+            fov,
+            dims,
+            samples,
+            psi_grid,
+            off_resonance: None,
+        })
+    }
+
+    /// Add off-resonance to the model, failing if it does not fit the samples and voxels
+    pub fn off_resonance(&mut self, off_resonance: OffResonance) -> Result<&mut Self> {
+        off_resonance.check(self.num_samples(), self.num_voxels())?;
+        self.off_resonance = Some(off_resonance);
+        Ok(self)
+    }
+
+    /// Add off-resonance from a B0 field map (Hz) and the readout time of every sample (s), in
+    /// the order of `KSpaceThings::samples`
+    pub fn field_map(
+        &mut self,
+        b0: &EncodingFieldDiscrete,
+        times: Vec<f64>,
+        method: OffResonanceMethod,
+    ) -> Result<&mut Self> {
+        let off_resonance = OffResonance::from_field_map(b0, &self.fov, &self.dims, times, method)?;
+        self.off_resonance(off_resonance)
+    }
+
+    /// Add off-resonance from a B0 field map (Hz) with the acquisition times of the trajectory,
+    /// failing if the trajectory has no timing
+    pub fn timed_field_map(
+        &mut self,
+        b0: &EncodingFieldDiscrete,
+        method: OffResonanceMethod,
+    ) -> Result<&mut Self> {
+        let times = self.k.sample_times().ok_or_else(|| {
+            MriError::InvalidParameter("trajectory has no sample timing".to_string())
+        })?;
+        self.field_map(b0, times, method)
+    }
+
+    /// Return the k-space trajectory
+    pub fn kspace(&self) -> &T {
+        &self.k
+    }
+
+    /// Return the encoding fields
+    pub fn fields(&self) -> &[EncodingField] {
+        &self.psi
+    }
+
+    /// Return the RF sensitivities
+    pub fn sensitivities(&self) -> &[RFSensitivity] {
+        &self.rf
+    }
+
+    /// Return the off-resonance term
+    pub fn off_resonance_term(&self) -> Option<&OffResonance> {
+        self.off_resonance.as_ref()
+    }
+
+    /// Return the FOV
+    pub fn fov(&self) -> SpatialDims<f64> {
+        self.fov.clone()
+    }
+
+    /// Return the grid dimensions
+    pub fn dimensions(&self) -> SpatialDims<usize> {
+        self.dims.clone()
+    }
+
+    /// Return the number of coils
+    pub fn num_coils(&self) -> usize {
+        self.rf.len()
+    }
+
+    /// Return the number of voxels
+    pub fn num_voxels(&self) -> usize {
+        self.psi_grid.len()
+    }
+
+    /// Return the number of k-space samples
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Encoding phase of voxel `r` at sample `m` (without the `-2 pi`)
+    fn phase(&self, m: usize, r: usize) -> f64 {
+        self.samples[m]
+            .iter()
+            .zip(self.psi_grid[r].iter())
+            .map(|(k, p)| k * p)
+            .sum()
+    }
+
+    /// Element of the encoding matrix for voxel `r` at sample `m`, without coil sensitivity
+    fn entry(&self, m: usize, r: usize) -> Complex<f64> {
+        let e = Complex::from_polar(1.0, -2.0 * PI * self.phase(m, r));
+        match self.off_resonance {
+            Some(ref o) => e * o.at(m, r),
+            None => e,
+        }
+    }
+
+    /// Apply the encoding matrix to an image.
+    ///
+    /// Returns the k-space signal of every coil (`[coil][sample]`), with the samples in the order
+    /// of `KSpaceThings::samples`.
+    pub fn apply(&self, img: &[Complex<f64>]) -> Vec<Vec<Complex<f64>>> {
+        assert!(img.len() == self.num_voxels());
+        let weighted: Vec<Vec<Complex<f64>>> = self.rf.iter().map(|s| s.mul(img)).collect();
+
+        let mut out = vec![vec![Complex::new(0.0, 0.0); self.num_samples()]; self.num_coils()];
+        for m in 0..self.num_samples() {
+            for r in 0..self.num_voxels() {
+                let e = self.entry(m, r);
+                for (o, w) in out.iter_mut().zip(weighted.iter()) {
+                    o[m] += w[r] * e;
+                }
+            }
+        }
+        out
+    }
+
+    /// Apply the adjoint (conjugate transpose) of the encoding matrix to multi-coil k-space data.
+    ///
+    /// `data` holds the signal of every coil (`[coil][sample]`) in the order of
+    /// `KSpaceThings::samples`. Returns an image on the voxel grid.
+    pub fn apply_adjoint(&self, data: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+        assert!(data.len() == self.num_coils());
+        assert!(data.iter().all(|d| d.len() == self.num_samples()));
+        let mut coil = vec![vec![Complex::new(0.0, 0.0); self.num_voxels()]; self.num_coils()];
+        for r in 0..self.num_voxels() {
+            for m in 0..self.num_samples() {
+                let e = self.entry(m, r).conj();
+                for (c, d) in coil.iter_mut().zip(data.iter()) {
+                    c[r] += d[m] * e;
+                }
+            }
+        }
+
+        let mut out = vec![Complex::new(0.0, 0.0); self.num_voxels()];
+        for (s, c) in self.rf.iter().zip(coil.iter()) {
+            for (o, v) in out.iter_mut().zip(s.conj_mul(c)) {
+                *o += v;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linalg::dot;
+    use random::Rng;
+    use std::rc::Rc;
+    use KSpace;
+    use KSpaceParameterizedProjections;
+    use KSpaceProjections;
+
+    /// deterministic pseudo random numbers in [-1, 1)
+    fn random(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| 2.0 * rng.uniform() - 1.0).collect()
+    }
+
+    fn random_complex(n: usize, seed: u64) -> Vec<Complex<f64>> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| Complex::new(rng.normal(), rng.normal())).collect()
+    }
+
+    /// quadratic (nonlinear) encoding fields
+    fn fields() -> Vec<EncodingField> {
+        vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| {
+                pos.x().unwrap().powi(2) - pos.y().unwrap().powi(2)
+            })),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| {
+                2.0 * pos.x().unwrap() * pos.y().unwrap()
+            })),
+        ]
+    }
+
+    /// check <Ex, y> == <x, E^H y>
+    fn check_adjoint<T: KSpaceThings>(k: T) {
+        check_adjoint_off_resonance(k, None);
+    }
+
+    /// check <Ex, y> == <x, E^H y> with an optional off-resonance method
+    fn check_adjoint_off_resonance<T: KSpaceThings>(k: T, method: Option<OffResonanceMethod>) {
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(6, 5);
+        let nv = dims.product();
+        let rf: Vec<RFSensitivity> = (0..3)
+            .map(|c| {
+                let re = random(nv, 10 + c);
+                let im = random(nv, 20 + c);
+                RFSensitivity::from_tuples(re.into_iter().zip(im).collect())
+            }).collect();
+        let mut e = EncodingMatrix::new(k, fields(), rf, fov.clone(), dims.clone());
+        if let Some(method) = method {
+            let b0 = random(nv, 30).iter().map(|x| 100.0 * x).collect();
+            let b0 = EncodingFieldDiscrete::new(b0, fov, dims).unwrap();
+            let times = (0..e.num_samples()).map(|m| 1e-4 * m as f64).collect();
+            e.field_map(&b0, times, method).unwrap();
+        }
+
+        let x = random_complex(e.num_voxels(), 1);
+        let y: Vec<Vec<Complex<f64>>> = (0..e.num_coils())
+            .map(|c| random_complex(e.num_samples(), 100 + 2 * c as u64))
+            .collect();
+
+        let ex = e.apply(&x);
+        let lhs: Complex<f64> = ex.iter().zip(y.iter()).map(|(a, b)| dot(a, b)).sum();
+        let rhs = dot(&x, &e.apply_adjoint(&y));
+        assert!((lhs - rhs).norm() <= 1e-10 * lhs.norm());
+    }
+
+    /// linear encoding fields `x` and `y`
+    fn linear_fields(n: usize) -> Vec<EncodingField> {
+        let mut out = vec![EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| {
+            pos.x().unwrap()
+        }))];
+        if n > 1 {
+            out.push(EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| {
+                pos.y().unwrap()
+            })));
+        }
+        out
+    }
+
+    #[test]
+    fn cartesian_dft() {
+        // k = -2, -1, 0, 1 and x = -0.5, -0.25, 0, 0.25, so the entry of sample i and voxel j
+        // is exp(-i pi (i - 2)(j - 2) / 2)
+        let e = EncodingMatrix::new(
+            KSpace::cartesian(SpatialDims::OneD(1.0), SpatialDims::OneD(4)),
+            linear_fields(1),
+            vec![RFSensitivity::from_tuples(vec![(1.0, 0.0); 4])],
+            SpatialDims::OneD(1.0),
+            SpatialDims::OneD(4),
+        );
+        let img: Vec<Complex<f64>> = [1.0, 2.0, 0.0, -1.0]
+            .iter()
+            .map(|&x| Complex::new(x, 0.0))
+            .collect();
+        let expected = [
+            Complex::new(0.0, 0.0),
+            Complex::new(-1.0, -3.0),
+            Complex::new(2.0, 0.0),
+            Complex::new(-1.0, 3.0),
+        ];
+        let out = e.apply(&img);
+        assert_eq!(out.len(), 1);
+        for (a, b) in out[0].iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn single_voxel() {
+        // a single voxel at (x, y) gives s_c exp(-2 pi i (kx x + ky y)) in coil c
+        let fov = SpatialDims::TwoD(0.2, 0.25);
+        let dims = SpatialDims::TwoD(4, 5);
+        let k = KSpace::cartesian(fov.clone(), dims.clone());
+        let samples = k.samples();
+        let (r, value) = (13, Complex::new(0.5, -2.0));
+        let sens = [Complex::new(1.0, 0.0), Complex::new(0.3, -0.7)];
+        let rf = sens
+            .iter()
+            .map(|&s| {
+                let mut map = vec![Complex::new(9.0, 9.0); dims.product()];
+                map[r] = s;
+                RFSensitivity::new(map)
+            }).collect();
+        let e = EncodingMatrix::new(k, linear_fields(2), rf, fov, dims);
+
+        // voxel 13 is column 1 of row 3
+        let (x, y) = (0.2 * (-0.5 + 1.0 / 4.0), 0.25 * (-0.5 + 3.0 / 5.0));
+        let mut img = vec![Complex::new(0.0, 0.0); e.num_voxels()];
+        img[r] = value;
+        let out = e.apply(&img);
+        for (c, s) in sens.iter().enumerate() {
+            for (m, k) in samples.iter().enumerate() {
+                let phase = -2.0 * PI * (k[0] * x + k[1] * y);
+                let expected = s * value * Complex::from_polar(1.0, phase);
+                assert!((out[c][m] - expected).norm() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn adjoint_cartesian() {
+        check_adjoint(KSpace::cartesian(
+            SpatialDims::TwoD(0.01, 0.01),
+            SpatialDims::TwoD(6, 5),
+        ));
+    }
+
+    #[test]
+    fn adjoint_projections() {
+        check_adjoint(KSpaceProjections::radial(0.01, 8, 5));
+    }
+
+    #[test]
+    fn adjoint_parameterized_projections() {
+        check_adjoint(KSpaceParameterizedProjections::radial(0.01, 5, 2, 8));
+    }
+
+    #[test]
+    fn adjoint_off_resonance() {
+        for &method in &[
+            OffResonanceMethod::Exact,
+            OffResonanceMethod::TimeSegmentation(4),
+            OffResonanceMethod::MultiFrequency(4),
+        ] {
+            check_adjoint_off_resonance(KSpaceProjections::radial(0.01, 8, 5), Some(method));
+        }
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Echo-planar imaging
+//!
+//! 2D EPI trajectories with the samples in acquisition order. Every echo (readout line) samples
+//! `nx` points in k-space, alternating in direction within a shot. The readout gradient is a
+//! trapezoid and the samples are equally spaced in time, such that ramp sampling results in
+//! denser samples at the edges of k-space. Echoes of opposite readout direction hit the same `kx`
+//! positions.
+
+use error::{check_grid, MriError, Result};
+use num::Integer;
+use std::ops::Range;
+use KSpace;
+use KSpaceThings;
+use SampleTiming;
+use SpatialDims;
+
+/// Options of an EPI trajectory
+#[derive(Debug, Clone)]
+pub struct EpiOptions {
+    /// field of view
+    fov: SpatialDims<f64>,
+    /// matrix size
+    dims: SpatialDims<usize>,
+    /// blipped phase encoding
+    blipped: bool,
+    /// fraction of the readout window on each ramp
+    ramp: f64,
+    /// fraction of the phase encoding lines covered
+    partial_fourier: f64,
+    /// acceleration factor
+    acceleration: usize,
+    /// number of shots
+    shots: usize,
+    /// dwell time
+    dwell: Option<f64>,
+    /// echo spacing
+    echo_spacing: Option<f64>,
+}
+
+impl EpiOptions {
+    /// Blipped, single shot EPI without acceleration, partial Fourier or ramp sampling on a 2D
+    /// grid with `dims` voxels covering `fov`. Readout is along x and phase encoding along y.
+    pub fn new(fov: SpatialDims<f64>, dims: SpatialDims<usize>) -> Self {
+        EpiOptions {
+            fov,
+            dims,
+            blipped: true,
+            ramp: 0.0,
+            partial_fourier: 1.0,
+            acceleration: 1,
+            shots: 1,
+            dwell: None,
+            echo_spacing: None,
+        }
+    }
+
+    /// Blipped phase encoding (default) or a constant phase encoding gradient during the
+    /// readout (non-blipped)
+    pub fn blipped(&mut self, blipped: bool) -> &mut Self {
+        self.blipped = blipped;
+        self
+    }
+
+    /// Sample on the ramps of the readout gradient, which take the fraction `ramp` in
+    /// `[0, 0.5)` of the readout window each
+    pub fn ramp_sampling(&mut self, ramp: f64) -> &mut Self {
+        self.ramp = ramp;
+        self
+    }
+
+    /// Acquire only the fraction `fraction` in `(0.5, 1]` of the phase encoding lines, omitting
+    /// the first ones
+    pub fn partial_fourier(&mut self, fraction: f64) -> &mut Self {
+        self.partial_fourier = fraction;
+        self
+    }
+
+    /// Acquire every `acceleration`th phase encoding line, always including the center line
+    pub fn acceleration(&mut self, acceleration: usize) -> &mut Self {
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Interleave the phase encoding lines over `shots` shots
+    pub fn shots(&mut self, shots: usize) -> &mut Self {
+        self.shots = shots;
+        self
+    }
+
+    /// Time the samples with the dwell time `dwell` (s), relative to the start of every shot
+    pub fn dwell(&mut self, dwell: f64) -> &mut Self {
+        self.dwell = Some(dwell);
+        self
+    }
+
+    /// Time between the starts of two echoes (s), at least `nx` times the dwell time (default)
+    pub fn echo_spacing(&mut self, echo_spacing: f64) -> &mut Self {
+        self.echo_spacing = Some(echo_spacing);
+        self
+    }
+
+    /// Check the options, returning `(nx, ny)`
+    fn check(&self) -> Result<(usize, usize)> {
+        check_grid(&self.fov, &self.dims)?;
+        let (nx, ny) = match self.dims {
+            SpatialDims::TwoD(nx, ny) => (nx, ny),
+            _ => {
+                return Err(MriError::InvalidParameter(
+                    "EPI trajectories are 2D".to_string(),
+                ))
+            }
+        };
+        if !(self.ramp >= 0.0 && self.ramp < 0.5) {
+            return Err(MriError::InvalidParameter(format!(
+                "ramp fraction {}",
+                self.ramp
+            )));
+        }
+        if !(self.partial_fourier > 0.5 && self.partial_fourier <= 1.0) {
+            return Err(MriError::InvalidParameter(format!(
+                "partial Fourier fraction {}",
+                self.partial_fourier
+            )));
+        }
+        if self.acceleration == 0 || self.shots == 0 {
+            return Err(MriError::InvalidParameter(
+                "acceleration and number of shots must be positive".to_string(),
+            ));
+        }
+        if let Some(dwell) = self.dwell {
+            if !(dwell > 0.0 && dwell.is_finite()) {
+                return Err(MriError::InvalidParameter(format!("dwell time {}", dwell)));
+            }
+            if let Some(es) = self.echo_spacing {
+                if !(es >= (nx as f64) * dwell && es.is_finite()) {
+                    return Err(MriError::InvalidParameter(format!("echo spacing {}", es)));
+                }
+            }
+        }
+        Ok((nx, ny))
+    }
+}
+
+/// A single echo of an EPI trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpiEcho {
+    /// shot
+    pub shot: usize,
+    /// echo within the shot
+    pub echo: usize,
+    /// phase encoding line on the full grid
+    pub line: usize,
+    /// readout in negative x direction (odd echoes)
+    pub reversed: bool,
+    /// indices of the samples in the trajectory
+    pub samples: Range<usize>,
+}
+
+/// EPI trajectory with its echoes
+#[derive(Debug, Clone)]
+pub struct Epi {
+    /// trajectory in acquisition order
+    pub kspace: KSpace,
+    /// echoes in acquisition order
+    pub echoes: Vec<EpiEcho>,
+}
+
+impl Epi {
+    /// Return whether every sample belongs to a reversed (odd) echo
+    pub fn reversed(&self) -> Vec<bool> {
+        self.echoes
+            .iter()
+            .flat_map(|e| e.samples.clone().map(move |_| e.reversed))
+            .collect()
+    }
+}
+
+impl KSpace {
+    /// Create an EPI trajectory
+    pub fn epi(options: &EpiOptions) -> Epi {
+        KSpace::try_epi(options).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create an EPI trajectory, failing on invalid options
+    pub fn try_epi(options: &EpiOptions) -> Result<Epi> {
+        let (nx, ny) = options.check()?;
+        let (dkx, dky) = match options.fov {
+            SpatialDims::TwoD(fx, fy) => (1.0 / fx, 1.0 / fy),
+            _ => unreachable!(),
+        };
+
+        // kx of the samples of a forward echo
+        let center = if nx.is_even() { -0.5 * dkx } else { 0.0 };
+        let kx: Vec<f64> = (0..nx)
+            .map(|i| {
+                let t = (i as f64 + 0.5) / (nx as f64);
+                center + (nx as f64) * dkx * (ramp_area(t, options.ramp) - 0.5)
+            }).collect();
+
+        // acquired phase encoding lines
+        let ny2 = if ny.is_even() { ny / 2 } else { (ny - 1) / 2 };
+        let first = ((1.0 - options.partial_fourier) * (ny as f64)).round() as usize;
+        let lines: Vec<usize> = (first.min(ny2)..ny)
+            .filter(|&l| (l as i64 - ny2 as i64).mod_floor(&(options.acceleration as i64)) == 0)
+            .collect();
+        let step = (options.acceleration * options.shots) as f64 * dky;
+
+        let dwell = options.dwell;
+        let echo_spacing = options
+            .echo_spacing
+            .or_else(|| dwell.map(|d| (nx as f64) * d));
+
+        let mut k = KSpace::new();
+        let mut times = vec![];
+        let mut echoes = vec![];
+        for shot in 0..options.shots {
+            for (echo, &line) in lines.iter().skip(shot).step_by(options.shots).enumerate() {
+                let reversed = echo.is_odd();
+                let ky = (line as f64 - ny2 as f64) * dky;
+                let start = k.num_samples();
+                for i in 0..nx {
+                    let s = if reversed { nx - 1 - i } else { i };
+                    let blip = if options.blipped {
+                        0.0
+                    } else {
+                        step * (i as f64 + 0.5) / (nx as f64)
+                    };
+                    let sample = vec![kx[s], ky + blip];
+                    k.try_add(sample)?;
+                    if let (Some(d), Some(es)) = (dwell, echo_spacing) {
+                        times.push((echo as f64) * es + (i as f64 + 0.5) * d);
+                    }
+                }
+                echoes.push(EpiEcho {
+                    shot,
+                    echo,
+                    line,
+                    reversed,
+                    samples: start..k.num_samples(),
+                });
+            }
+        }
+        if dwell.is_some() && echo_spacing.is_some() {
+            k.try_set_times(SampleTiming::PerSample(times))?;
+        }
+        Ok(Epi { kspace: k, echoes })
+    }
+}
+
+/// Normalized area under a trapezoidal gradient with ramps of the fraction `ramp` of the readout
+/// window at the fraction `t` of the window
+fn ramp_area(t: f64, ramp: f64) -> f64 {
+    if ramp <= 0.0 {
+        return t;
+    }
+    let total = 1.0 - ramp;
+    let area = if t < ramp {
+        t * t / (2.0 * ramp)
+    } else if t <= 1.0 - ramp {
+        ramp / 2.0 + (t - ramp)
+    } else {
+        let r = 1.0 - t;
+        total - r * r / (2.0 * ramp)
+    };
+    area / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cartesian_positions() {
+        let (nx, ny) = (8, 6);
+        let fov = SpatialDims::TwoD(0.2, 0.3);
+        let dims = SpatialDims::TwoD(nx, ny);
+        let dwell = 4e-6;
+        let epi = KSpace::epi(EpiOptions::new(fov.clone(), dims.clone()).dwell(dwell));
+        let cartesian = KSpace::cartesian(fov.clone(), dims.clone());
+        assert_eq!(epi.kspace.num_samples(), nx * ny);
+        assert_eq!(epi.echoes.len(), ny);
+
+        let reversed = epi.reversed();
+        assert_eq!(reversed.len(), nx * ny);
+        for (e, echo) in epi.echoes.iter().enumerate() {
+            assert_eq!((echo.shot, echo.echo, echo.line), (0, e, e));
+            assert_eq!(echo.reversed, e % 2 == 1);
+            assert_eq!(echo.samples, e * nx..(e + 1) * nx);
+            for (i, j) in echo.samples.clone().enumerate() {
+                assert_eq!(reversed[j], echo.reversed);
+                let s = if echo.reversed { nx - 1 - i } else { i };
+                let a = epi.kspace.sample_at(j);
+                let b = cartesian.sample_at(echo.line * nx + s);
+                assert!((a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9);
+            }
+        }
+
+        // samples are equally spaced in time with the default echo spacing
+        let times = epi.kspace.sample_times().unwrap();
+        for (j, t) in times.iter().enumerate() {
+            assert!((t - (j as f64 + 0.5) * dwell).abs() < 1e-15);
+        }
+        let epi = KSpace::epi(&EpiOptions::new(fov.clone(), dims.clone()));
+        assert_eq!(epi.kspace.sample_times(), None);
+    }
+
+    #[test]
+    fn echo_spacing_and_blips() {
+        let (nx, ny) = (8, 6);
+        let fov = SpatialDims::TwoD(0.2, 0.3);
+        let (dwell, es) = (4e-6, 1e-4);
+        let epi = KSpace::epi(
+            EpiOptions::new(fov.clone(), SpatialDims::TwoD(nx, ny))
+                .dwell(dwell)
+                .echo_spacing(es)
+                .blipped(false),
+        );
+        let times = epi.kspace.sample_times().unwrap();
+        let dky = 1.0 / 0.3;
+        for echo in &epi.echoes {
+            let start = echo.samples.start;
+            assert!((times[start] - (echo.echo as f64) * es - 0.5 * dwell).abs() < 1e-15);
+            // the phase encoding gradient moves ky by one line per echo
+            let first = epi.kspace.sample_at(start)[1];
+            let last = epi.kspace.sample_at(echo.samples.end - 1)[1];
+            let line = (echo.line as f64 - (ny / 2) as f64) * dky;
+            assert!((first - line - dky * 0.5 / (nx as f64)).abs() < 1e-9);
+            assert!((last - first - dky * (nx - 1) as f64 / (nx as f64)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn ramp_sampling() {
+        let nx = 16;
+        let epi = KSpace::epi(
+            EpiOptions::new(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(nx, 4))
+                .ramp_sampling(0.25),
+        );
+        let kx = |e: &EpiEcho| -> Vec<f64> {
+            e.samples.clone().map(|j| epi.kspace.sample_at(j)[0]).collect()
+        };
+        let forward = kx(&epi.echoes[0]);
+        let mut backward = kx(&epi.echoes[1]);
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert!(forward.windows(2).all(|w| w[1] > w[0]));
+        // denser samples on the ramps
+        let edge = forward[1] - forward[0];
+        let center = forward[nx / 2] - forward[nx / 2 - 1];
+        assert!(edge < center);
+    }
+
+    #[test]
+    fn lines_kept() {
+        let (nx, ny) = (4, 16);
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(nx, ny);
+        let lines = |options: &EpiOptions| -> Vec<usize> {
+            let epi = KSpace::epi(options);
+            assert_eq!(epi.kspace.num_samples(), epi.echoes.len() * nx);
+            epi.echoes.iter().map(|e| e.line).collect()
+        };
+
+        assert_eq!(lines(&EpiOptions::new(fov.clone(), dims.clone())), (0..16).collect::<Vec<_>>());
+        assert_eq!(
+            lines(EpiOptions::new(fov.clone(), dims.clone()).partial_fourier(0.75)),
+            (4..16).collect::<Vec<_>>()
+        );
+        // the center line is always acquired
+        assert_eq!(
+            lines(EpiOptions::new(fov.clone(), dims.clone()).acceleration(3)),
+            vec![2, 5, 8, 11, 14]
+        );
+        assert_eq!(
+            lines(EpiOptions::new(fov.clone(), dims.clone()).partial_fourier(0.75).acceleration(2)),
+            vec![4, 6, 8, 10, 12, 14]
+        );
+        assert_eq!(
+            lines(EpiOptions::new(fov.clone(), dims.clone()).partial_fourier(0.51)),
+            (8..16).collect::<Vec<_>>()
+        );
+
+        // shots interleave the lines, restarting the echoes and the timing
+        let dwell = 1e-5;
+        let epi = KSpace::epi(EpiOptions::new(fov.clone(), dims.clone()).shots(2).dwell(dwell));
+        let times = epi.kspace.sample_times().unwrap();
+        assert_eq!(epi.echoes.len(), ny);
+        for (i, echo) in epi.echoes.iter().enumerate() {
+            assert_eq!(echo.shot, i / 8);
+            assert_eq!(echo.echo, i % 8);
+            assert_eq!(echo.line, 2 * (i % 8) + echo.shot);
+            assert_eq!(echo.reversed, echo.echo % 2 == 1);
+            let t = times[echo.samples.start];
+            assert!((t - ((echo.echo * nx) as f64 + 0.5) * dwell).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn invalid_options() {
+        let options = || EpiOptions::new(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(8, 8));
+        let one_d = EpiOptions::new(SpatialDims::OneD(0.2), SpatialDims::OneD(8));
+        assert!(KSpace::try_epi(&one_d).is_err());
+        assert!(KSpace::try_epi(options().ramp_sampling(0.5)).is_err());
+        assert!(KSpace::try_epi(options().partial_fourier(0.5)).is_err());
+        assert!(KSpace::try_epi(options().acceleration(0)).is_err());
+        assert!(KSpace::try_epi(options().shots(0)).is_err());
+        assert!(KSpace::try_epi(options().dwell(1e-5).echo_spacing(7e-5)).is_err());
+        assert!(KSpace::try_epi(options().dwell(1e-5).echo_spacing(8e-5)).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Errors
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+use SpatialDims;
+
+/// Errors of this crate
+#[derive(Debug, Clone, PartialEq)]
+pub enum MriError {
+    /// Number of dimensions, channels or elements does not match
+    DimensionMismatch {
+        /// expected number
+        expected: usize,
+        /// actual number
+        found: usize,
+    },
+    /// A projection without any samples
+    EmptyProjection,
+    /// A field of view which is not positive and finite
+    InvalidFov(f64),
+    /// An index beyond the number of elements
+    IndexOutOfRange {
+        /// requested index
+        index: usize,
+        /// number of elements
+        len: usize,
+    },
+    /// Any other invalid parameter
+    InvalidParameter(String),
+    /// Reading or writing a file failed
+    Io(String),
+    /// A file is malformed or uses an unsupported feature
+    InvalidFile(String),
+}
+
+/// Result type of this crate
+pub type Result<T> = result::Result<T, MriError>;
+
+impl fmt::Display for MriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MriError::DimensionMismatch { expected, found } => {
+                write!(f, "dimension mismatch: expected {}, found {}", expected, found)
+            }
+            MriError::EmptyProjection => write!(f, "projection without samples"),
+            MriError::InvalidFov(fov) => write!(f, "invalid field of view: {}", fov),
+            MriError::IndexOutOfRange { index, len } => {
+                write!(f, "index {} out of range for length {}", index, len)
+            }
+            MriError::InvalidParameter(ref msg) => write!(f, "invalid parameter: {}", msg),
+            MriError::Io(ref msg) => write!(f, "I/O error: {}", msg),
+            MriError::InvalidFile(ref msg) => write!(f, "invalid file: {}", msg),
+        }
+    }
+}
+
+impl error::Error for MriError {}
+
+impl From<io::Error> for MriError {
+    fn from(e: io::Error) -> Self {
+        MriError::Io(e.to_string())
+    }
+}
+
+#[cfg(feature = "hdf5")]
+impl From<::hdf5::Error> for MriError {
+    fn from(e: ::hdf5::Error) -> Self {
+        MriError::Io(e.to_string())
+    }
+}
+
+/// Check that a field of view is positive and finite
+pub(crate) fn check_fov(fov: f64) -> Result<()> {
+    if fov > 0.0 && fov.is_finite() {
+        Ok(())
+    } else {
+        Err(MriError::InvalidFov(fov))
+    }
+}
+
+/// Check that two numbers of dimensions agree
+pub(crate) fn check_dims(expected: usize, found: usize) -> Result<()> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(MriError::DimensionMismatch { expected, found })
+    }
+}
+
+/// Check that an index is in range
+pub(crate) fn check_index(index: usize, len: usize) -> Result<()> {
+    if index < len {
+        Ok(())
+    } else {
+        Err(MriError::IndexOutOfRange { index, len })
+    }
+}
+
+/// Check that a field of view and a grid have the same number of dimensions, that the field of
+/// view is valid and that the grid is not empty
+pub(crate) fn check_grid(fov: &SpatialDims<f64>, dims: &SpatialDims<usize>) -> Result<()> {
+    check_dims(dims.len(), fov.len())?;
+    for f in fov.clone() {
+        check_fov(f)?;
+    }
+    if dims.clone().into_iter().any(|n| n == 0) {
+        return Err(MriError::InvalidParameter(
+            "number of grid points must be positive".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks() {
+        assert_eq!(check_fov(0.2), Ok(()));
+        for &fov in &[0.0, -0.1, f64::INFINITY] {
+            assert_eq!(check_fov(fov), Err(MriError::InvalidFov(fov)));
+        }
+        assert!(matches!(check_fov(f64::NAN), Err(MriError::InvalidFov(_))));
+        assert_eq!(
+            check_dims(2, 3),
+            Err(MriError::DimensionMismatch {
+                expected: 2,
+                found: 3,
+            })
+        );
+        assert_eq!(
+            check_index(4, 4),
+            Err(MriError::IndexOutOfRange { index: 4, len: 4 })
+        );
+        assert_eq!(check_index(3, 4), Ok(()));
+    }
+
+    #[test]
+    fn grid_checks() {
+        let fov = SpatialDims::TwoD(0.2, 0.3);
+        assert_eq!(check_grid(&fov, &SpatialDims::TwoD(4, 5)), Ok(()));
+        assert!(matches!(
+            check_grid(&fov, &SpatialDims::TwoD(4, 0)),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert_eq!(
+            check_grid(&fov, &SpatialDims::ThreeD(4, 5, 6)),
+            Err(MriError::DimensionMismatch {
+                expected: 3,
+                found: 2,
+            })
+        );
+        assert_eq!(
+            check_grid(&SpatialDims::TwoD(0.2, -1.0), &SpatialDims::TwoD(4, 5)),
+            Err(MriError::InvalidFov(-1.0))
+        );
+    }
+
+    #[test]
+    fn display() {
+        let e = MriError::DimensionMismatch {
+            expected: 2,
+            found: 3,
+        };
+        assert_eq!(e.to_string(), "dimension mismatch: expected 2, found 3");
+        assert_eq!(
+            MriError::IndexOutOfRange { index: 5, len: 2 }.to_string(),
+            "index 5 out of range for length 2"
+        );
+        let io: MriError = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(io, MriError::Io("missing".to_string()));
+    }
+}
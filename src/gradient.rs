@@ -0,0 +1,461 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Gradient waveforms
+//!
+//! k-space positions are given in 1/m, gradients in T/m, slew rates in T/m/s, the gyromagnetic
+//! ratio in Hz/T and times in s, such that `k(t) = gamma * integral(g(t) dt)`.
+//!
+//! The time-optimal design follows Lustig et al., "A fast method for designing time-optimal
+//! gradient waveforms for arbitrary k-space trajectories", IEEE TMI 27(6), 2008.
+
+use error::{check_dims, MriError, Result};
+use kspace::KProjection;
+use KSample;
+use KSpace;
+use KSpaceProjections;
+use KSpaceThings;
+
+/// Gyromagnetic ratio of 1H in Hz/T
+pub const GAMMA_1H: f64 = 42.577_478_518e6;
+
+/// Relative tolerance when checking waveforms against the hardware limits
+const LIMIT_TOLERANCE: f64 = 1e-6;
+
+/// Fraction of the hardware limits used by the time-optimal design, which leaves some headroom
+/// for the discretization on the gradient raster
+const DESIGN_MARGIN: f64 = 0.99;
+
+/// A gradient waveform on a regular raster
+#[derive(Debug, Clone)]
+pub struct GradientWaveform {
+    /// gradient (T/m) of every channel during each raster interval (`[time][channel]`)
+    pub gradient: Vec<Vec<f64>>,
+    /// raster time (s)
+    pub dwell: f64,
+}
+
+impl GradientWaveform {
+    /// Return the number of raster intervals
+    pub fn len(&self) -> usize {
+        self.gradient.len()
+    }
+
+    /// true if the waveform has no raster intervals
+    pub fn is_empty(&self) -> bool {
+        self.gradient.is_empty()
+    }
+
+    /// Return the duration of the waveform
+    pub fn duration(&self) -> f64 {
+        self.dwell * (self.gradient.len() as f64)
+    }
+
+    /// Return the slew rate between consecutive raster intervals (including ramping up from and
+    /// down to zero at both ends)
+    pub fn slew(&self) -> Vec<Vec<f64>> {
+        let num_channels = self.gradient.first().map_or(0, |g| g.len());
+        let zero = vec![0.0; num_channels];
+        let mut out = Vec::with_capacity(self.gradient.len() + 1);
+        let mut prev = &zero;
+        for g in self.gradient.iter().chain(Some(&zero)) {
+            out.push(
+                g.iter()
+                    .zip(prev.iter())
+                    .map(|(a, b)| (a - b) / self.dwell)
+                    .collect(),
+            );
+            prev = g;
+        }
+        out
+    }
+
+    /// Return the maximum gradient amplitude
+    pub fn max_amplitude(&self) -> f64 {
+        self.gradient.iter().map(|g| norm(g)).fold(0.0, f64::max)
+    }
+
+    /// Return the maximum slew rate
+    pub fn max_slew(&self) -> f64 {
+        self.slew().iter().map(|s| norm(s)).fold(0.0, f64::max)
+    }
+
+    /// Integrate the waveform, starting at `k0`.
+    ///
+    /// Returns the k-space position at the beginning and the end of every raster interval.
+    pub fn integrate(&self, gamma: f64, k0: &[f64]) -> KProjection {
+        let mut out = Vec::with_capacity(self.gradient.len() + 1);
+        let mut k = k0.to_vec();
+        out.push(k.clone());
+        for g in &self.gradient {
+            assert!(g.len() == k.len());
+            for (kc, gc) in k.iter_mut().zip(g.iter()) {
+                *kc += gamma * gc * self.dwell;
+            }
+            out.push(k.clone());
+        }
+        out
+    }
+}
+
+/// Hardware limits of a gradient system
+#[derive(Debug, Clone)]
+pub struct GradientSystem {
+    /// maximum gradient amplitude (T/m)
+    max_amplitude: f64,
+    /// maximum slew rate (T/m/s)
+    max_slew: f64,
+    /// gyromagnetic ratio (Hz/T)
+    gamma: f64,
+    /// gradient raster time / dwell time (s)
+    dwell: f64,
+}
+
+impl GradientSystem {
+    /// Constructor
+    pub fn new(max_amplitude: f64, max_slew: f64, gamma: f64, dwell: f64) -> Self {
+        GradientSystem::try_new(max_amplitude, max_slew, gamma, dwell)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Constructor, failing unless all parameters are positive and finite
+    pub fn try_new(max_amplitude: f64, max_slew: f64, gamma: f64, dwell: f64) -> Result<Self> {
+        let params = [
+            ("maximum amplitude", max_amplitude),
+            ("maximum slew rate", max_slew),
+            ("gyromagnetic ratio", gamma),
+            ("dwell time", dwell),
+        ];
+        for &(name, value) in &params {
+            if !(value > 0.0 && value.is_finite()) {
+                return Err(MriError::InvalidParameter(format!("{} {}", name, value)));
+            }
+        }
+        Ok(GradientSystem {
+            max_amplitude,
+            max_slew,
+            gamma,
+            dwell,
+        })
+    }
+
+    /// Return the maximum gradient amplitude
+    pub fn max_amplitude(&self) -> f64 {
+        self.max_amplitude
+    }
+
+    /// Return the maximum slew rate
+    pub fn max_slew(&self) -> f64 {
+        self.max_slew
+    }
+
+    /// Return the gyromagnetic ratio
+    pub fn gamma(&self) -> f64 {
+        self.gamma
+    }
+
+    /// Return the dwell time
+    pub fn dwell(&self) -> f64 {
+        self.dwell
+    }
+
+    /// Check whether a waveform respects the amplitude and slew rate limits
+    pub fn is_playable(&self, waveform: &GradientWaveform) -> bool {
+        waveform.max_amplitude() <= self.max_amplitude * (1.0 + LIMIT_TOLERANCE)
+            && waveform.max_slew() <= self.max_slew * (1.0 + LIMIT_TOLERANCE)
+    }
+
+    /// Gradient waveform needed to acquire consecutive samples of `path` one dwell time apart.
+    pub fn waveform(&self, path: &[KSample]) -> GradientWaveform {
+        let gradient = path
+            .windows(2)
+            .map(|w| {
+                w[1].iter()
+                    .zip(w[0].iter())
+                    .map(|(b, a)| (b - a) / (self.gamma * self.dwell))
+                    .collect()
+            }).collect();
+        GradientWaveform {
+            gradient,
+            dwell: self.dwell,
+        }
+    }
+
+    /// Check whether a trajectory can be acquired with one sample per dwell time.
+    ///
+    /// The samples are traversed in the order of `KSpaceThings::samples`.
+    pub fn is_playable_kspace(&self, k: &KSpace) -> bool {
+        self.is_playable(&self.waveform(&k.samples()))
+    }
+
+    /// Check whether every projection can be acquired with one sample per dwell time.
+    pub fn is_playable_projections(&self, k: &KSpaceProjections) -> bool {
+        (0..k.num_units()).all(|i| self.is_playable(&self.waveform(&k.sample_at(i))))
+    }
+
+    /// Time-optimal gradient waveform traversing `path` in the given order.
+    ///
+    /// The waveform starts at `path[0]` and ends at the last point of the path, both with zero
+    /// gradient amplitude. The gradient also passes through zero at every corner of the path
+    /// where the direction changes by more than 90 degrees (e.g. at the end of each line of a
+    /// Cartesian raster).
+    pub fn time_optimal(&self, path: &[KSample]) -> GradientWaveform {
+        let mut points: Vec<KSample> = Vec::with_capacity(path.len());
+        for p in path {
+            if points.last().is_none_or(|l| distance(l, p) > 0.0) {
+                points.push(p.clone());
+            }
+        }
+
+        let mut gradient = vec![];
+        let mut start = 0;
+        for i in 1..points.len() {
+            let corner = i + 1 < points.len()
+                && (0..points[i].len())
+                    .map(|c| (points[i][c] - points[i - 1][c]) * (points[i + 1][c] - points[i][c]))
+                    .sum::<f64>()
+                    < 0.0;
+            if corner || i + 1 == points.len() {
+                gradient.extend(self.time_optimal_segment(&points[start..=i]));
+                start = i;
+            }
+        }
+        GradientWaveform {
+            gradient,
+            dwell: self.dwell,
+        }
+    }
+
+    /// Time-optimal gradient waveform of a path without repeated points
+    fn time_optimal_segment(&self, path: &[KSample]) -> Vec<Vec<f64>> {
+        let num_channels = path[0].len();
+        let curve = densify(path, 0.25 * self.gamma * self.max_amplitude * self.dwell);
+
+        // arc length and curvature
+        let n = curve.len();
+        let ds: Vec<f64> = curve.windows(2).map(|w| distance(&w[0], &w[1])).collect();
+        let mut kappa = vec![0.0; n];
+        for i in 1..n - 1 {
+            let (h0, h1) = (ds[i - 1], ds[i]);
+            let curv: Vec<f64> = (0..num_channels)
+                .map(|c| {
+                    2.0 * (h0 * curve[i + 1][c] - (h0 + h1) * curve[i][c] + h1 * curve[i - 1][c])
+                        / (h0 * h1 * (h0 + h1))
+                }).collect();
+            kappa[i] = norm(&curv);
+        }
+
+        // velocity limits (in k-space units per second)
+        let vmax_g = DESIGN_MARGIN * self.gamma * self.max_amplitude;
+        let amax = DESIGN_MARGIN * self.gamma * self.max_slew;
+        let vmax: Vec<f64> = kappa
+            .iter()
+            .map(|&k| {
+                if k > 0.0 {
+                    vmax_g.min((amax / k).sqrt())
+                } else {
+                    vmax_g
+                }
+            }).collect();
+        let accel = |v: f64, k: f64| (amax * amax - (v * v * k).powi(2)).max(0.0).sqrt();
+
+        // forward and backward pass
+        let mut forward = vec![0.0; n];
+        for i in 0..n - 1 {
+            let v = forward[i];
+            forward[i + 1] = vmax[i + 1].min((v * v + 2.0 * accel(v, kappa[i]) * ds[i]).sqrt());
+        }
+        let mut backward = vec![0.0; n];
+        for i in (1..n).rev() {
+            let v = backward[i];
+            backward[i - 1] = vmax[i - 1].min((v * v + 2.0 * accel(v, kappa[i]) * ds[i - 1]).sqrt());
+        }
+        let velocity: Vec<f64> = forward
+            .iter()
+            .zip(backward.iter())
+            .map(|(a, b)| a.min(*b))
+            .collect();
+
+        // time at each point along the curve
+        let mut time = Vec::with_capacity(n);
+        time.push(0.0);
+        for i in 0..n - 1 {
+            let v = 0.5 * (velocity[i] + velocity[i + 1]);
+            let prev = time[i];
+            time.push(prev + if v > 0.0 { ds[i] / v } else { 0.0 });
+        }
+
+        // resample on the gradient raster
+        let total = time[n - 1];
+        let steps = (total / self.dwell).ceil().max(1.0) as usize;
+        let mut j = 0;
+        let mut ks: Vec<KSample> = Vec::with_capacity(steps + 1);
+        for s in 0..=steps {
+            let t = (total * (s as f64) / (steps as f64)).min(total);
+            while j < n - 2 && time[j + 1] < t {
+                j += 1;
+            }
+            // constant acceleration within each segment
+            let dt = time[j + 1] - time[j];
+            let w = if dt > 0.0 {
+                let tau = (t - time[j]).max(0.0).min(dt);
+                let a = (velocity[j + 1] - velocity[j]) / dt;
+                ((velocity[j] * tau + 0.5 * a * tau * tau) / ds[j]).min(1.0)
+            } else {
+                1.0
+            };
+            ks.push(
+                curve[j]
+                    .iter()
+                    .zip(curve[j + 1].iter())
+                    .map(|(a, b)| a + w * (b - a))
+                    .collect(),
+            );
+        }
+        ks.windows(2)
+            .map(|w| {
+                w[1].iter()
+                    .zip(w[0].iter())
+                    .map(|(b, a)| (b - a) / (self.gamma * self.dwell))
+                    .collect()
+            }).collect()
+    }
+
+    /// Time-optimal gradient waveforms for every projection
+    pub fn time_optimal_projections(&self, k: &KSpaceProjections) -> Vec<GradientWaveform> {
+        (0..k.num_units())
+            .map(|i| self.time_optimal(&k.sample_at(i)))
+            .collect()
+    }
+
+    /// Integrate a gradient waveform into a trajectory, starting at `k0`
+    pub fn kspace(&self, waveform: &GradientWaveform, k0: &[f64]) -> Result<KSpace> {
+        check_channels(waveform, k0)?;
+        let mut out = KSpace::new();
+        for s in waveform.integrate(self.gamma, k0) {
+            out.try_add(s)?;
+        }
+        Ok(out)
+    }
+
+    /// Integrate gradient waveforms into a set of projections, where projection `i` starts at
+    /// `k0[i]`
+    pub fn projections(
+        &self,
+        waveforms: &[GradientWaveform],
+        k0: &[KSample],
+    ) -> Result<KSpaceProjections> {
+        check_dims(waveforms.len(), k0.len())?;
+        let mut out = KSpaceProjections::new();
+        for (w, k) in waveforms.iter().zip(k0.iter()) {
+            check_channels(w, k)?;
+            out.try_add(w.integrate(self.gamma, k))?;
+        }
+        Ok(out)
+    }
+}
+
+/// Check that a waveform has as many channels as its starting point
+fn check_channels(waveform: &GradientWaveform, k0: &[f64]) -> Result<()> {
+    for g in &waveform.gradient {
+        check_dims(k0.len(), g.len())?;
+    }
+    Ok(())
+}
+
+/// Euclidean norm
+fn norm(x: &[f64]) -> f64 {
+    x.iter().map(|a| a * a).sum::<f64>().sqrt()
+}
+
+/// Euclidean distance
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Subdivide a path with Catmull-Rom splines such that consecutive points are at most `max_ds`
+/// apart and every segment is split into at least 64 pieces.
+fn densify(points: &[KSample], max_ds: f64) -> Vec<KSample> {
+    let n = points.len();
+    let mut out = Vec::with_capacity(n);
+    for i in 0..n - 1 {
+        let p0 = &points[if i > 0 { i - 1 } else { i }];
+        let p1 = &points[i];
+        let p2 = &points[i + 1];
+        let p3 = &points[if i + 2 < n { i + 2 } else { i + 1 }];
+        let steps = (distance(p1, p2) / max_ds).ceil().max(64.0) as usize;
+        for s in 0..steps {
+            let t = (s as f64) / (steps as f64);
+            let (t2, t3) = (t * t, t * t * t);
+            out.push(
+                (0..p1.len())
+                    .map(|c| {
+                        0.5 * (2.0 * p1[c]
+                            + (p2[c] - p0[c]) * t
+                            + (2.0 * p0[c] - 5.0 * p1[c] + 4.0 * p2[c] - p3[c]) * t2
+                            + (3.0 * p1[c] - p0[c] - 3.0 * p2[c] + p3[c]) * t3)
+                    }).collect(),
+            );
+        }
+    }
+    out.push(points[n - 1].clone());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> GradientSystem {
+        GradientSystem::new(40e-3, 150.0, GAMMA_1H, 10e-6)
+    }
+
+    /// Check that the time-optimal waveform is playable and integrates to the path
+    fn check_time_optimal(path: &[KSample]) {
+        let sys = system();
+        let w = sys.time_optimal(path);
+        assert!(!w.is_empty());
+        assert!(sys.is_playable(&w));
+
+        let k = sys.kspace(&w, &path[0]).unwrap().samples();
+        let end = &k[k.len() - 1];
+        assert!(distance(end, &path[path.len() - 1]) < 1e-6 * norm(&path[path.len() - 1]).max(1.0));
+
+        // every point of the path is passed within one raster step
+        let step = sys.gamma() * sys.max_amplitude() * sys.dwell();
+        for p in path {
+            let d = k.iter().map(|q| distance(p, q)).fold(f64::INFINITY, f64::min);
+            assert!(d <= step);
+        }
+    }
+
+    #[test]
+    fn time_optimal_spiral() {
+        let k = KSpaceProjections::spiral(0.2, 2e-3, 8);
+        check_time_optimal(&k.sample_at(0));
+    }
+
+    #[test]
+    fn time_optimal_radial() {
+        let k = KSpaceProjections::radial(0.2, 64, 4);
+        for i in 0..k.num_units() {
+            check_time_optimal(&k.sample_at(i));
+        }
+    }
+
+    #[test]
+    fn integrate_mismatched_channels() {
+        let sys = system();
+        let w = sys.waveform(&[vec![0.0, 0.0], vec![1.0, 0.0]]);
+        assert!(sys.kspace(&w, &[0.0]).is_err());
+        assert!(sys.projections(&[w], &[]).is_err());
+    }
+}
@@ -0,0 +1,1047 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! ISMRMRD acquisitions
+//!
+//! Data model of the acquisitions of the ISMRM raw data format (Inati et al., "ISMRM raw data
+//! format: a proposed standard for MRI raw datasets", MRM 77(1), 2017) and conversion from and
+//! to trajectories and multi-coil data.
+//!
+//! Acquisition headers can be converted from and to their 340 byte little endian binary
+//! representation, which is shared by the HDF5 compound type and the streaming protocol.
+//! Reading and writing HDF5 files (the XML header and the acquisitions of a dataset) requires
+//! the `hdf5` feature and the HDF5 library.
+//!
+//! Trajectories are copied without scaling, so they have to be stored in 1/m to be used with
+//! the rest of this crate. Cartesian acquisitions (without trajectory) are placed on the grid of
+//! a `CartesianEncoding`, usually the encoded space of the XML header.
+
+use error::{check_dims, check_fov, MriError, Result};
+#[cfg(feature = "hdf5")]
+use hdf5::types::{VarLenArray, VarLenAscii, VarLenUnicode};
+#[cfg(feature = "hdf5")]
+use hdf5::H5Type;
+use kspace::{KProjection, KSample};
+use num::Complex;
+use std::ops::Range;
+#[cfg(feature = "hdf5")]
+use std::path::Path;
+use KSpace;
+use KSpaceProjections;
+use KSpaceThings;
+use SampleTiming;
+
+/// Size of the binary acquisition header
+pub const ACQUISITION_HEADER_SIZE: usize = 340;
+/// Version of the ISMRMRD format
+pub const ISMRMRD_VERSION: u16 = 1;
+/// Default name of the group of a dataset in an HDF5 file
+pub const DEFAULT_DATASET: &str = "dataset";
+
+/// Flag of noise measurements
+pub const ACQ_IS_NOISE_MEASUREMENT: u64 = 19;
+/// Flag of parallel imaging calibration lines
+pub const ACQ_IS_PARALLEL_CALIBRATION: u64 = 20;
+/// Flag of the last acquisition of a measurement
+pub const ACQ_LAST_IN_MEASUREMENT: u64 = 25;
+
+/// Counters of an acquisition
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "hdf5", derive(H5Type))]
+#[repr(C)]
+pub struct EncodingCounters {
+    /// phase encoding line
+    pub kspace_encode_step_1: u16,
+    /// partition encoding
+    pub kspace_encode_step_2: u16,
+    /// signal average
+    pub average: u16,
+    /// slice
+    pub slice: u16,
+    /// echo
+    pub contrast: u16,
+    /// cardiac phase
+    pub phase: u16,
+    /// repetition
+    pub repetition: u16,
+    /// set
+    pub set: u16,
+    /// segment
+    pub segment: u16,
+    /// user defined counters
+    pub user: [u16; 8],
+}
+
+/// Header of an acquisition
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "hdf5", derive(H5Type))]
+#[repr(C)]
+pub struct AcquisitionHeader {
+    /// format version
+    pub version: u16,
+    /// bit field of flags
+    pub flags: u64,
+    /// unique measurement id
+    pub measurement_uid: u32,
+    /// counter of the acquisitions
+    pub scan_counter: u32,
+    /// time stamp
+    pub acquisition_time_stamp: u32,
+    /// physiology time stamps
+    pub physiology_time_stamp: [u32; 3],
+    /// number of samples
+    pub number_of_samples: u16,
+    /// number of available channels
+    pub available_channels: u16,
+    /// number of channels in this acquisition
+    pub active_channels: u16,
+    /// bit mask of the active channels
+    pub channel_mask: [u64; 16],
+    /// samples to discard at the beginning
+    pub discard_pre: u16,
+    /// samples to discard at the end
+    pub discard_post: u16,
+    /// sample at the center of k-space
+    pub center_sample: u16,
+    /// encoding space of this acquisition
+    pub encoding_space_ref: u16,
+    /// number of trajectory dimensions, zero for Cartesian acquisitions
+    pub trajectory_dimensions: u16,
+    /// dwell time (us)
+    pub sample_time_us: f32,
+    /// position of the slice (mm)
+    pub position: [f32; 3],
+    /// readout direction
+    pub read_dir: [f32; 3],
+    /// phase encoding direction
+    pub phase_dir: [f32; 3],
+    /// slice direction
+    pub slice_dir: [f32; 3],
+    /// table position (mm)
+    pub patient_table_position: [f32; 3],
+    /// encoding counters
+    pub idx: EncodingCounters,
+    /// user defined integers
+    pub user_int: [i32; 8],
+    /// user defined floats
+    pub user_float: [f32; 8],
+}
+
+impl AcquisitionHeader {
+    /// true if flag number `flag` (starting at 1) is set
+    pub fn is_flag_set(&self, flag: u64) -> bool {
+        flag > 0 && flag <= 64 && self.flags & (1 << (flag - 1)) != 0
+    }
+
+    /// Set flag number `flag` (starting at 1)
+    pub fn set_flag(&mut self, flag: u64) -> &mut Self {
+        assert!(flag > 0 && flag <= 64);
+        self.flags |= 1 << (flag - 1);
+        self
+    }
+
+    /// Parse the binary little endian representation
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < ACQUISITION_HEADER_SIZE {
+            return Err(MriError::InvalidFile(format!(
+                "acquisition header needs {} bytes, found {}",
+                ACQUISITION_HEADER_SIZE,
+                bytes.len()
+            )));
+        }
+        let mut r = ByteReader { bytes, pos: 0 };
+        let mut h = AcquisitionHeader {
+            version: r.u16(),
+            flags: r.u64(),
+            measurement_uid: r.u32(),
+            scan_counter: r.u32(),
+            acquisition_time_stamp: r.u32(),
+            ..Default::default()
+        };
+        for t in &mut h.physiology_time_stamp {
+            *t = r.u32();
+        }
+        h.number_of_samples = r.u16();
+        h.available_channels = r.u16();
+        h.active_channels = r.u16();
+        for m in &mut h.channel_mask {
+            *m = r.u64();
+        }
+        h.discard_pre = r.u16();
+        h.discard_post = r.u16();
+        h.center_sample = r.u16();
+        h.encoding_space_ref = r.u16();
+        h.trajectory_dimensions = r.u16();
+        h.sample_time_us = r.f32();
+        for v in [
+            &mut h.position,
+            &mut h.read_dir,
+            &mut h.phase_dir,
+            &mut h.slice_dir,
+            &mut h.patient_table_position,
+        ] {
+            for x in v.iter_mut() {
+                *x = r.f32();
+            }
+        }
+        h.idx.kspace_encode_step_1 = r.u16();
+        h.idx.kspace_encode_step_2 = r.u16();
+        h.idx.average = r.u16();
+        h.idx.slice = r.u16();
+        h.idx.contrast = r.u16();
+        h.idx.phase = r.u16();
+        h.idx.repetition = r.u16();
+        h.idx.set = r.u16();
+        h.idx.segment = r.u16();
+        for u in &mut h.idx.user {
+            *u = r.u16();
+        }
+        for u in &mut h.user_int {
+            *u = r.u32() as i32;
+        }
+        for u in &mut h.user_float {
+            *u = r.f32();
+        }
+        Ok(h)
+    }
+
+    /// Binary little endian representation
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(ACQUISITION_HEADER_SIZE);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&self.measurement_uid.to_le_bytes());
+        out.extend_from_slice(&self.scan_counter.to_le_bytes());
+        out.extend_from_slice(&self.acquisition_time_stamp.to_le_bytes());
+        for t in &self.physiology_time_stamp {
+            out.extend_from_slice(&t.to_le_bytes());
+        }
+        out.extend_from_slice(&self.number_of_samples.to_le_bytes());
+        out.extend_from_slice(&self.available_channels.to_le_bytes());
+        out.extend_from_slice(&self.active_channels.to_le_bytes());
+        for m in &self.channel_mask {
+            out.extend_from_slice(&m.to_le_bytes());
+        }
+        for v in &[
+            self.discard_pre,
+            self.discard_post,
+            self.center_sample,
+            self.encoding_space_ref,
+            self.trajectory_dimensions,
+        ] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&self.sample_time_us.to_le_bytes());
+        for v in &[
+            self.position,
+            self.read_dir,
+            self.phase_dir,
+            self.slice_dir,
+            self.patient_table_position,
+        ] {
+            for x in v {
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        let idx = &self.idx;
+        for v in &[
+            idx.kspace_encode_step_1,
+            idx.kspace_encode_step_2,
+            idx.average,
+            idx.slice,
+            idx.contrast,
+            idx.phase,
+            idx.repetition,
+            idx.set,
+            idx.segment,
+        ] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        for u in &idx.user {
+            out.extend_from_slice(&u.to_le_bytes());
+        }
+        for u in &self.user_int {
+            out.extend_from_slice(&u.to_le_bytes());
+        }
+        for u in &self.user_float {
+            out.extend_from_slice(&u.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// A single acquisition (readout) with its trajectory and the data of all active channels
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acquisition {
+    /// header
+    pub head: AcquisitionHeader,
+    /// trajectory, `[sample][dimension]` flattened
+    pub traj: Vec<f32>,
+    /// data, `[channel][sample]` flattened
+    pub data: Vec<Complex<f32>>,
+}
+
+impl Acquisition {
+    /// Create an acquisition from its trajectory (`[sample][dimension]`) and data
+    /// (`[channel][sample]`)
+    pub fn new(traj: &[Vec<f64>], data: &[Vec<Complex<f64>>]) -> Result<Self> {
+        let num_samples = traj.len();
+        let dims = traj.first().map_or(0, |s| s.len());
+        for s in traj {
+            check_dims(dims, s.len())?;
+        }
+        for d in data {
+            check_dims(num_samples, d.len())?;
+        }
+        if num_samples > u16::MAX as usize || data.len() > 1024 {
+            return Err(MriError::InvalidParameter(
+                "too many samples or channels for an ISMRMRD acquisition".to_string(),
+            ));
+        }
+        let mut head = AcquisitionHeader {
+            version: ISMRMRD_VERSION,
+            number_of_samples: num_samples as u16,
+            available_channels: data.len() as u16,
+            active_channels: data.len() as u16,
+            trajectory_dimensions: dims as u16,
+            center_sample: (num_samples / 2) as u16,
+            ..Default::default()
+        };
+        for c in 0..data.len() {
+            head.channel_mask[c / 64] |= 1 << (c % 64);
+        }
+        Ok(Acquisition {
+            head,
+            traj: traj.iter().flat_map(|s| s.iter().map(|&x| x as f32)).collect(),
+            data: data
+                .iter()
+                .flat_map(|d| d.iter().map(|x| Complex::new(x.re as f32, x.im as f32)))
+                .collect(),
+        })
+    }
+
+    /// Number of samples
+    pub fn num_samples(&self) -> usize {
+        self.head.number_of_samples as usize
+    }
+
+    /// Number of active channels
+    pub fn num_channels(&self) -> usize {
+        self.head.active_channels as usize
+    }
+
+    /// Trajectory as one k-space sample per readout sample, without the discarded samples.
+    ///
+    /// Acquisitions without trajectory are Cartesian readouts, whose samples are placed
+    /// according to `encoding`.
+    pub fn trajectory(&self, encoding: Option<&CartesianEncoding>) -> Result<KProjection> {
+        let kept = self.kept()?;
+        let dims = self.head.trajectory_dimensions as usize;
+        if dims == 0 {
+            let encoding = encoding.ok_or_else(|| {
+                MriError::InvalidParameter(
+                    "Cartesian acquisitions need the encoded field of view and matrix".to_string(),
+                )
+            })?;
+            return Ok(kept.map(|s| encoding.sample(&self.head, s)).collect());
+        }
+        check_dims(self.num_samples() * dims, self.traj.len())?;
+        Ok(self.traj[kept.start * dims..kept.end * dims]
+            .chunks(dims)
+            .map(|s| s.iter().map(|&x| f64::from(x)).collect())
+            .collect())
+    }
+
+    /// Data of every channel (`[channel][sample]`), without the discarded samples
+    pub fn channels(&self) -> Result<Vec<Vec<Complex<f64>>>> {
+        let ns = self.num_samples();
+        check_dims(ns * self.num_channels(), self.data.len())?;
+        let kept = self.kept()?;
+        if ns == 0 {
+            return Ok(vec![vec![]; self.num_channels()]);
+        }
+        Ok(self
+            .data
+            .chunks(ns)
+            .map(|c| {
+                c[kept.clone()]
+                    .iter()
+                    .map(|x| Complex::new(f64::from(x.re), f64::from(x.im)))
+                    .collect()
+            }).collect())
+    }
+
+    /// Acquisition times of the samples which are not discarded relative to the start of the
+    /// readout, if the acquisition has a dwell time
+    pub fn timing(&self) -> Option<SampleTiming> {
+        if self.head.sample_time_us > 0.0 {
+            let dwell = 1e-6 * f64::from(self.head.sample_time_us);
+            Some(SampleTiming::Dwell {
+                start: f64::from(self.head.discard_pre) * dwell,
+                dwell,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Indices of the samples which are not discarded
+    fn kept(&self) -> Result<Range<usize>> {
+        let pre = self.head.discard_pre as usize;
+        let post = self.head.discard_post as usize;
+        if pre + post > self.num_samples() {
+            return Err(MriError::InvalidFile(
+                "acquisition discards more samples than it contains".to_string(),
+            ));
+        }
+        Ok(pre..self.num_samples() - post)
+    }
+}
+
+/// Encoded space of Cartesian acquisitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartesianEncoding {
+    /// encoded field of view in x, y and z (m)
+    pub fov: [f64; 3],
+    /// encoded matrix size in x, y and z
+    pub matrix: [usize; 3],
+    /// phase (step 1) and partition (step 2) encoding step at the center of k-space
+    pub center: [usize; 2],
+}
+
+impl CartesianEncoding {
+    /// Encoded space of `matrix` samples over a field of view `fov` (m), with the center of
+    /// k-space at `matrix / 2` in the phase and partition encoding directions
+    pub fn new(fov: [f64; 3], matrix: [usize; 3]) -> Result<Self> {
+        for &f in &fov {
+            check_fov(f)?;
+        }
+        if matrix.contains(&0) {
+            return Err(MriError::InvalidParameter(
+                "encoded matrix size must be positive".to_string(),
+            ));
+        }
+        Ok(CartesianEncoding {
+            fov,
+            matrix,
+            center: [matrix[1] / 2, matrix[2] / 2],
+        })
+    }
+
+    /// Encoded space of the first encoding of an ISMRMRD XML header, with the centers of the
+    /// encoding limits if they are given
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let invalid = |what: &str| MriError::InvalidFile(format!("XML header: {}", what));
+        let encoding = xml_element(xml, "encoding").ok_or_else(|| invalid("no encoding"))?;
+        let space =
+            xml_element(encoding, "encodedSpace").ok_or_else(|| invalid("no encodedSpace"))?;
+        let xyz = |element: &str| -> Result<[f64; 3]> {
+            let e = xml_element(space, element).ok_or_else(|| invalid(element))?;
+            let mut out = [0.0; 3];
+            for (o, axis) in out.iter_mut().zip(&["x", "y", "z"]) {
+                *o = xml_element(e, axis)
+                    .and_then(|v| v.trim().parse().ok())
+                    .ok_or_else(|| invalid(element))?;
+            }
+            Ok(out)
+        };
+        let fov = xyz("fieldOfView_mm")?;
+        let matrix = xyz("matrixSize")?;
+        if matrix.iter().any(|&n| n < 0.0 || n.fract() != 0.0) {
+            return Err(invalid("matrixSize"));
+        }
+        let mut out = CartesianEncoding::new(
+            [1e-3 * fov[0], 1e-3 * fov[1], 1e-3 * fov[2]],
+            [matrix[0] as usize, matrix[1] as usize, matrix[2] as usize],
+        )?;
+        if let Some(limits) = xml_element(encoding, "encodingLimits") {
+            for (c, step) in out
+                .center
+                .iter_mut()
+                .zip(&["kspace_encoding_step_1", "kspace_encoding_step_2"])
+            {
+                let center = xml_element(limits, step).and_then(|l| xml_element(l, "center"));
+                if let Some(center) = center {
+                    *c = center.trim().parse().map_err(|_| invalid(step))?;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// k-space sample of readout sample `sample` of the acquisition with the header `head`, two
+    /// dimensional if the matrix has a single partition
+    fn sample(&self, head: &AcquisitionHeader, sample: usize) -> KSample {
+        let k = [
+            (sample as f64 - f64::from(head.center_sample)) / self.fov[0],
+            (f64::from(head.idx.kspace_encode_step_1) - self.center[0] as f64) / self.fov[1],
+            (f64::from(head.idx.kspace_encode_step_2) - self.center[1] as f64) / self.fov[2],
+        ];
+        if self.matrix[2] == 1 {
+            k[..2].to_vec()
+        } else {
+            k.to_vec()
+        }
+    }
+}
+
+/// Content of the first element `name` of an XML document
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut from = 0;
+    while let Some(i) = xml[from..].find(&open) {
+        let rest = &xml[from + i + open.len()..];
+        // skip elements whose name merely starts with `name`
+        if rest.starts_with('>') || rest.starts_with(char::is_whitespace) {
+            let start = rest.find('>')? + 1;
+            let end = rest.find(&close)?;
+            return rest.get(start..end);
+        }
+        from += i + open.len();
+    }
+    None
+}
+
+/// Convert acquisitions into one projection per acquisition and multi-coil data
+/// (`[coil][sample]`, in the order of `KSpaceThings::samples`). Noise measurements and discarded
+/// samples are skipped, Cartesian acquisitions need `encoding`.
+///
+/// Projections of acquisitions with a dwell time are timed relative to the start of their
+/// readout.
+pub fn to_projections(
+    acquisitions: &[Acquisition],
+    encoding: Option<&CartesianEncoding>,
+) -> Result<(KSpaceProjections, Vec<Vec<Complex<f64>>>)> {
+    let mut k = KSpaceProjections::new();
+    let mut data: Vec<Vec<Complex<f64>>> = vec![];
+    for acq in measurements(acquisitions) {
+        match acq.timing() {
+            Some(timing) => k.try_add_timed(acq.trajectory(encoding)?, timing)?,
+            None => k.try_add(acq.trajectory(encoding)?)?,
+        };
+        append(&mut data, acq.channels()?)?;
+    }
+    Ok((k, data))
+}
+
+/// Convert acquisitions into a trajectory of single samples and multi-coil data
+/// (`[coil][sample]`). Noise measurements and discarded samples are skipped, Cartesian
+/// acquisitions need `encoding`.
+///
+/// Samples are timed relative to the start of their readout if all acquisitions have a dwell
+/// time, it fails if only some of them have one.
+pub fn to_kspace(
+    acquisitions: &[Acquisition],
+    encoding: Option<&CartesianEncoding>,
+) -> Result<(KSpace, Vec<Vec<Complex<f64>>>)> {
+    let mut k = KSpace::new();
+    let mut data: Vec<Vec<Complex<f64>>> = vec![];
+    let mut times = vec![];
+    let mut timed = None;
+    for acq in measurements(acquisitions) {
+        let traj = acq.trajectory(encoding)?;
+        let timing = acq.timing();
+        if *timed.get_or_insert(timing.is_some()) != timing.is_some() {
+            return Err(MriError::InvalidFile(
+                "acquisitions with and without dwell time".to_string(),
+            ));
+        }
+        if let Some(timing) = timing {
+            times.extend(timing.times(traj.len())?);
+        }
+        for s in traj {
+            k.try_add(s)?;
+        }
+        append(&mut data, acq.channels()?)?;
+    }
+    if timed == Some(true) {
+        k.try_set_times(SampleTiming::PerSample(times))?;
+    }
+    Ok((k, data))
+}
+
+/// Create one acquisition per projection from a trajectory and multi-coil data (`[coil][sample]`,
+/// in the order of `KSpaceThings::samples`).
+///
+/// The dwell time of timed projections is stored in `sample_time_us`, which fails unless their
+/// samples are equally spaced in time. The start of the readouts is not stored.
+pub fn from_projections(
+    k: &KSpaceProjections,
+    data: &[Vec<Complex<f64>>],
+) -> Result<Vec<Acquisition>> {
+    let total = k.samples().len();
+    for d in data {
+        check_dims(total, d.len())?;
+    }
+    let times = k.sample_times();
+    let mut offset = 0;
+    (0..k.num_units())
+        .map(|i| {
+            let proj = k.try_sample_at(i)?;
+            let n = proj.len();
+            let channels: Vec<Vec<Complex<f64>>> =
+                data.iter().map(|d| d[offset..offset + n].to_vec()).collect();
+            offset += n;
+            let mut acq = Acquisition::new(&proj, &channels)?;
+            if let Some(ref t) = times {
+                acq.head.sample_time_us = (1e6 * dwell(&t[offset - n..offset])?) as f32;
+            }
+            acq.head.scan_counter = i as u32;
+            acq.head.idx.kspace_encode_step_1 = i as u16;
+            if i + 1 == k.num_units() {
+                acq.head.set_flag(ACQ_LAST_IN_MEASUREMENT);
+            }
+            Ok(acq)
+        }).collect()
+}
+
+/// Create acquisitions of `readout` consecutive samples (fewer in the last one) from a trajectory
+/// of single samples and multi-coil data (`[coil][sample]`), see `from_projections`
+pub fn from_kspace(
+    k: &KSpace,
+    data: &[Vec<Complex<f64>>],
+    readout: usize,
+) -> Result<Vec<Acquisition>> {
+    if readout == 0 {
+        return Err(MriError::InvalidParameter(
+            "readouts need at least one sample".to_string(),
+        ));
+    }
+    let times = k.sample_times();
+    let mut projections = KSpaceProjections::new();
+    for (i, proj) in k.kspace().chunks(readout).enumerate() {
+        match times {
+            Some(ref t) => {
+                let t = t[i * readout..i * readout + proj.len()].to_vec();
+                projections.try_add_timed(proj.to_vec(), SampleTiming::PerSample(t))?
+            }
+            None => projections.try_add(proj.to_vec())?,
+        };
+    }
+    from_projections(&projections, data)
+}
+
+/// Time between the equally spaced samples of a readout, zero for a single sample
+fn dwell(times: &[f64]) -> Result<f64> {
+    if times.len() < 2 {
+        return Ok(0.0);
+    }
+    let dwell = (times[times.len() - 1] - times[0]) / ((times.len() - 1) as f64);
+    let tol = 1e-6 * dwell.abs();
+    let uneven = times.windows(2).any(|w| (w[1] - w[0] - dwell).abs() > tol);
+    if dwell <= 0.0 || !dwell.is_finite() || uneven {
+        return Err(MriError::InvalidParameter(
+            "ISMRMRD acquisitions need equally spaced sample times".to_string(),
+        ));
+    }
+    Ok(dwell)
+}
+
+/// Acquisition as stored in HDF5 files, with the data as interleaved real and imaginary parts
+#[cfg(feature = "hdf5")]
+#[derive(Clone, H5Type)]
+#[repr(C)]
+struct AcquisitionRecord {
+    head: AcquisitionHeader,
+    traj: VarLenArray<f32>,
+    data: VarLenArray<f32>,
+}
+
+/// Read the XML header and the acquisitions of the dataset `dataset` (see `DEFAULT_DATASET`)
+/// from an ISMRMRD HDF5 file
+#[cfg(feature = "hdf5")]
+pub fn read_hdf5<P: AsRef<Path>>(path: P, dataset: &str) -> Result<(String, Vec<Acquisition>)> {
+    let file = hdf5::File::open(path)?;
+    let group = file.group(dataset)?;
+    // the XML header is stored as a single variable length string of either character set
+    let xml = group.dataset("xml")?;
+    let xml: Vec<String> = match xml.read_raw::<VarLenUnicode>() {
+        Ok(v) => v.iter().map(|x| x.as_str().to_string()).collect(),
+        Err(_) => xml
+            .read_raw::<VarLenAscii>()?
+            .iter()
+            .map(|x| x.as_str().to_string())
+            .collect(),
+    };
+    let xml = xml.into_iter().next().unwrap_or_default();
+    if !group.link_exists("data") {
+        return Ok((xml, vec![]));
+    }
+    let acquisitions = group
+        .dataset("data")?
+        .read_raw::<AcquisitionRecord>()?
+        .into_iter()
+        .map(|r| {
+            let data = r.data.as_slice();
+            if data.len() % 2 != 0 {
+                return Err(MriError::InvalidFile(
+                    "odd number of values in complex acquisition data".to_string(),
+                ));
+            }
+            let acq = Acquisition {
+                head: r.head,
+                traj: r.traj.as_slice().to_vec(),
+                data: data.chunks(2).map(|c| Complex::new(c[0], c[1])).collect(),
+            };
+            check_dims(acq.num_samples() * acq.num_channels(), acq.data.len())?;
+            Ok(acq)
+        }).collect::<Result<Vec<_>>>()?;
+    Ok((xml, acquisitions))
+}
+
+/// Write an XML header and acquisitions as the dataset `dataset` (see `DEFAULT_DATASET`) of a
+/// new ISMRMRD HDF5 file. Existing files are overwritten.
+#[cfg(feature = "hdf5")]
+pub fn write_hdf5<P: AsRef<Path>>(
+    path: P,
+    dataset: &str,
+    xml: &str,
+    acquisitions: &[Acquisition],
+) -> Result<()> {
+    let records = acquisitions
+        .iter()
+        .map(|acq| {
+            let h = &acq.head;
+            check_dims(
+                h.number_of_samples as usize * h.trajectory_dimensions as usize,
+                acq.traj.len(),
+            )?;
+            check_dims(acq.num_samples() * acq.num_channels(), acq.data.len())?;
+            let data: Vec<f32> = acq.data.iter().flat_map(|x| vec![x.re, x.im]).collect();
+            Ok(AcquisitionRecord {
+                head: acq.head.clone(),
+                traj: VarLenArray::from_slice(&acq.traj),
+                data: VarLenArray::from_slice(&data),
+            })
+        }).collect::<Result<Vec<_>>>()?;
+    let xml: VarLenUnicode = xml
+        .parse()
+        .map_err(|_| MriError::InvalidParameter("XML header contains a NUL".to_string()))?;
+
+    let file = hdf5::File::create(path)?;
+    let group = file.create_group(dataset)?;
+    group
+        .new_dataset::<VarLenUnicode>()
+        .shape(1)
+        .create("xml")?
+        .write_raw(&[xml])?;
+    group
+        .new_dataset::<AcquisitionRecord>()
+        .chunk(records.len().clamp(1, 1024))
+        .shape(records.len()..)
+        .create("data")?
+        .write_raw(&records)?;
+    Ok(())
+}
+
+/// Acquisitions which are not noise measurements
+fn measurements(acquisitions: &[Acquisition]) -> impl Iterator<Item = &Acquisition> {
+    acquisitions
+        .iter()
+        .filter(|a| !a.head.is_flag_set(ACQ_IS_NOISE_MEASUREMENT))
+}
+
+/// Append the channels of an acquisition to multi-coil data
+fn append(data: &mut Vec<Vec<Complex<f64>>>, channels: Vec<Vec<Complex<f64>>>) -> Result<()> {
+    if data.is_empty() {
+        *data = channels;
+        return Ok(());
+    }
+    check_dims(data.len(), channels.len())?;
+    for (d, c) in data.iter_mut().zip(channels) {
+        d.extend(c);
+    }
+    Ok(())
+}
+
+/// Sequential little endian reader
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    /// Next `N` bytes
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut b = [0u8; N];
+        b.copy_from_slice(&self.bytes[self.pos..self.pos + N]);
+        self.pos += N;
+        b
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SpatialDims;
+
+    /// timed radial trajectory and two coil data
+    fn projections() -> (KSpaceProjections, Vec<Vec<Complex<f64>>>) {
+        let mut k = KSpaceProjections::radial(0.2, 8, 4);
+        for i in 0..4 {
+            let timing = SampleTiming::Dwell {
+                start: 0.0,
+                dwell: 4e-6,
+            };
+            k.try_set_timing(i, timing).unwrap();
+        }
+        let n = k.samples().len();
+        let data = (0..2)
+            .map(|c| {
+                (0..n)
+                    .map(|i| Complex::new(i as f64, (c * n) as f64 - 0.5 * (i as f64)))
+                    .collect()
+            }).collect();
+        (k, data)
+    }
+
+    /// sample `s` of channel `c` of Cartesian line `l`
+    fn value(c: usize, l: u16, s: usize) -> Complex<f64> {
+        Complex::new((100 * c + 10 * l as usize + s) as f64, -(s as f64))
+    }
+
+    /// Cartesian line of 10 samples and two channels with one discarded sample at either end,
+    /// as in `tests/data/ismrmrd_cartesian.h5`
+    fn cartesian_line(line: u16) -> Acquisition {
+        let data: Vec<Vec<Complex<f64>>> = (0..2)
+            .map(|c| (0..10).map(|s| value(c, line, s)).collect())
+            .collect();
+        let mut acq = Acquisition::new(&vec![vec![]; 10], &data).unwrap();
+        acq.head.discard_pre = 1;
+        acq.head.discard_post = 1;
+        acq.head.center_sample = 5;
+        acq.head.sample_time_us = 2.5;
+        acq.head.idx.kspace_encode_step_1 = line;
+        acq
+    }
+
+    /// Check the samples, times and data of four Cartesian lines on an 8x4 matrix over
+    /// 0.2 m x 0.1 m with the center at line 2
+    fn check_cartesian(k: &KSpace, data: &[Vec<Complex<f64>>]) {
+        assert_eq!(k.num_samples(), 32);
+        assert_eq!(data.len(), 2);
+        let times = k.sample_times().unwrap();
+        for l in 0..4 {
+            for s in 1..9 {
+                let i = 8 * l as usize + s - 1;
+                let expected = [(s as f64 - 5.0) / 0.2, (f64::from(l) - 2.0) / 0.1];
+                assert_eq!(k.kspace()[i].len(), 2);
+                for (x, y) in k.kspace()[i].iter().zip(&expected) {
+                    assert!((x - y).abs() < 1e-9);
+                }
+                assert!((times[i] - 2.5e-6 * s as f64).abs() < 1e-12);
+                for (c, d) in data.iter().enumerate() {
+                    assert_eq!(d[i], value(c, l, s));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn header_bytes() {
+        let mut head = AcquisitionHeader {
+            version: ISMRMRD_VERSION,
+            number_of_samples: 128,
+            sample_time_us: 2.5,
+            position: [1.0, -2.0, 3.5],
+            user_int: [1, 2, 3, 4, 5, 6, 7, -8],
+            ..Default::default()
+        };
+        head.set_flag(ACQ_IS_PARALLEL_CALIBRATION);
+        head.channel_mask[3] = 0xdead_beef;
+        head.idx.kspace_encode_step_1 = 17;
+        head.idx.user[7] = 9;
+        let bytes = head.to_bytes();
+        assert_eq!(bytes.len(), ACQUISITION_HEADER_SIZE);
+        assert_eq!(AcquisitionHeader::from_bytes(&bytes).unwrap(), head);
+        assert!(AcquisitionHeader::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn projections_round_trip() {
+        let (k, data) = projections();
+        let acquisitions = from_projections(&k, &data).unwrap();
+        assert_eq!(acquisitions.len(), 4);
+        for acq in &acquisitions {
+            assert!((acq.head.sample_time_us - 4.0).abs() < 1e-6);
+        }
+        assert!(acquisitions[3].head.is_flag_set(ACQ_LAST_IN_MEASUREMENT));
+
+        let (k2, data2) = to_projections(&acquisitions, None).unwrap();
+        assert_eq!(k2.num_units(), 4);
+        for (a, b) in k.samples().iter().zip(k2.samples().iter()) {
+            for (x, y) in a.iter().zip(b.iter()) {
+                assert!((x - y).abs() < 1e-5 * x.abs().max(1.0));
+            }
+        }
+        for (a, b) in k.sample_times().unwrap().iter().zip(k2.sample_times().unwrap()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+        assert_eq!(data2, data);
+    }
+
+    #[test]
+    fn uneven_timing() {
+        let (mut k, data) = projections();
+        let times = (0..7).map(|i| (i * i) as f64 * 1e-6).collect();
+        k.try_set_timing(1, SampleTiming::PerSample(times)).unwrap();
+        assert!(from_projections(&k, &data).is_err());
+    }
+
+    #[test]
+    fn cartesian_acquisitions() {
+        let encoding = CartesianEncoding::new([0.2, 0.1, 0.005], [8, 4, 1]).unwrap();
+        assert_eq!(encoding.center, [2, 0]);
+        let noise = vec![vec![Complex::new(1.0, 0.0); 8]; 2];
+        let mut acquisitions = vec![Acquisition::new(&vec![vec![]; 8], &noise).unwrap()];
+        acquisitions[0].head.set_flag(ACQ_IS_NOISE_MEASUREMENT);
+        acquisitions.extend((0..4).map(cartesian_line));
+
+        assert!(to_kspace(&acquisitions, None).is_err());
+        let (k, data) = to_kspace(&acquisitions, Some(&encoding)).unwrap();
+        check_cartesian(&k, &data);
+
+        let (p, data2) = to_projections(&acquisitions, Some(&encoding)).unwrap();
+        assert_eq!(p.num_units(), 4);
+        assert_eq!(p.samples(), k.samples());
+        assert_eq!(p.sample_times(), k.sample_times());
+        assert_eq!(data2, data);
+
+        // acquisitions with and without dwell time do not form a timed trajectory
+        acquisitions[2].head.sample_time_us = 0.0;
+        assert!(to_kspace(&acquisitions, Some(&encoding)).is_err());
+        acquisitions[2].head.discard_post = 10;
+        assert!(acquisitions[2].channels().is_err());
+        assert!(to_projections(&acquisitions, Some(&encoding)).is_err());
+    }
+
+    #[test]
+    fn encoding_from_xml() {
+        let xml = "<ismrmrdHeader><encoding><encodedSpace>\
+                   <matrixSize><x>16</x><y>8</y><z>4</z></matrixSize>\
+                   <fieldOfView_mm><x>256</x><y>128</y><z>40.5</z></fieldOfView_mm>\
+                   </encodedSpace><reconSpace><matrixSize><x>8</x><y>8</y><z>4</z></matrixSize>\
+                   </reconSpace><encodingLimits><kspace_encoding_step_1>\
+                   <minimum>0</minimum><maximum>7</maximum><center>3</center>\
+                   </kspace_encoding_step_1></encodingLimits></encoding></ismrmrdHeader>";
+        let encoding = CartesianEncoding::from_xml(xml).unwrap();
+        assert_eq!(encoding.matrix, [16, 8, 4]);
+        assert_eq!(encoding.center, [3, 2]);
+        for (x, y) in encoding.fov.iter().zip(&[0.256, 0.128, 0.0405]) {
+            assert!((x - y).abs() < 1e-12);
+        }
+        // three dimensional samples of a 3D matrix
+        let mut acq = cartesian_line(1);
+        acq.head.idx.kspace_encode_step_2 = 3;
+        let traj = acq.trajectory(Some(&encoding)).unwrap();
+        assert_eq!(traj.len(), 8);
+        assert!((traj[0][0] + 4.0 / 0.256).abs() < 1e-9);
+        assert!((traj[0][1] + 2.0 / 0.128).abs() < 1e-9);
+        assert!((traj[0][2] - 1.0 / 0.0405).abs() < 1e-9);
+
+        assert!(CartesianEncoding::from_xml("<ismrmrdHeader/>").is_err());
+        let no_fov = xml.replace("fieldOfView_mm", "fov");
+        assert!(CartesianEncoding::from_xml(&no_fov).is_err());
+        let fractional = xml.replace("<x>16</x>", "<x>1.5</x>");
+        assert!(CartesianEncoding::from_xml(&fractional).is_err());
+        assert!(CartesianEncoding::new([0.2, 0.0, 0.1], [2, 2, 2]).is_err());
+        assert!(CartesianEncoding::new([0.2, 0.2, 0.1], [2, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn kspace_round_trip() {
+        let mut k = KSpace::cartesian(SpatialDims::TwoD(0.2, 0.1), SpatialDims::TwoD(8, 4));
+        let data = vec![(0..32).map(|i| Complex::new(i as f64, 1.0)).collect()];
+        assert!(from_kspace(&k, &data, 0).is_err());
+        assert_eq!(from_kspace(&k, &data, 3).unwrap().len(), 11);
+
+        // one readout per line, starting every millisecond
+        let times = (0..32)
+            .map(|i| 1e-3 * (i / 8) as f64 + 2e-6 * (i % 8) as f64)
+            .collect();
+        k.set_times(SampleTiming::PerSample(times));
+        // readouts across two lines are not equally spaced in time
+        assert!(from_kspace(&k, &data, 16).is_err());
+        let acquisitions = from_kspace(&k, &data, 8).unwrap();
+        assert_eq!(acquisitions.len(), 4);
+        let (k2, data2) = to_kspace(&acquisitions, None).unwrap();
+        for (a, b) in k.kspace().iter().zip(k2.kspace()) {
+            for (x, y) in a.iter().zip(b) {
+                assert!((x - y).abs() < 1e-5 * x.abs().max(1.0));
+            }
+        }
+        // the start of the readouts is lost
+        for (i, t) in k2.sample_times().unwrap().iter().enumerate() {
+            assert!((t - 2e-6 * (i % 8) as f64).abs() < 1e-12);
+        }
+        assert_eq!(data2, data);
+    }
+
+    #[cfg(feature = "hdf5")]
+    #[test]
+    fn hdf5_round_trip() {
+        let (k, data) = projections();
+        let mut acquisitions = from_projections(&k, &data).unwrap();
+        acquisitions[0].head.set_flag(ACQ_IS_NOISE_MEASUREMENT);
+        acquisitions[2].head.user_float[5] = -1.25;
+        let xml = "<?xml version=\"1.0\"?><ismrmrdHeader>\u{b5}</ismrmrdHeader>";
+        let path = ::std::env::temp_dir().join(format!("mri-ismrmrd-{}.h5", ::std::process::id()));
+        write_hdf5(&path, DEFAULT_DATASET, xml, &acquisitions).unwrap();
+        let read = read_hdf5(&path, DEFAULT_DATASET);
+        let missing = read_hdf5(&path, "other");
+        ::std::fs::remove_file(&path).unwrap();
+        let (xml2, acquisitions2) = read.unwrap();
+        assert_eq!(xml2, xml);
+        assert_eq!(acquisitions2, acquisitions);
+        assert!(missing.is_err());
+    }
+
+    #[cfg(feature = "hdf5")]
+    #[test]
+    fn reference_fixture() {
+        // written with the HDF5 types of the ISMRMRD reference library, see the generator next
+        // to the file
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/ismrmrd_cartesian.h5");
+        let (xml, acquisitions) = read_hdf5(path, DEFAULT_DATASET).unwrap();
+        assert_eq!(acquisitions.len(), 5);
+        assert!(acquisitions[0].head.is_flag_set(ACQ_IS_NOISE_MEASUREMENT));
+        assert!(acquisitions[4].head.is_flag_set(ACQ_LAST_IN_MEASUREMENT));
+        for (acq, line) in acquisitions[1..].iter().zip(0..) {
+            let mut expected = cartesian_line(line).head;
+            expected.center_sample = acq.head.center_sample;
+            expected.scan_counter = u32::from(line) + 1;
+            expected.flags = acq.head.flags;
+            expected.read_dir = [1.0, 0.0, 0.0];
+            expected.phase_dir = [0.0, 1.0, 0.0];
+            expected.slice_dir = [0.0, 0.0, 1.0];
+            assert_eq!(acq.head, expected);
+        }
+
+        let encoding = CartesianEncoding::from_xml(&xml).unwrap();
+        assert_eq!(encoding.matrix, [8, 4, 1]);
+        assert_eq!(encoding.center, [2, 0]);
+        let (k, data) = to_kspace(&acquisitions, Some(&encoding)).unwrap();
+        check_cartesian(&k, &data);
+    }
+}
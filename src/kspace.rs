@@ -7,8 +7,10 @@
 
 //! k-Space
 
+use error::{check_dims, check_fov, check_grid, check_index, MriError, Result};
 use num::Integer;
-use std::f64::consts::PI;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::ops::Range;
 use SpatialDims;
 
 /// A single k-space sample (one sample point in k-space)
@@ -16,17 +18,92 @@ pub type KSample = Vec<f64>;
 /// A k-Space projection
 pub type KProjection = Vec<KSample>;
 
+/// Acquisition times of the samples of one unit of a trajectory (s)
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleTiming {
+    /// readout starting at `start` with one sample every `dwell`
+    Dwell {
+        /// time of the first sample
+        start: f64,
+        /// time between two samples
+        dwell: f64,
+    },
+    /// time of every sample
+    PerSample(Vec<f64>),
+}
+
+impl SampleTiming {
+    /// Times of a unit with `num_samples` samples
+    pub fn times(&self, num_samples: usize) -> Result<Vec<f64>> {
+        let times = match *self {
+            SampleTiming::Dwell { start, dwell } => (0..num_samples)
+                .map(|i| start + (i as f64) * dwell)
+                .collect(),
+            SampleTiming::PerSample(ref t) => {
+                check_dims(num_samples, t.len())?;
+                t.clone()
+            }
+        };
+        if !times.iter().all(|t: &f64| t.is_finite()) {
+            return Err(MriError::InvalidParameter(
+                "sample times must be finite".to_string(),
+            ));
+        }
+        Ok(times)
+    }
+}
+
 /// Implement k-Space things!
 pub trait KSpaceThings {
     /// One thing
     type KUnit;
 
     /// Thing 1
-    fn add(&mut self, Self::KUnit) -> &mut Self;
+    fn add(&mut self, unit: Self::KUnit) -> &mut Self {
+        self.try_add(unit).unwrap_or_else(|e| panic!("{}", e))
+    }
     /// Thing 2
-    fn sample_at(&self, usize) -> Self::KUnit;
+    fn sample_at(&self, idx: usize) -> Self::KUnit {
+        self.try_sample_at(idx).unwrap_or_else(|e| panic!("{}", e))
+    }
     /// Thing 3
-    fn set_sample(&mut self, usize, Self::KUnit) -> &mut Self;
+    fn set_sample(&mut self, idx: usize, unit: Self::KUnit) -> &mut Self {
+        self.try_set_sample(idx, unit)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Add a unit acquired at the given times
+    fn add_timed(&mut self, unit: Self::KUnit, timing: SampleTiming) -> &mut Self {
+        self.try_add_timed(unit, timing)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Replace a unit and its acquisition times
+    fn set_sample_timed(
+        &mut self,
+        idx: usize,
+        unit: Self::KUnit,
+        timing: SampleTiming,
+    ) -> &mut Self {
+        self.try_set_sample_timed(idx, unit, timing)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Thing 1, without panicking
+    fn try_add(&mut self, unit: Self::KUnit) -> Result<&mut Self>;
+    /// Thing 2, without panicking
+    fn try_sample_at(&self, idx: usize) -> Result<Self::KUnit>;
+    /// Thing 3, without panicking. The acquisition times of the unit are kept if its number of
+    /// samples does not change and removed otherwise.
+    fn try_set_sample(&mut self, idx: usize, unit: Self::KUnit) -> Result<&mut Self>;
+    /// Add a unit acquired at the given times, without panicking
+    fn try_add_timed(&mut self, unit: Self::KUnit, timing: SampleTiming) -> Result<&mut Self>;
+    /// Replace a unit and its acquisition times, without panicking
+    fn try_set_sample_timed(
+        &mut self,
+        idx: usize,
+        unit: Self::KUnit,
+        timing: SampleTiming,
+    ) -> Result<&mut Self>;
+    /// Set the acquisition times of the unit at `idx`
+    fn try_set_timing(&mut self, idx: usize, timing: SampleTiming) -> Result<&mut Self>;
     /// Thing 4
     fn num_channels(&self) -> usize;
     /// Thing 5
@@ -35,6 +112,51 @@ pub trait KSpaceThings {
     fn num_units(&self) -> usize;
     /// Thing 7
     fn samples(&self) -> Vec<KSample>;
+    /// Acquisition time of every sample in the order of `samples`, or `None` if a unit has no
+    /// timing
+    fn sample_times(&self) -> Option<Vec<f64>>;
+}
+
+/// Angular ordering of the spokes of 2D radial trajectories
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpokeOrdering {
+    /// spokes spaced uniformly by `pi / N`
+    Uniform,
+    /// successive spokes rotated by the golden angle `pi / phi` (about 111.25 degrees), see
+    /// Winkelmann et al., "An optimal radial profile order based on the golden ratio for
+    /// time-resolved MRI", IEEE TMI 26(1), 2007
+    GoldenAngle,
+    /// successive spokes rotated by the `N`th tiny golden angle `pi / (phi + N - 1)`, see Wundrak
+    /// et al., "Golden ratio sparse MRI using tiny golden angles", MRM 75(6), 2016. `N = 1` is
+    /// the golden angle.
+    TinyGoldenAngle(usize),
+}
+
+/// Distribution of the spoke directions of 3D radial trajectories
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpokeDistribution {
+    /// spiral phyllotaxis (Piccini et al., "Spiral phyllotaxis: the natural way to construct a 3D
+    /// radial trajectory in MRI", MRM 66(4), 2011)
+    Phyllotaxis,
+    /// generalized spiral (Saff and Kuijlaars, "Distributing many points on a sphere", Math.
+    /// Intelligencer 19(1), 1997)
+    SaffKuijlaars,
+    /// 2D golden means, such that every contiguous set of spokes is distributed almost uniformly
+    /// (Chan et al., "Temporal stability of adaptive 3D radial MRI using multidimensional golden
+    /// means", MRM 61(2), 2009)
+    GoldenMeans,
+}
+
+/// Acquisition times of all units in order, or `None` if a unit has no timing
+fn flatten_times(times: &[Option<Vec<f64>>], num_units: usize) -> Option<Vec<f64>> {
+    if times.len() != num_units {
+        return None;
+    }
+    let mut out = vec![];
+    for t in times {
+        out.extend_from_slice(t.as_ref()?);
+    }
+    Some(out)
 }
 
 /// K-space defined as a set of projections
@@ -44,6 +166,8 @@ pub struct KSpaceProjections {
     num_channels: usize,
     num_samples: usize,
     num_projections: usize,
+    /// acquisition times of every projection
+    times: Vec<Option<Vec<f64>>>,
 }
 
 impl KSpaceProjections {
@@ -54,13 +178,27 @@ impl KSpaceProjections {
             num_channels: 0,
             num_samples: 0,
             num_projections: 0,
+            times: vec![],
         }
     }
 
-    /// create radial trajectory (only 2D so far)
+    /// create radial trajectory (2D), see `radial_3d` and `stack_of_stars` for 3D
     pub fn radial(fov: f64, samples: usize, spokes: usize) -> Self {
+        KSpaceProjections::try_radial(fov, samples, spokes).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create radial trajectory (2D), failing on invalid parameters
+    pub fn try_radial(fov: f64, samples: usize, spokes: usize) -> Result<Self> {
+        check_fov(fov)?;
+        if samples < 2 {
+            return Err(MriError::EmptyProjection);
+        }
+        if spokes == 0 {
+            return Err(MriError::InvalidParameter(
+                "number of spokes must be positive".to_string(),
+            ));
+        }
         let dk = 1. / fov;
-        let num_samples = samples * spokes;
         let mut k: Vec<KProjection> = Vec::with_capacity(spokes);
         // create single spoke
         let nx2 = if samples.is_even() {
@@ -92,39 +230,323 @@ impl KSpaceProjections {
             k.push(spoke_n);
         }
 
-        // create all spokes
+        // count the samples actually pushed, the center point is missing on every spoke
+        Ok(KSpaceProjections::from_projections(k, 2))
+    }
 
-        KSpaceProjections {
+    /// create an Archimedean spiral trajectory (2D) with one projection per interleaf.
+    ///
+    /// `fov` and the resolution `res` are given in the same unit. The interleaves are spaced such
+    /// that the distance between neighbouring turns is `1/fov` and each interleaf is sampled with
+    /// (at most) `1/fov` spacing along the readout, starting in the center of k-space and ending
+    /// at `1/(2 * res)`.
+    pub fn spiral(fov: f64, res: f64, interleaves: usize) -> Self {
+        KSpaceProjections::spiral_variable_density(fov, res, interleaves, 1.0)
+    }
+
+    /// create an Archimedean spiral trajectory, failing on invalid parameters
+    pub fn try_spiral(fov: f64, res: f64, interleaves: usize) -> Result<Self> {
+        KSpaceProjections::try_spiral_variable_density(fov, res, interleaves, 1.0)
+    }
+
+    /// create a variable density spiral trajectory (2D) with one projection per interleaf.
+    ///
+    /// The radius grows as `kmax * tau^alpha` while the angle grows linearly with `tau` in
+    /// `[0, 1]`. `alpha = 1` is an Archimedean spiral, `alpha > 1` samples the center of k-space
+    /// more densely. The outer edge of k-space is sampled at the Nyquist rate given by `fov`.
+    pub fn spiral_variable_density(fov: f64, res: f64, interleaves: usize, alpha: f64) -> Self {
+        KSpaceProjections::try_spiral_variable_density(fov, res, interleaves, alpha)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a variable density spiral trajectory, failing on invalid parameters
+    pub fn try_spiral_variable_density(
+        fov: f64,
+        res: f64,
+        interleaves: usize,
+        alpha: f64,
+    ) -> Result<Self> {
+        check_fov(fov)?;
+        if !(res > 0.0 && res.is_finite()) {
+            return Err(MriError::InvalidParameter(format!("resolution {}", res)));
+        }
+        if interleaves == 0 {
+            return Err(MriError::InvalidParameter(
+                "number of interleaves must be positive".to_string(),
+            ));
+        }
+        if !(alpha > 0.0 && alpha.is_finite()) {
+            return Err(MriError::InvalidParameter(format!("density exponent {}", alpha)));
+        }
+        let kmax = 1.0 / (2.0 * res);
+        let dk = 1.0 / fov;
+        let theta_max = 2.0 * PI * alpha * kmax * fov / (interleaves as f64);
+
+        // finely sampled interleaf
+        let num_fine = 8 * ((kmax * theta_max * fov).ceil() as usize) + 128;
+        let fine: Vec<(f64, f64)> = (0..=num_fine)
+            .map(|i| {
+                let tau = (i as f64) / (num_fine as f64);
+                let r = kmax * tau.powf(alpha);
+                let theta = theta_max * tau;
+                (r * theta.cos(), r * theta.sin())
+            }).collect();
+        let mut arc = Vec::with_capacity(fine.len());
+        arc.push(0.0);
+        for i in 1..fine.len() {
+            let ds = (fine[i].0 - fine[i - 1].0).hypot(fine[i].1 - fine[i - 1].1);
+            let prev = arc[i - 1];
+            arc.push(prev + ds);
+        }
+
+        // resample with equidistant arc length
+        let length = arc[arc.len() - 1];
+        let samples = (length / dk).ceil() as usize + 1;
+        let mut arm: Vec<(f64, f64)> = Vec::with_capacity(samples);
+        let mut j = 0;
+        for i in 0..samples {
+            let s = length * (i as f64) / ((samples - 1) as f64);
+            while j < fine.len() - 2 && arc[j + 1] < s {
+                j += 1;
+            }
+            let w = if arc[j + 1] > arc[j] {
+                ((s - arc[j]) / (arc[j + 1] - arc[j])).min(1.0)
+            } else {
+                0.0
+            };
+            arm.push((
+                fine[j].0 + w * (fine[j + 1].0 - fine[j].0),
+                fine[j].1 + w * (fine[j + 1].1 - fine[j].1),
+            ));
+        }
+
+        let k: Vec<KProjection> = (0..interleaves)
+            .map(|i| {
+                let phi = 2.0 * PI * (i as f64) / (interleaves as f64);
+                let (sin_phi, cos_phi) = phi.sin_cos();
+                arm.iter()
+                    .map(|&(x, y)| vec![x * cos_phi - y * sin_phi, x * sin_phi + y * cos_phi])
+                    .collect()
+            }).collect();
+
+        Ok(KSpaceProjections {
             projections: k,
             num_channels: 2,
-            num_samples: num_samples,
-            num_projections: spokes,
+            num_samples: samples * interleaves,
+            num_projections: interleaves,
+            times: vec![None; interleaves],
+        })
+    }
+
+    /// create a 2D radial trajectory with `spokes` spokes through the center of k-space in the
+    /// given order, each with `samples` samples spaced by `1/fov`. Unlike `radial`, every spoke
+    /// includes the center of k-space.
+    pub fn radial_ordered(
+        fov: f64,
+        samples: usize,
+        spokes: usize,
+        ordering: SpokeOrdering,
+    ) -> Self {
+        KSpaceProjections::try_radial_ordered(fov, samples, spokes, ordering)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a 2D radial trajectory in the given order, failing on invalid parameters
+    pub fn try_radial_ordered(
+        fov: f64,
+        samples: usize,
+        spokes: usize,
+        ordering: SpokeOrdering,
+    ) -> Result<Self> {
+        check_radial(fov, samples, spokes)?;
+        let offsets = spoke_offsets(samples);
+        let k: Vec<KProjection> = spoke_angles(spokes, ordering)?
+            .iter()
+            .map(|theta| spoke(&[0.0; 2], &[theta.cos(), theta.sin()], 1.0 / fov, &offsets))
+            .collect();
+        Ok(KSpaceProjections::from_projections(k, 2))
+    }
+
+    /// create a 3D radial (koosh ball) trajectory with `spokes` spokes through the center of
+    /// k-space, each with `samples` samples spaced by `1/fov`
+    pub fn radial_3d(fov: f64, samples: usize, spokes: usize, dist: SpokeDistribution) -> Self {
+        KSpaceProjections::try_radial_3d(fov, samples, spokes, dist)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a 3D radial trajectory, failing on invalid parameters
+    pub fn try_radial_3d(
+        fov: f64,
+        samples: usize,
+        spokes: usize,
+        dist: SpokeDistribution,
+    ) -> Result<Self> {
+        check_radial(fov, samples, spokes)?;
+        let offsets = spoke_offsets(samples);
+        let k: Vec<KProjection> = spoke_directions_3d(spokes, dist)
+            .iter()
+            .map(|dir| spoke(&[0.0; 3], dir, 1.0 / fov, &offsets))
+            .collect();
+        Ok(KSpaceProjections::from_projections(k, 3))
+    }
+
+    /// create a stack-of-stars trajectory: 2D radial spokes in the xy plane with field of view
+    /// `fov` in the given order, repeated on `partitions` Cartesian partitions for the slab
+    /// thickness `fov_z`. The projections are ordered by angle and then by partition.
+    pub fn stack_of_stars(
+        fov: f64,
+        fov_z: f64,
+        samples: usize,
+        spokes: usize,
+        partitions: usize,
+        ordering: SpokeOrdering,
+    ) -> Self {
+        KSpaceProjections::try_stack_of_stars(fov, fov_z, samples, spokes, partitions, ordering)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a stack-of-stars trajectory, failing on invalid parameters
+    pub fn try_stack_of_stars(
+        fov: f64,
+        fov_z: f64,
+        samples: usize,
+        spokes: usize,
+        partitions: usize,
+        ordering: SpokeOrdering,
+    ) -> Result<Self> {
+        check_radial(fov, samples, spokes)?;
+        let offsets = spoke_offsets(samples);
+        let k: Vec<KProjection> = stack_of_stars_spokes(fov_z, spokes, partitions, ordering)?
+            .iter()
+            .map(|(pos, dir)| spoke(pos, dir, 1.0 / fov, &offsets))
+            .collect();
+        Ok(KSpaceProjections::from_projections(k, 3))
+    }
+
+    /// Return the projections with the indices `units` in the given order, including their
+    /// acquisition times
+    pub fn select(&self, units: &[usize]) -> Result<Self> {
+        let mut out = KSpaceProjections::new();
+        for &u in units {
+            check_index(u, self.num_projections)?;
+            out.try_add(self.projections[u].clone())?;
+            out.times[out.num_projections - 1] = self.times[u].clone();
+        }
+        Ok(out)
+    }
+
+    /// Return the indices in `samples` of the samples of the projections with the indices
+    /// `units`, e.g. to select the data belonging to `select(units)`
+    pub fn sample_indices(&self, units: &[usize]) -> Result<Vec<usize>> {
+        let mut offsets = Vec::with_capacity(self.num_projections + 1);
+        offsets.push(0);
+        for p in &self.projections {
+            let last = offsets[offsets.len() - 1];
+            offsets.push(last + p.len());
+        }
+        let mut out = vec![];
+        for &u in units {
+            check_index(u, self.num_projections)?;
+            out.extend(offsets[u]..offsets[u + 1]);
+        }
+        Ok(out)
+    }
+
+    /// Split the projections into frames of `size` consecutive projections, starting every
+    /// `step` projections, see `frame_ranges`
+    pub fn frames(&self, size: usize, step: usize) -> Result<Vec<Self>> {
+        frame_ranges(self.num_projections, size, step)?
+            .into_iter()
+            .map(|r| self.select(&r.collect::<Vec<usize>>()))
+            .collect()
+    }
+}
+
+impl KSpaceProjections {
+    /// Create a trajectory from non-empty projections with `num_channels` channels
+    fn from_projections(projections: Vec<KProjection>, num_channels: usize) -> Self {
+        let num_projections = projections.len();
+        KSpaceProjections {
+            num_channels,
+            num_samples: projections.iter().map(|p| p.len()).sum(),
+            num_projections,
+            projections,
+            times: vec![None; num_projections],
         }
     }
+
+    /// Check that a projection is not empty and that all its samples have the number of channels
+    /// of this trajectory. Returns the number of channels of the projection.
+    fn check_projection(&self, proj: &[KSample]) -> Result<usize> {
+        let num_ch = match proj.first() {
+            Some(s) => s.len(),
+            None => return Err(MriError::EmptyProjection),
+        };
+        if self.num_channels != 0 {
+            check_dims(self.num_channels, num_ch)?;
+        }
+        for s in proj {
+            check_dims(num_ch, s.len())?;
+        }
+        Ok(num_ch)
+    }
 }
 
 impl KSpaceThings for KSpaceProjections {
     type KUnit = KProjection;
 
-    fn add(&mut self, proj: KProjection) -> &mut Self {
-        let num_ch = proj[0].len();
+    fn try_add(&mut self, proj: KProjection) -> Result<&mut Self> {
+        let num_ch = self.check_projection(&proj)?;
         if self.num_channels == 0 {
-            self.num_channels = proj[0].len();
+            self.num_channels = num_ch;
         }
-        assert!(num_ch == self.num_channels);
+        self.num_samples += proj.len();
         self.projections.push(proj);
+        self.times.resize(self.num_projections, None);
+        self.times.push(None);
         self.num_projections += 1;
-        self
+        Ok(self)
     }
 
-    fn sample_at(&self, idx: usize) -> KProjection {
-        self.projections[idx].clone()
+    fn try_sample_at(&self, idx: usize) -> Result<KProjection> {
+        check_index(idx, self.projections.len())?;
+        Ok(self.projections[idx].clone())
     }
 
-    fn set_sample(&mut self, idx: usize, proj: KProjection) -> &mut Self {
-        // assert!(self.num_channels == sample.len());
+    fn try_set_sample(&mut self, idx: usize, proj: KProjection) -> Result<&mut Self> {
+        check_index(idx, self.projections.len())?;
+        self.check_projection(&proj)?;
+        self.num_samples = self.num_samples - self.projections[idx].len() + proj.len();
+        if self.projections[idx].len() != proj.len() {
+            self.times[idx] = None;
+        }
         self.projections[idx] = proj;
-        self
+        Ok(self)
+    }
+
+    fn try_add_timed(&mut self, proj: KProjection, timing: SampleTiming) -> Result<&mut Self> {
+        let times = timing.times(proj.len())?;
+        self.try_add(proj)?;
+        self.times[self.num_projections - 1] = Some(times);
+        Ok(self)
+    }
+
+    fn try_set_sample_timed(
+        &mut self,
+        idx: usize,
+        proj: KProjection,
+        timing: SampleTiming,
+    ) -> Result<&mut Self> {
+        let times = timing.times(proj.len())?;
+        self.try_set_sample(idx, proj)?;
+        self.times[idx] = Some(times);
+        Ok(self)
+    }
+
+    fn try_set_timing(&mut self, idx: usize, timing: SampleTiming) -> Result<&mut Self> {
+        check_index(idx, self.projections.len())?;
+        self.times[idx] = Some(timing.times(self.projections[idx].len())?);
+        Ok(self)
     }
 
     fn num_channels(&self) -> usize {
@@ -146,17 +568,23 @@ impl KSpaceThings for KSpaceProjections {
             .cloned()
             .collect()
     }
+
+    fn sample_times(&self) -> Option<Vec<f64>> {
+        flatten_times(&self.times, self.num_projections)
+    }
 }
 
 /// Representation of a k-space trajectory
 #[derive(Debug, Clone, Default)]
 pub struct KSpace {
     /// A vector of k-space samples
-    pub kspace: Vec<KSample>,
+    kspace: Vec<KSample>,
     /// Number of encoding channels
     num_channels: usize,
     /// Number of samples
     num_samples: usize,
+    /// acquisition time of every sample, or `None` if the trajectory is not timed
+    times: Option<Vec<f64>>,
 }
 
 impl KSpace {
@@ -166,6 +594,7 @@ impl KSpace {
             kspace: vec![],
             num_channels: 0,
             num_samples: 0,
+            times: None,
         }
     }
 
@@ -180,11 +609,19 @@ impl KSpace {
             kspace,
             num_channels,
             num_samples,
+            times: None,
         }
     }
 
     /// Create a Cartesian trajectory
     pub fn cartesian(fov: SpatialDims<f64>, samples: SpatialDims<usize>) -> Self {
+        KSpace::try_cartesian(fov, samples).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a Cartesian trajectory, failing if the field of view is invalid or does not match
+    /// the number of dimensions of `samples`
+    pub fn try_cartesian(fov: SpatialDims<f64>, samples: SpatialDims<usize>) -> Result<Self> {
+        check_grid(&fov, &samples)?;
         let dk = fov.invert();
         let num_samples = samples.product();
         let mut k: Vec<KSample> = Vec::with_capacity(num_samples);
@@ -198,11 +635,12 @@ impl KSpace {
                 for ii in 0..nx {
                     k.push(vec![-nx2 * dkx + (ii as f64) * dkx]);
                 }
-                KSpace {
+                Ok(KSpace {
                     kspace: k,
                     num_channels: 1,
-                    num_samples: num_samples,
-                }
+                    num_samples,
+                    times: None,
+                })
             }
             (SpatialDims::TwoD(dkx, dky), SpatialDims::TwoD(nx, ny)) => {
                 let nx2 = if nx.is_even() {
@@ -223,11 +661,12 @@ impl KSpace {
                         ]);
                     }
                 }
-                KSpace {
+                Ok(KSpace {
                     kspace: k,
                     num_channels: 2,
-                    num_samples: num_samples,
-                }
+                    num_samples,
+                    times: None,
+                })
             }
             (SpatialDims::ThreeD(dkx, dky, dkz), SpatialDims::ThreeD(nx, ny, nz)) => {
                 let nx2 = if nx.is_even() {
@@ -256,43 +695,128 @@ impl KSpace {
                         }
                     }
                 }
-                KSpace {
+                Ok(KSpace {
                     kspace: k,
                     num_channels: 3,
-                    num_samples: num_samples,
-                }
+                    num_samples,
+                    times: None,
+                })
             }
-            _ => panic!("Wrong combination of things."),
+            _ => unreachable!(),
         }
     }
-}
 
-impl KSpaceThings for KSpace {
-    type KUnit = KSample;
+    /// Return the k-space samples
+    pub fn kspace(&self) -> &[KSample] {
+        &self.kspace
+    }
 
-    /// Add a single k-space sample point to an existing trajectory
-    fn add(&mut self, sample: KSample) -> &mut Self {
+    /// Set the acquisition times of all samples, e.g. of a single readout with
+    /// `SampleTiming::Dwell`
+    pub fn set_times(&mut self, timing: SampleTiming) -> &mut Self {
+        self.try_set_times(timing)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Set the acquisition times of all samples, failing if `timing` does not match the number
+    /// of samples or is not finite
+    pub fn try_set_times(&mut self, timing: SampleTiming) -> Result<&mut Self> {
+        self.times = Some(timing.times(self.kspace.len())?);
+        Ok(self)
+    }
+
+    /// Append a sample without touching the acquisition times
+    fn push(&mut self, sample: KSample) -> Result<()> {
+        if sample.is_empty() {
+            return Err(MriError::EmptyProjection);
+        }
         if self.num_channels == 0 {
             self.num_channels = sample.len();
-        } else if self.num_channels != sample.len() {
-            panic!("Wrong number of samples");
+        } else {
+            check_dims(self.num_channels, sample.len())?;
         }
 
         self.kspace.push(sample);
         self.num_samples += 1;
-        self
+        Ok(())
+    }
+
+    /// Acquisition times of the trajectory, failing if it is not timed
+    fn times_mut(&mut self) -> Result<&mut Vec<f64>> {
+        self.times.as_mut().ok_or_else(|| {
+            MriError::InvalidParameter(
+                "trajectory has no acquisition times, set them with `set_times`".to_string(),
+            )
+        })
+    }
+}
+
+impl KSpaceThings for KSpace {
+    type KUnit = KSample;
+
+    /// Add a single k-space sample point to an existing trajectory. Fails if the trajectory
+    /// already contains samples with acquisition times, see `try_add_timed`.
+    fn try_add(&mut self, sample: KSample) -> Result<&mut Self> {
+        if self.times.is_some() && !self.kspace.is_empty() {
+            return Err(MriError::InvalidParameter(
+                "trajectory has acquisition times, add samples with `add_timed`".to_string(),
+            ));
+        }
+        self.push(sample)?;
+        self.times = None;
+        Ok(self)
     }
 
     /// Return sample at position `idx`
-    fn sample_at(&self, idx: usize) -> KSample {
-        self.kspace[idx].clone()
+    fn try_sample_at(&self, idx: usize) -> Result<KSample> {
+        check_index(idx, self.kspace.len())?;
+        Ok(self.kspace[idx].clone())
     }
 
     /// Set a sample at a specific position
-    fn set_sample(&mut self, idx: usize, sample: KSample) -> &mut Self {
-        assert!(self.num_channels == sample.len());
+    fn try_set_sample(&mut self, idx: usize, sample: KSample) -> Result<&mut Self> {
+        check_index(idx, self.kspace.len())?;
+        check_dims(self.num_channels, sample.len())?;
         self.kspace[idx] = sample;
-        self
+        Ok(self)
+    }
+
+    /// Add a single k-space sample point acquired at the first time of `timing`. Fails if the
+    /// trajectory already contains samples without acquisition times.
+    fn try_add_timed(&mut self, sample: KSample, timing: SampleTiming) -> Result<&mut Self> {
+        let t = timing.times(1)?[0];
+        if self.kspace.is_empty() {
+            self.push(sample)?;
+            self.times = Some(vec![t]);
+        } else {
+            self.times_mut()?;
+            self.push(sample)?;
+            self.times_mut()?.push(t);
+        }
+        Ok(self)
+    }
+
+    /// Set a sample and its acquisition time at a specific position
+    fn try_set_sample_timed(
+        &mut self,
+        idx: usize,
+        sample: KSample,
+        timing: SampleTiming,
+    ) -> Result<&mut Self> {
+        let t = timing.times(1)?[0];
+        check_index(idx, self.kspace.len())?;
+        self.times_mut()?;
+        self.try_set_sample(idx, sample)?;
+        self.times_mut()?[idx] = t;
+        Ok(self)
+    }
+
+    /// Set the acquisition time of the sample at `idx`
+    fn try_set_timing(&mut self, idx: usize, timing: SampleTiming) -> Result<&mut Self> {
+        check_index(idx, self.kspace.len())?;
+        let t = timing.times(1)?[0];
+        self.times_mut()?[idx] = t;
+        Ok(self)
     }
 
     /// Return the number of channels
@@ -313,6 +837,10 @@ impl KSpaceThings for KSpace {
     fn samples(&self) -> Vec<KSample> {
         self.kspace.clone()
     }
+
+    fn sample_times(&self) -> Option<Vec<f64>> {
+        self.times.clone()
+    }
 }
 
 /// Another way of defining a trajectory
@@ -327,12 +855,34 @@ pub struct KSpaceParameterizedProjections {
     dk: f64,
     limits: Vec<f64>,
     spoke_ind: Vec<i64>,
+    /// acquisition times of every projection
+    times: Vec<Option<Vec<f64>>>,
 }
 
 impl KSpaceParameterizedProjections {
     /// radial only using the first two channels.
     pub fn radial(fov: f64, num_projections: usize, num_channels: usize, samples: usize) -> Self {
-        assert!(num_channels >= 2);
+        KSpaceParameterizedProjections::try_radial(fov, num_projections, num_channels, samples)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// radial only using the first two channels, failing on invalid parameters.
+    pub fn try_radial(
+        fov: f64,
+        num_projections: usize,
+        num_channels: usize,
+        samples: usize,
+    ) -> Result<Self> {
+        check_fov(fov)?;
+        if num_channels < 2 {
+            return Err(MriError::InvalidParameter(format!(
+                "radial trajectories need at least 2 channels, found {}",
+                num_channels
+            )));
+        }
+        if samples == 0 {
+            return Err(MriError::EmptyProjection);
+        }
         let dk = 1. / fov;
         let num_samples_per_spoke = samples;
         let num_samples = samples * num_projections;
@@ -362,7 +912,7 @@ impl KSpaceParameterizedProjections {
             thing.push(-nx2 + ii as i64);
         }
 
-        KSpaceParameterizedProjections {
+        Ok(KSpaceParameterizedProjections {
             positions,
             directions,
             num_channels,
@@ -372,10 +922,201 @@ impl KSpaceParameterizedProjections {
             dk,
             limits: vec![-165.0, 165.0],
             spoke_ind: thing,
+            times: vec![None; num_projections],
+        })
+    }
+
+    /// radial trajectory using the first two channels, with the spokes in the given order
+    pub fn radial_ordered(
+        fov: f64,
+        num_projections: usize,
+        num_channels: usize,
+        samples: usize,
+        ordering: SpokeOrdering,
+    ) -> Self {
+        KSpaceParameterizedProjections::try_radial_ordered(
+            fov,
+            num_projections,
+            num_channels,
+            samples,
+            ordering,
+        ).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// radial trajectory with the spokes in the given order, failing on invalid parameters
+    pub fn try_radial_ordered(
+        fov: f64,
+        num_projections: usize,
+        num_channels: usize,
+        samples: usize,
+        ordering: SpokeOrdering,
+    ) -> Result<Self> {
+        check_radial(fov, samples, num_projections)?;
+        if num_channels < 2 {
+            return Err(MriError::InvalidParameter(format!(
+                "radial trajectories need at least 2 channels, found {}",
+                num_channels
+            )));
         }
+        let positions = vec![vec![0.0; num_channels]; num_projections];
+        let directions = spoke_angles(num_projections, ordering)?
+            .iter()
+            .map(|theta| {
+                let mut dir = vec![0.0; num_channels];
+                dir[0] = theta.cos();
+                dir[1] = theta.sin();
+                dir
+            }).collect();
+        Ok(KSpaceParameterizedProjections::from_spokes(
+            fov, positions, directions, samples,
+        ))
+    }
+
+    /// 3D radial (koosh ball) trajectory with `num_projections` spokes
+    pub fn radial_3d(
+        fov: f64,
+        num_projections: usize,
+        samples: usize,
+        dist: SpokeDistribution,
+    ) -> Self {
+        KSpaceParameterizedProjections::try_radial_3d(fov, num_projections, samples, dist)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// 3D radial trajectory, failing on invalid parameters
+    pub fn try_radial_3d(
+        fov: f64,
+        num_projections: usize,
+        samples: usize,
+        dist: SpokeDistribution,
+    ) -> Result<Self> {
+        check_radial(fov, samples, num_projections)?;
+        let positions = vec![vec![0.0; 3]; num_projections];
+        let directions = spoke_directions_3d(num_projections, dist);
+        Ok(KSpaceParameterizedProjections::from_spokes(
+            fov, positions, directions, samples,
+        ))
+    }
+
+    /// stack-of-stars trajectory with `samples` samples on `num_projections` spokes in the xy
+    /// plane in the given order on each of `partitions` Cartesian partitions for the slab
+    /// thickness `fov_z`. The projections are ordered by angle and then by partition.
+    pub fn stack_of_stars(
+        fov: f64,
+        fov_z: f64,
+        samples: usize,
+        num_projections: usize,
+        partitions: usize,
+        ordering: SpokeOrdering,
+    ) -> Self {
+        KSpaceParameterizedProjections::try_stack_of_stars(
+            fov,
+            fov_z,
+            samples,
+            num_projections,
+            partitions,
+            ordering,
+        ).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// stack-of-stars trajectory, failing on invalid parameters
+    pub fn try_stack_of_stars(
+        fov: f64,
+        fov_z: f64,
+        samples: usize,
+        num_projections: usize,
+        partitions: usize,
+        ordering: SpokeOrdering,
+    ) -> Result<Self> {
+        check_radial(fov, samples, num_projections)?;
+        let (positions, directions) =
+            stack_of_stars_spokes(fov_z, num_projections, partitions, ordering)?
+                .into_iter()
+                .unzip();
+        Ok(KSpaceParameterizedProjections::from_spokes(
+            fov, positions, directions, samples,
+        ))
+    }
+
+    /// Return the projections with the indices `units` in the given order, including their
+    /// acquisition times
+    pub fn select(&self, units: &[usize]) -> Result<Self> {
+        for &u in units {
+            check_index(u, self.num_projections)?;
+        }
+        let mut out = self.clone();
+        out.positions = units.iter().map(|&u| self.positions[u].clone()).collect();
+        out.directions = units.iter().map(|&u| self.directions[u].clone()).collect();
+        out.times = units.iter().map(|&u| self.times[u].clone()).collect();
+        out.num_projections = units.len();
+        out.num_samples = units.len() * self.num_samples_per_spoke;
+        Ok(out)
+    }
+
+    /// Return the indices in `samples` of the samples of the projections with the indices
+    /// `units`, e.g. to select the data belonging to `select(units)`
+    pub fn sample_indices(&self, units: &[usize]) -> Result<Vec<usize>> {
+        let n = self.num_samples_per_spoke;
+        let mut out = Vec::with_capacity(units.len() * n);
+        for &u in units {
+            check_index(u, self.num_projections)?;
+            out.extend(u * n..(u + 1) * n);
+        }
+        Ok(out)
+    }
+
+    /// Split the projections into frames of `size` consecutive projections, starting every
+    /// `step` projections, see `frame_ranges`
+    pub fn frames(&self, size: usize, step: usize) -> Result<Vec<Self>> {
+        frame_ranges(self.num_projections, size, step)?
+            .into_iter()
+            .map(|r| self.select(&r.collect::<Vec<usize>>()))
+            .collect()
+    }
+
+    /// Create a trajectory from the positions and directions of spokes with `samples` samples
+    /// spaced by `1/fov`
+    fn from_spokes(
+        fov: f64,
+        positions: Vec<Vec<f64>>,
+        directions: Vec<Vec<f64>>,
+        samples: usize,
+    ) -> Self {
+        let num_projections = positions.len();
+        let nx2 = if samples.is_even() {
+            (samples / 2) as i64
+        } else {
+            ((samples - 1) / 2) as i64
+        };
+        KSpaceParameterizedProjections {
+            num_channels: positions.first().map_or(0, |p| p.len()),
+            positions,
+            directions,
+            num_samples: samples * num_projections,
+            num_samples_per_spoke: samples,
+            num_projections,
+            dk: 1. / fov,
+            limits: vec![-165.0, 165.0],
+            spoke_ind: (0..samples).map(|ii| -nx2 + ii as i64).collect(),
+            times: vec![None; num_projections],
+        }
+    }
+
+    /// Check that position and direction have the number of channels of this trajectory
+    fn check_unit(&self, unit: &(Vec<f64>, Vec<f64>)) -> Result<()> {
+        check_dims(self.num_channels, unit.0.len())?;
+        check_dims(self.num_channels, unit.1.len())
     }
 
-    fn calc_projection(&self, pos: &Vec<f64>, dir: &Vec<f64>) -> Vec<KSample> {
+    /// Check whether the projection of a unit lies within the limits
+    fn inside(&self, unit: &(Vec<f64>, Vec<f64>)) -> bool {
+        self.calc_projection(&unit.0, &unit.1)
+            .iter()
+            .flat_map(|arr| arr.iter())
+            .all(|&x| x < self.limits[1] && x > self.limits[0])
+    }
+
+    fn calc_projection(&self, pos: &[f64], dir: &[f64]) -> Vec<KSample> {
         let mut out = Vec::with_capacity(self.num_samples_per_spoke);
         for i in 0..self.num_samples_per_spoke {
             let mut sample = Vec::with_capacity(self.num_channels);
@@ -392,30 +1133,65 @@ impl KSpaceThings for KSpaceParameterizedProjections {
     type KUnit = (Vec<f64>, Vec<f64>);
 
     /// Add a single k-space sample point to an existing trajectory
-    fn add(&mut self, sample: Self::KUnit) -> &mut Self {
+    fn try_add(&mut self, sample: Self::KUnit) -> Result<&mut Self> {
+        self.check_unit(&sample)?;
         self.positions.push(sample.0);
         self.directions.push(sample.1);
-        self
+        self.times.resize(self.num_projections, None);
+        self.times.push(None);
+        self.num_projections += 1;
+        self.num_samples += self.num_samples_per_spoke;
+        Ok(self)
     }
 
     /// Return sample at position `idx`
-    fn sample_at(&self, idx: usize) -> Self::KUnit {
-        (self.positions[idx].clone(), self.directions[idx].clone())
+    fn try_sample_at(&self, idx: usize) -> Result<Self::KUnit> {
+        check_index(idx, self.num_projections)?;
+        Ok((self.positions[idx].clone(), self.directions[idx].clone()))
     }
 
     /// Set a sample at a specific position
-    fn set_sample(&mut self, idx: usize, sample: Self::KUnit) -> &mut Self {
-        let spoke = self.calc_projection(&sample.0, &sample.1);
-        let inside: bool = spoke
-            .iter()
-            .flat_map(|arr| arr.iter())
-            .map(|&x| x < self.limits[1] && x > self.limits[0])
-            .fold(true, |arr, x| arr && x);
-        if inside {
+    fn try_set_sample(&mut self, idx: usize, sample: Self::KUnit) -> Result<&mut Self> {
+        check_index(idx, self.num_projections)?;
+        self.check_unit(&sample)?;
+        if self.inside(&sample) {
             self.positions[idx] = sample.0;
             self.directions[idx] = sample.1;
         }
-        self
+        Ok(self)
+    }
+
+    /// Add a projection acquired at the given times
+    fn try_add_timed(&mut self, sample: Self::KUnit, timing: SampleTiming) -> Result<&mut Self> {
+        let times = timing.times(self.num_samples_per_spoke)?;
+        self.try_add(sample)?;
+        self.times[self.num_projections - 1] = Some(times);
+        Ok(self)
+    }
+
+    /// Set a projection and its acquisition times at a specific position
+    fn try_set_sample_timed(
+        &mut self,
+        idx: usize,
+        sample: Self::KUnit,
+        timing: SampleTiming,
+    ) -> Result<&mut Self> {
+        check_index(idx, self.num_projections)?;
+        self.check_unit(&sample)?;
+        let times = timing.times(self.num_samples_per_spoke)?;
+        if self.inside(&sample) {
+            self.positions[idx] = sample.0;
+            self.directions[idx] = sample.1;
+            self.times[idx] = Some(times);
+        }
+        Ok(self)
+    }
+
+    /// Set the acquisition times of the projection at `idx`
+    fn try_set_timing(&mut self, idx: usize, timing: SampleTiming) -> Result<&mut Self> {
+        check_index(idx, self.num_projections)?;
+        self.times[idx] = Some(timing.times(self.num_samples_per_spoke)?);
+        Ok(self)
     }
 
     /// Return the number of channels
@@ -451,4 +1227,504 @@ impl KSpaceThings for KSpaceParameterizedProjections {
         }
         out
     }
+    fn sample_times(&self) -> Option<Vec<f64>> {
+        flatten_times(&self.times, self.num_projections)
+    }
+}
+
+/// Check the parameters of radial trajectories
+fn check_radial(fov: f64, samples: usize, spokes: usize) -> Result<()> {
+    check_fov(fov)?;
+    if samples == 0 {
+        return Err(MriError::EmptyProjection);
+    }
+    if spokes == 0 {
+        return Err(MriError::InvalidParameter(
+            "number of spokes must be positive".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Positions of the samples of a spoke in units of the sample spacing, with the center of
+/// k-space at sample `samples / 2`
+fn spoke_offsets(samples: usize) -> Vec<f64> {
+    let nx2 = if samples.is_even() {
+        (samples as f64) / 2.0
+    } else {
+        (samples - 1) as f64 / 2.0
+    };
+    (0..samples).map(|ii| -nx2 + ii as f64).collect()
+}
+
+/// Samples of a spoke through `pos` along `dir`
+fn spoke(pos: &[f64], dir: &[f64], dk: f64, offsets: &[f64]) -> KProjection {
+    offsets
+        .iter()
+        .map(|o| pos.iter().zip(dir).map(|(p, d)| p + o * dk * d).collect())
+        .collect()
+}
+
+/// Ranges of the units of frames with `size` consecutive units out of `num_units`, starting every
+/// `step` units. `step == size` results in consecutive frames, `step < size` in a sliding
+/// window. Incomplete frames at the end are dropped.
+pub fn frame_ranges(num_units: usize, size: usize, step: usize) -> Result<Vec<Range<usize>>> {
+    if size == 0 || step == 0 {
+        return Err(MriError::InvalidParameter(
+            "frame size and step must be positive".to_string(),
+        ));
+    }
+    Ok((0..)
+        .map(|i| i * step)
+        .take_while(|start| start + size <= num_units)
+        .map(|start| start..start + size)
+        .collect())
+}
+
+/// In-plane angles of `num` spokes
+fn spoke_angles(num: usize, ordering: SpokeOrdering) -> Result<Vec<f64>> {
+    let phi = (1.0 + 5f64.sqrt()) / 2.0;
+    let increment = match ordering {
+        SpokeOrdering::Uniform => PI / (num as f64),
+        SpokeOrdering::GoldenAngle => PI / phi,
+        SpokeOrdering::TinyGoldenAngle(n) if n > 0 => PI / (phi + (n - 1) as f64),
+        SpokeOrdering::TinyGoldenAngle(_) => {
+            return Err(MriError::InvalidParameter(
+                "tiny golden angles start at 1".to_string(),
+            ))
+        }
+    };
+    Ok((0..num)
+        .map(|i| ((i as f64) * increment) % (2.0 * PI))
+        .collect())
+}
+
+/// Unit directions of `num` spokes covering the upper hemisphere
+fn spoke_directions_3d(num: usize, dist: SpokeDistribution) -> Vec<Vec<f64>> {
+    let n = num as f64;
+    let direction = |theta: f64, phi: f64| {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        vec![sin_theta * cos_phi, sin_theta * sin_phi, cos_theta]
+    };
+    match dist {
+        SpokeDistribution::Phyllotaxis => {
+            let golden_angle = PI * (3.0 - 5f64.sqrt());
+            (0..num)
+                .map(|i| {
+                    let theta = FRAC_PI_2 * ((i as f64) / n).sqrt();
+                    direction(theta, (i as f64) * golden_angle % (2.0 * PI))
+                }).collect()
+        }
+        SpokeDistribution::SaffKuijlaars => {
+            // every spoke covers two antipodal points of a sphere with 2 num points
+            let mut phi = 0.0;
+            (0..num)
+                .map(|i| {
+                    let z = 1.0 - (i as f64 + 0.5) / n;
+                    if i > 0 {
+                        phi = (phi + 3.6 / (2.0 * n * (1.0 - z * z)).sqrt()) % (2.0 * PI);
+                    }
+                    direction(z.acos(), phi)
+                }).collect()
+        }
+        SpokeDistribution::GoldenMeans => {
+            // eigenvalues of the modified Fibonacci matrix in Chan et al.
+            let (phi1, phi2) = (0.465_571_231_876_768, 0.682_327_803_828_019);
+            (0..num)
+                .map(|i| {
+                    let z = ((i as f64) * phi1).fract();
+                    direction(z.acos(), 2.0 * PI * ((i as f64) * phi2).fract())
+                }).collect()
+        }
+    }
+}
+
+/// Position and direction of stack-of-stars spokes, ordered by angle and then by partition
+fn stack_of_stars_spokes(
+    fov_z: f64,
+    spokes: usize,
+    partitions: usize,
+    ordering: SpokeOrdering,
+) -> Result<Vec<(Vec<f64>, Vec<f64>)>> {
+    check_fov(fov_z)?;
+    if partitions == 0 {
+        return Err(MriError::InvalidParameter(
+            "number of partitions must be positive".to_string(),
+        ));
+    }
+    let kz = spoke_offsets(partitions);
+    let mut out = Vec::with_capacity(spokes * partitions);
+    for theta in spoke_angles(spokes, ordering)? {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        for z in &kz {
+            out.push((vec![0.0, 0.0, z / fov_z], vec![cos_theta, sin_theta, 0.0]));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_kspace() {
+        assert_eq!(
+            KSpace::try_cartesian(SpatialDims::TwoD(0.2, 0.2), SpatialDims::OneD(8)).err(),
+            Some(MriError::DimensionMismatch {
+                expected: 1,
+                found: 2,
+            })
+        );
+        assert!(matches!(
+            KSpace::try_cartesian(SpatialDims::TwoD(0.2, f64::NAN), SpatialDims::TwoD(8, 8)),
+            Err(MriError::InvalidFov(_))
+        ));
+        assert!(matches!(
+            KSpace::try_cartesian(SpatialDims::OneD(0.2), SpatialDims::OneD(0)),
+            Err(MriError::InvalidParameter(_))
+        ));
+
+        let mut k = KSpace::cartesian(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(2, 2));
+        assert_eq!(
+            k.try_add(vec![0.0; 3]).err(),
+            Some(MriError::DimensionMismatch {
+                expected: 2,
+                found: 3,
+            })
+        );
+        assert_eq!(k.try_add(vec![]).err(), Some(MriError::EmptyProjection));
+        assert_eq!(
+            k.try_sample_at(4).err(),
+            Some(MriError::IndexOutOfRange { index: 4, len: 4 })
+        );
+        assert_eq!(
+            k.try_set_sample(7, vec![0.0; 2]).err(),
+            Some(MriError::IndexOutOfRange { index: 7, len: 4 })
+        );
+        assert_eq!(
+            k.try_set_sample(0, vec![0.0]).err(),
+            Some(MriError::DimensionMismatch {
+                expected: 2,
+                found: 1,
+            })
+        );
+        assert_eq!(k.num_samples(), 4);
+    }
+
+    #[test]
+    fn radial_sample_count() {
+        // every spoke misses its center point
+        let mut k = KSpaceProjections::radial(0.2, 16, 5);
+        assert_eq!(k.num_samples(), 15 * 5);
+        assert_eq!(k.num_samples(), k.samples().len());
+        k.try_set_sample(2, vec![vec![0.0, 0.0]; 4]).unwrap();
+        assert_eq!(k.num_samples(), 15 * 4 + 4);
+        assert_eq!(k.num_samples(), k.samples().len());
+        let s = k.select(&[0, 2]).unwrap();
+        assert_eq!(s.num_samples(), 19);
+        assert_eq!(s.num_samples(), s.samples().len());
+        assert!(matches!(
+            KSpaceProjections::try_radial(0.0, 16, 5),
+            Err(MriError::InvalidFov(_))
+        ));
+        assert_eq!(
+            KSpaceProjections::try_radial(0.2, 1, 5).err(),
+            Some(MriError::EmptyProjection)
+        );
+    }
+
+    #[test]
+    fn kspace_dwell() {
+        let mut k = KSpace::cartesian(SpatialDims::OneD(0.2), SpatialDims::OneD(8));
+        assert_eq!(k.sample_times(), None);
+        k.try_set_times(SampleTiming::Dwell {
+            start: 1e-3,
+            dwell: 1e-5,
+        })
+        .unwrap();
+        let times = k.sample_times().unwrap();
+        assert_eq!(times.len(), 8);
+        for (i, t) in times.iter().enumerate() {
+            assert!((t - (1e-3 + (i as f64) * 1e-5)).abs() < 1e-15);
+        }
+
+        // replacing a sample keeps its time
+        k.try_set_sample(3, vec![0.5]).unwrap();
+        assert_eq!(k.sample_times().unwrap(), times);
+        k.try_set_timing(3, SampleTiming::PerSample(vec![2.0]))
+            .unwrap();
+        assert_eq!(k.sample_times().unwrap()[3], 2.0);
+
+        assert!(k.try_set_times(SampleTiming::PerSample(vec![0.0; 7])).is_err());
+        assert!(k
+            .try_set_times(SampleTiming::Dwell {
+                start: 0.0,
+                dwell: f64::NAN,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn kspace_add_timed() {
+        let mut k = KSpace::new();
+        for i in 0..4 {
+            k.try_add_timed(vec![i as f64, 0.0], SampleTiming::PerSample(vec![i as f64]))
+                .unwrap();
+        }
+        assert_eq!(k.sample_times(), Some(vec![0.0, 1.0, 2.0, 3.0]));
+        assert!(k
+            .try_add_timed(vec![0.0], SampleTiming::PerSample(vec![4.0]))
+            .is_err());
+        assert_eq!(k.num_samples(), 4);
+        assert_eq!(k.sample_times(), Some(vec![0.0, 1.0, 2.0, 3.0]));
+
+        // untimed samples cannot be added to a timed trajectory
+        assert!(matches!(
+            k.try_add(vec![4.0, 0.0]),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert_eq!(k.kspace().len(), 4);
+        assert_eq!(k.sample_times(), Some(vec![0.0, 1.0, 2.0, 3.0]));
+
+        // nor timed samples to an untimed one
+        let mut k = KSpace::cartesian(SpatialDims::OneD(0.2), SpatialDims::OneD(4));
+        k.try_add(vec![4.0]).unwrap();
+        assert_eq!(k.sample_times(), None);
+        assert!(k
+            .try_add_timed(vec![5.0], SampleTiming::PerSample(vec![5.0]))
+            .is_err());
+        assert!(k
+            .try_set_timing(0, SampleTiming::PerSample(vec![0.0]))
+            .is_err());
+        assert_eq!(k.kspace().len(), 5);
+    }
+
+    #[test]
+    fn stack_of_stars_types_agree() {
+        let (samples, spokes, partitions) = (16, 5, 3);
+        let a = KSpaceProjections::stack_of_stars(
+            0.2,
+            0.1,
+            samples,
+            spokes,
+            partitions,
+            SpokeOrdering::GoldenAngle,
+        );
+        let b = KSpaceParameterizedProjections::stack_of_stars(
+            0.2,
+            0.1,
+            samples,
+            spokes,
+            partitions,
+            SpokeOrdering::GoldenAngle,
+        );
+        assert_eq!(a.num_units(), spokes * partitions);
+        assert_eq!(b.num_units(), spokes * partitions);
+        let (a, b) = (a.samples(), b.samples());
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            for (xi, yi) in x.iter().zip(y.iter()) {
+                assert!((xi - yi).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn golden_angle_increments() {
+        let phi = (1.0 + 5f64.sqrt()) / 2.0;
+        let golden = spoke_angles(50, SpokeOrdering::GoldenAngle).unwrap();
+        assert_eq!(golden, spoke_angles(50, SpokeOrdering::TinyGoldenAngle(1)).unwrap());
+        assert!((PI / phi - 111.246_117_975f64.to_radians()).abs() < 1e-9);
+        for &(ordering, increment) in &[
+            (SpokeOrdering::Uniform, PI / 50.0),
+            (SpokeOrdering::GoldenAngle, PI / phi),
+            (SpokeOrdering::TinyGoldenAngle(5), PI / (phi + 4.0)),
+        ] {
+            let angles = spoke_angles(50, ordering).unwrap();
+            assert_eq!(angles[0], 0.0);
+            for w in angles.windows(2) {
+                assert!(w[1] >= 0.0 && w[1] < 2.0 * PI);
+                let d = (w[1] - w[0]).rem_euclid(2.0 * PI);
+                assert!((d - increment).abs() < 1e-9);
+            }
+        }
+        assert!(spoke_angles(50, SpokeOrdering::TinyGoldenAngle(0)).is_err());
+
+        // the spokes of the trajectory point along the angles
+        let k = KSpaceProjections::radial_ordered(0.2, 16, 50, SpokeOrdering::GoldenAngle);
+        for (p, theta) in k.projections.iter().zip(golden.iter()) {
+            let last = &p[p.len() - 1];
+            let angle = last[1].atan2(last[0]).rem_euclid(2.0 * PI);
+            let d = (angle - theta).abs();
+            assert!(d < 1e-9 || (d - 2.0 * PI).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn frames_cover_every_spoke() {
+        let spokes = 30;
+        let mut k = KSpaceProjections::radial_ordered(0.2, 16, spokes, SpokeOrdering::GoldenAngle);
+        for i in 0..spokes {
+            k.try_set_timing(
+                i,
+                SampleTiming::Dwell {
+                    start: (i as f64) * 1e-3,
+                    dwell: 1e-5,
+                },
+            ).unwrap();
+        }
+
+        // consecutive frames partition the spokes
+        let frames = k.frames(5, 5).unwrap();
+        assert_eq!(frames.len(), 6);
+        let samples: Vec<KSample> = frames.iter().flat_map(|f| f.samples()).collect();
+        assert_eq!(samples, k.samples());
+        let times: Vec<f64> = frames
+            .iter()
+            .flat_map(|f| f.sample_times().unwrap())
+            .collect();
+        assert_eq!(times, k.sample_times().unwrap());
+
+        // a sliding window covers every spoke in up to size / step frames
+        let ranges = frame_ranges(spokes, 6, 2).unwrap();
+        assert_eq!(ranges.len(), 13);
+        let mut count = vec![0; spokes];
+        for r in ranges {
+            for u in r {
+                count[u] += 1;
+            }
+        }
+        assert!(count.iter().all(|&c| (1..=3).contains(&c)));
+        assert_eq!(count[spokes / 2], 3);
+
+        // incomplete frames are dropped
+        assert_eq!(frame_ranges(spokes, 7, 7).unwrap(), vec![0..7, 7..14, 14..21, 21..28]);
+        assert!(frame_ranges(spokes, 0, 1).is_err());
+        assert!(frame_ranges(spokes, 1, 0).is_err());
+
+        let p = KSpaceParameterizedProjections::radial_ordered(
+            0.2,
+            spokes,
+            2,
+            16,
+            SpokeOrdering::GoldenAngle,
+        );
+        let frames = p.frames(10, 10).unwrap();
+        assert_eq!(frames.len(), 3);
+        let samples: Vec<KSample> = frames.iter().flat_map(|f| f.samples()).collect();
+        assert_eq!(samples, p.samples());
+    }
+
+    #[test]
+    fn spiral_geometry() {
+        let (fov, res) = (0.2, 0.005);
+        let kmax = 1.0 / (2.0 * res);
+        for &(interleaves, alpha) in &[(1, 1.0), (4, 1.0), (3, 2.0), (8, 0.5)] {
+            let k = KSpaceProjections::spiral_variable_density(fov, res, interleaves, alpha);
+            assert_eq!(k.num_units(), interleaves);
+            assert_eq!(k.num_samples(), k.samples().len());
+            for (i, p) in k.projections.iter().enumerate() {
+                assert_eq!(p[0], vec![0.0, 0.0]);
+                // equally rotated interleaves ending at the resolution
+                let last = &p[p.len() - 1];
+                assert!((last[0].hypot(last[1]) - kmax).abs() < 1e-9);
+                let first = &k.projections[0][p.len() - 1];
+                let rotation = (last[1].atan2(last[0]) - first[1].atan2(first[0]))
+                    .rem_euclid(2.0 * PI);
+                let expected = 2.0 * PI * (i as f64) / (interleaves as f64);
+                let d = (rotation - expected).abs();
+                assert!(d < 1e-9 || (d - 2.0 * PI).abs() < 1e-9);
+                // readouts sampled at the Nyquist rate
+                for w in p.windows(2) {
+                    assert!((w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1]) <= (1.0 + 1e-9) / fov);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn archimedean_spiral_turns() {
+        // at the edge of k-space, the next interleaf in the direction of the end of the first
+        // one is one turn distance `1/fov` further in
+        let (fov, res) = (0.2, 0.005);
+        let kmax = 1.0 / (2.0 * res);
+        for &interleaves in &[1, 4, 6] {
+            let k = KSpaceProjections::spiral(fov, res, interleaves);
+            let end = &k.projections[0][k.projections[0].len() - 1];
+            let direction = end[1].atan2(end[0]);
+            let neighbour = &k.projections[1 % interleaves];
+            // radius of the outermost crossing of `direction` inside the end of the first one
+            let radius = neighbour
+                .windows(2)
+                .filter_map(|w| {
+                    let a0 = w[0][1].atan2(w[0][0]) - direction;
+                    let a1 = w[1][1].atan2(w[1][0]) - direction;
+                    if a0.cos() > 0.0 && a0.sin() <= 0.0 && a1.sin() > 0.0 {
+                        let t = a0.sin() / (a0.sin() - a1.sin());
+                        let r0 = w[0][0].hypot(w[0][1]);
+                        Some(r0 + t * (w[1][0].hypot(w[1][1]) - r0))
+                    } else {
+                        None
+                    }
+                }).filter(|&r| r < kmax - 0.5 / fov)
+                .fold(0.0, f64::max);
+            assert!((kmax - radius - 1.0 / fov).abs() < 0.01 / fov);
+        }
+    }
+
+    #[test]
+    fn invalid_spirals() {
+        for &res in &[0.0, -0.005, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                KSpaceProjections::try_spiral_variable_density(0.2, res, 4, 1.0),
+                Err(MriError::InvalidParameter(_))
+            ));
+        }
+        for &alpha in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                KSpaceProjections::try_spiral_variable_density(0.2, 0.005, 4, alpha),
+                Err(MriError::InvalidParameter(_))
+            ));
+        }
+        assert!(matches!(
+            KSpaceProjections::try_spiral(0.2, 0.005, 0),
+            Err(MriError::InvalidParameter(_))
+        ));
+        assert_eq!(
+            KSpaceProjections::try_spiral(0.0, 0.005, 4).err(),
+            Some(MriError::InvalidFov(0.0))
+        );
+    }
+
+    #[test]
+    fn spoke_directions_cover_hemisphere() {
+        for &dist in &[
+            SpokeDistribution::Phyllotaxis,
+            SpokeDistribution::SaffKuijlaars,
+            SpokeDistribution::GoldenMeans,
+        ] {
+            for &num in &[10, 50, 200] {
+                let dirs = spoke_directions_3d(num, dist);
+                assert_eq!(dirs.len(), num);
+                for d in &dirs {
+                    let norm: f64 = d.iter().map(|x| x * x).sum();
+                    assert!((norm - 1.0).abs() < 1e-12);
+                    assert!(d[2] >= 0.0);
+                }
+                // angle between the lines of two spokes, compared to the spacing of `num`
+                // points spread uniformly over a hemisphere
+                let mut min_angle = PI;
+                for (i, a) in dirs.iter().enumerate() {
+                    for b in &dirs[..i] {
+                        let cos: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+                        min_angle = min_angle.min(cos.abs().min(1.0).acos());
+                    }
+                }
+                let spacing = (2.0 * PI / num as f64).sqrt();
+                assert!(min_angle > 0.25 * spacing, "{:?} {}", dist, num);
+            }
+        }
+    }
 }
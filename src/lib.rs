@@ -11,41 +11,51 @@
 #![cfg_attr(feature = "clippy", plugin(clippy))]
 #![warn(missing_docs)]
 
+#[cfg(feature = "hdf5")]
+extern crate hdf5;
 extern crate num;
+extern crate rustfft;
 
+pub mod coil;
+pub mod cs;
+pub mod dcf;
 pub mod encodingfield;
+pub mod encodingmatrix;
+pub mod epi;
+pub mod error;
+pub mod gradient;
+pub mod ismrmrd;
 pub mod kspace;
+mod linalg;
 pub mod localkspace;
+pub mod nifti;
+pub mod nufft;
+pub mod offresonance;
+pub mod phantom;
+mod random;
 pub mod rf;
+pub mod sense;
+pub mod simulate;
 pub mod spatialdims;
+pub mod sphericalharmonics;
+pub mod undersampling;
 
 pub use encodingfield::EncodingField;
+pub use encodingmatrix::EncodingMatrix;
+pub use error::MriError;
 pub use kspace::KSample;
 pub use kspace::KSpace;
 pub use kspace::KSpaceParameterizedProjections;
 pub use kspace::KSpaceProjections;
 pub use kspace::KSpaceThings;
+pub use kspace::SampleTiming;
+pub use kspace::SpokeDistribution;
+pub use kspace::SpokeOrdering;
 pub use localkspace::LocalKSpace;
+pub use phantom::Phantom;
 pub use rf::RFSensitivity;
 pub use spatialdims::SpatialDims;
 
-// /// todo
-// pub struct EncodingMatrix {
-//     /// kspace
-//     k: KSpace,
-//     /// vector of encoding fields
-//     psi: Vec<EncodingField>,
-//     //// rf sensitivity maps
-//     rf: Vec<RFSensitivity>,
-// }
-
-// impl EncodingMatrix {
-//     /// todo
-//     pub fn new(k: KSpace, psi: Vec<EncodingField>, rf: Vec<RFSensitivity>) -> Self {
-//         EncodingMatrix { k, psi, rf }
-//     }
-// }
-
 // #[cfg(test)]
 // mod tests {
 //     #[test]
@@ -0,0 +1,20 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Vector operations shared by the iterative reconstructions
+
+use num::Complex;
+
+/// `a^H b`
+pub(crate) fn dot(a: &[Complex<f64>], b: &[Complex<f64>]) -> Complex<f64> {
+    a.iter().zip(b.iter()).map(|(x, y)| x.conj() * y).sum()
+}
+
+/// squared norm
+pub(crate) fn norm_sqr(a: &[Complex<f64>]) -> f64 {
+    a.iter().map(|x| x.norm_sqr()).sum()
+}
@@ -7,11 +7,28 @@
 
 //! Local k-Space
 
+use error::{check_dims, Result};
 use EncodingField;
+use KSample;
 use KSpace;
 use KSpaceThings;
 use SpatialDims;
 
+/// Coverage of the local k-space at a single position
+#[derive(Debug, Clone)]
+pub struct LocalKSpaceCoverage {
+    /// smallest local k per axis
+    pub min: SpatialDims<f64>,
+    /// largest local k per axis
+    pub max: SpatialDims<f64>,
+    /// extent (`max - min`) per axis
+    pub extent: SpatialDims<f64>,
+    /// maximum |k| per axis
+    pub kmax: SpatialDims<f64>,
+    /// implied local resolution `1 / (2 * kmax)` per axis (infinite where `kmax` is zero)
+    pub resolution: SpatialDims<f64>,
+}
+
 /// Local k-Space
 pub struct LocalKSpace<T>
 where
@@ -19,23 +36,36 @@ where
 {
     /// Actual k-Space
     kspace: T,
+    /// samples of the k-space
+    samples: Vec<KSample>,
     /// fields
     fields: Vec<EncodingField>,
 }
 
 impl<T: KSpaceThings + Clone> LocalKSpace<T> {
     /// Create new local k-space object
-    pub fn new(kspace: &T, fields: &Vec<EncodingField>) -> Self {
-        assert!(kspace.num_channels() == fields.len());
-        LocalKSpace {
+    pub fn new(kspace: &T, fields: &[EncodingField]) -> Self {
+        LocalKSpace::try_new(kspace, fields).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create new local k-space object, failing if there is not one field per k-space channel
+    pub fn try_new(kspace: &T, fields: &[EncodingField]) -> Result<Self> {
+        check_dims(kspace.num_channels(), fields.len())?;
+        Ok(LocalKSpace {
             kspace: kspace.clone(),
-            fields: fields.clone(),
-        }
+            samples: kspace.samples(),
+            fields: fields.to_vec(),
+        })
     }
 
-    /// return local k space a certain position
-    pub fn at(&self, pos: &SpatialDims<f64>) -> KSpace {
-        let derivs: Vec<SpatialDims<f64>> = self.fields.iter().map(|x| x.deriv_at(&pos)).collect();
+    /// Return the underlying k-space
+    pub fn kspace(&self) -> &T {
+        &self.kspace
+    }
+
+    /// local k-space samples at a certain position (`[sample][axis]`)
+    fn local_samples(&self, pos: &SpatialDims<f64>) -> Vec<Vec<f64>> {
+        let derivs: Vec<SpatialDims<f64>> = self.fields.iter().map(|x| x.deriv_at(pos)).collect();
         let mut grad: Vec<Vec<f64>> = Vec::with_capacity(pos.len());
         grad.push(derivs.iter().map(|x| x.x().unwrap()).collect());
         if pos.len() > 1 {
@@ -45,19 +75,154 @@ impl<T: KSpaceThings + Clone> LocalKSpace<T> {
             grad.push(derivs.iter().map(|x| x.z().unwrap()).collect());
         }
 
-        let mut out = KSpace::new();
-        let tmp: Vec<Vec<f64>> = self
-            .kspace
-            .samples()
+        self.samples
             .iter()
             .map(|x| {
                 grad.iter()
                     .map(|c| x.iter().zip(c.iter()).map(|(a, b)| a * b).sum())
                     .collect()
-            }).collect();
-        for x in tmp {
+            }).collect()
+    }
+
+    /// return local k space a certain position
+    pub fn at(&self, pos: &SpatialDims<f64>) -> KSpace {
+        let mut out = KSpace::new();
+        for x in self.local_samples(pos) {
             out.add(x);
         }
         out
     }
+
+    /// return the coverage of the local k-space at a certain position
+    pub fn coverage_at(&self, pos: &SpatialDims<f64>) -> LocalKSpaceCoverage {
+        let local = self.local_samples(pos);
+        let n = pos.len();
+        let mut min = vec![0.0; n];
+        let mut max = vec![0.0; n];
+        if let Some(first) = local.first() {
+            min.copy_from_slice(first);
+            max.copy_from_slice(first);
+        }
+        for s in &local {
+            for d in 0..n {
+                min[d] = s[d].min(min[d]);
+                max[d] = s[d].max(max[d]);
+            }
+        }
+        let kmax: Vec<f64> = min
+            .iter()
+            .zip(max.iter())
+            .map(|(a, b)| a.abs().max(b.abs()))
+            .collect();
+        let extent: Vec<f64> = min.iter().zip(max.iter()).map(|(a, b)| b - a).collect();
+        let resolution: Vec<f64> = kmax.iter().map(|k| 1.0 / (2.0 * k)).collect();
+        LocalKSpaceCoverage {
+            min: SpatialDims::from_slice(&min),
+            max: SpatialDims::from_slice(&max),
+            extent: SpatialDims::from_slice(&extent),
+            kmax: SpatialDims::from_slice(&kmax),
+            resolution: SpatialDims::from_slice(&resolution),
+        }
+    }
+
+    /// return the coverage of the local k-space of every voxel of a grid with `dims` voxels
+    /// covering `fov` (in the order of `SpatialDims::grid`)
+    pub fn coverage(
+        &self,
+        fov: &SpatialDims<f64>,
+        dims: &SpatialDims<usize>,
+    ) -> Vec<LocalKSpaceCoverage> {
+        fov.grid(dims).iter().map(|p| self.coverage_at(p)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn linear() -> Vec<EncodingField> {
+        vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap())),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap())),
+        ]
+    }
+
+    #[test]
+    fn linear_fields() {
+        let fov = SpatialDims::TwoD(0.2, 0.25);
+        let dims = SpatialDims::TwoD(8, 5);
+        let k = KSpace::cartesian(fov.clone(), dims.clone());
+        let local = LocalKSpace::new(&k, &linear());
+        // kx in -4..3 steps of 1/0.2, ky in -2..2 steps of 1/0.25
+        let (min, max, largest) = ([-20.0, -8.0], [15.0, 8.0], [20.0, 8.0]);
+        let coverage = local.coverage(&fov, &dims);
+        assert_eq!(coverage.len(), dims.product());
+        for c in coverage {
+            let axes = [
+                (c.min.x(), c.max.x(), c.kmax.x(), c.extent.x(), c.resolution.x()),
+                (c.min.y(), c.max.y(), c.kmax.y(), c.extent.y(), c.resolution.y()),
+            ];
+            for (d, &(lo, hi, kmax, extent, res)) in axes.iter().enumerate() {
+                assert!((lo.unwrap() - min[d]).abs() < 1e-9);
+                assert!((hi.unwrap() - max[d]).abs() < 1e-9);
+                assert!((kmax.unwrap() - largest[d]).abs() < 1e-9);
+                assert!((extent.unwrap() - (max[d] - min[d])).abs() < 1e-9);
+                assert!((res.unwrap() - 1.0 / (2.0 * kmax.unwrap())).abs() < 1e-12);
+            }
+        }
+        // the local k-space of linear fields is the trajectory itself
+        let at = local.at(&SpatialDims::TwoD(0.03, -0.07));
+        for (a, b) in at.samples().iter().zip(k.samples().iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn quadratic_field() {
+        let (a, b) = (3.0, -4.0);
+        let fields = vec![
+            EncodingField::new(Rc::new(move |pos: &SpatialDims<f64>| {
+                let x = pos.x().unwrap();
+                x + a * x * x
+            })),
+            EncodingField::new(Rc::new(move |pos: &SpatialDims<f64>| {
+                let (x, y) = (pos.x().unwrap(), pos.y().unwrap());
+                y + b * x * y
+            })),
+        ];
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(8, 8);
+        let k = KSpace::cartesian(fov.clone(), dims.clone());
+        let local = LocalKSpace::new(&k, &fields);
+        let corners = [(-20.0, -20.0), (-20.0, 15.0), (15.0, -20.0), (15.0, 15.0)];
+
+        let mut kmax_x = vec![];
+        for (pos, c) in fov.grid(&dims).iter().zip(local.coverage(&fov, &dims)) {
+            let (x, y) = (pos.x().unwrap(), pos.y().unwrap());
+            // local k = J^T k with the Jacobian of the fields
+            let local_k = |&(kx, ky): &(f64, f64)| {
+                (kx * (1.0 + 2.0 * a * x) + ky * b * y, ky * (1.0 + b * x))
+            };
+            let lk: Vec<(f64, f64)> = corners.iter().map(local_k).collect();
+            let min_x = lk.iter().map(|v| v.0).fold(f64::INFINITY, f64::min);
+            let max_x = lk.iter().map(|v| v.0).fold(f64::NEG_INFINITY, f64::max);
+            let max_y = lk.iter().map(|v| v.1.abs()).fold(0.0, f64::max);
+            assert!((c.min.x().unwrap() - min_x).abs() < 1e-6);
+            assert!((c.max.x().unwrap() - max_x).abs() < 1e-6);
+            assert!((c.kmax.x().unwrap() - min_x.abs().max(max_x.abs())).abs() < 1e-6);
+            assert!((c.kmax.y().unwrap() - max_y).abs() < 1e-6);
+            kmax_x.push(c.kmax.x().unwrap());
+        }
+        // the coverage varies with the position
+        let lo = kmax_x.iter().cloned().fold(f64::INFINITY, f64::min);
+        let hi = kmax_x.iter().cloned().fold(0.0, f64::max);
+        assert!(hi > 1.5 * lo);
+    }
+
+    #[test]
+    fn field_count() {
+        let k = KSpace::cartesian(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(4, 4));
+        assert!(LocalKSpace::try_new(&k, &linear()[..1]).is_err());
+    }
 }
@@ -0,0 +1,663 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! NIfTI-1 and NIfTI-2 files
+//!
+//! Single files (`.nii`) and header/image pairs (`.hdr`/`.img`) of either byte order can be
+//! read, compressed files are not supported. Files are written as single little endian files
+//! with `f64` voxels. Lengths are converted to m when reading and written in mm.
+
+use encodingfield::{Affine, EncodingFieldDiscrete};
+use error::{MriError, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use SpatialDims;
+
+/// Size of the NIfTI-1 header
+const NIFTI1_HEADER_SIZE: usize = 348;
+/// Size of the NIfTI-2 header
+const NIFTI2_HEADER_SIZE: usize = 540;
+
+/// NIfTI file format version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NiftiVersion {
+    /// NIfTI-1, 348 byte header with 16 bit dimensions
+    Nifti1,
+    /// NIfTI-2, 540 byte header with 64 bit dimensions
+    Nifti2,
+}
+
+/// An image read from or written to a NIfTI file
+#[derive(Debug, Clone)]
+pub struct NiftiImage {
+    /// number of voxels along every dimension
+    pub dims: Vec<usize>,
+    /// voxel size along every spatial dimension (m)
+    pub voxel_size: Vec<f64>,
+    /// rows of the affine transformation from voxel indices to world coordinates (m)
+    pub affine: Affine,
+    /// voxel values with `scl_slope` and `scl_inter` applied, x varies fastest
+    pub data: Vec<f64>,
+}
+
+impl NiftiImage {
+    /// Read a NIfTI-1 or NIfTI-2 file
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        if bytes.len() >= 2 && bytes[0] == 0x1f && bytes[1] == 0x8b {
+            return Err(MriError::InvalidFile(
+                "compressed NIfTI files are not supported".to_string(),
+            ));
+        }
+        let header = Header::parse(&bytes)?;
+        if header.single_file {
+            header.image(&bytes)
+        } else {
+            let mut image = vec![];
+            File::open(path.with_extension("img"))?.read_to_end(&mut image)?;
+            header.image(&image)
+        }
+    }
+
+    /// Write a single NIfTI file
+    pub fn write<P: AsRef<Path>>(&self, path: P, version: NiftiVersion) -> Result<()> {
+        if self.dims.is_empty() || self.dims.len() > 7 {
+            return Err(MriError::InvalidParameter(format!(
+                "NIfTI images have 1 to 7 dimensions, found {}",
+                self.dims.len()
+            )));
+        }
+        let num_voxels = num_voxels(&self.dims).ok_or_else(|| {
+            MriError::InvalidParameter(format!("too many voxels {:?}", self.dims))
+        })?;
+        if num_voxels != self.data.len() {
+            return Err(MriError::DimensionMismatch {
+                expected: num_voxels,
+                found: self.data.len(),
+            });
+        }
+        if version == NiftiVersion::Nifti1 && self.dims.iter().any(|&d| d > i16::MAX as usize) {
+            return Err(MriError::InvalidParameter(
+                "dimensions too large for NIfTI-1".to_string(),
+            ));
+        }
+
+        let mut dim = [1i64; 8];
+        dim[0] = self.dims.len() as i64;
+        for (d, &n) in dim[1..].iter_mut().zip(self.dims.iter()) {
+            *d = n as i64;
+        }
+        let mut pixdim = [1.0; 8];
+        for (p, &v) in pixdim[1..].iter_mut().zip(self.voxel_size.iter()) {
+            *p = v * 1e3;
+        }
+        let srow: Vec<[f64; 4]> = self
+            .affine
+            .iter()
+            .map(|r| [r[0] * 1e3, r[1] * 1e3, r[2] * 1e3, r[3] * 1e3])
+            .collect();
+
+        let mut w = Writer::new(match version {
+            NiftiVersion::Nifti1 => NIFTI1_HEADER_SIZE + 4,
+            NiftiVersion::Nifti2 => NIFTI2_HEADER_SIZE + 4,
+        });
+        match version {
+            NiftiVersion::Nifti1 => {
+                w.i32(0, NIFTI1_HEADER_SIZE as i32);
+                for (i, &d) in dim.iter().enumerate() {
+                    w.i16(40 + 2 * i, d as i16);
+                }
+                w.i16(70, DATATYPE_FLOAT64);
+                w.i16(72, 64);
+                for (i, &p) in pixdim.iter().enumerate() {
+                    w.f32(76 + 4 * i, p as f32);
+                }
+                w.f32(108, (NIFTI1_HEADER_SIZE + 4) as f32);
+                w.f32(112, 1.0);
+                w.u8(123, UNITS_MM);
+                w.i16(254, 1);
+                for (r, row) in srow.iter().enumerate() {
+                    for (c, &v) in row.iter().enumerate() {
+                        w.f32(280 + 16 * r + 4 * c, v as f32);
+                    }
+                }
+                w.bytes(344, b"n+1\0");
+            }
+            NiftiVersion::Nifti2 => {
+                w.i32(0, NIFTI2_HEADER_SIZE as i32);
+                w.bytes(4, b"n+2\0\r\n\x1a\n");
+                w.i16(12, DATATYPE_FLOAT64);
+                w.i16(14, 64);
+                for (i, &d) in dim.iter().enumerate() {
+                    w.i64(16 + 8 * i, d);
+                }
+                for (i, &p) in pixdim.iter().enumerate() {
+                    w.f64(104 + 8 * i, p);
+                }
+                w.i64(168, (NIFTI2_HEADER_SIZE + 4) as i64);
+                w.f64(176, 1.0);
+                w.i32(348, 1);
+                for (r, row) in srow.iter().enumerate() {
+                    for (c, &v) in row.iter().enumerate() {
+                        w.f64(400 + 32 * r + 8 * c, v);
+                    }
+                }
+                w.i32(500, i32::from(UNITS_MM));
+            }
+        }
+        let mut bytes = w.buf;
+        bytes.reserve(8 * self.data.len());
+        for v in &self.data {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        File::create(path)?.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Create an image of a discrete field with the affine of the field
+    pub fn from_field(field: &EncodingFieldDiscrete) -> Self {
+        let dims: Vec<usize> = field.dimensions().into_iter().collect();
+        let voxel_size: Vec<f64> = field
+            .fov()
+            .into_iter()
+            .zip(dims.iter())
+            .map(|(f, &n)| f / (n as f64))
+            .collect();
+        let affine = field.affine();
+        NiftiImage {
+            dims,
+            voxel_size,
+            affine,
+            data: field.field.clone(),
+        }
+    }
+
+    /// Convert a 1D, 2D or 3D image into a discrete field at the positions given by the affine,
+    /// see `EncodingFieldDiscrete::with_affine`. Fails if the affine is singular or, for 1D and
+    /// 2D images, moves the voxels out of their line or plane.
+    pub fn to_field(&self) -> Result<EncodingFieldDiscrete> {
+        let mut num_dims = self.dims.len();
+        while num_dims > 3 && self.dims[num_dims - 1] == 1 {
+            num_dims -= 1;
+        }
+        if num_dims == 0 || num_dims > 3 {
+            return Err(MriError::InvalidFile(format!(
+                "expected a 1D, 2D or 3D image, found {} dimensions",
+                num_dims
+            )));
+        }
+        EncodingFieldDiscrete::with_affine(
+            self.data.clone(),
+            SpatialDims::from_slice(&self.dims[..num_dims]),
+            self.affine,
+        )
+    }
+}
+
+/// NIfTI datatype code of `f64`
+const DATATYPE_FLOAT64: i16 = 64;
+/// NIfTI unit code of mm
+const UNITS_MM: u8 = 2;
+
+/// The parts of a header needed to read the image
+struct Header {
+    big_endian: bool,
+    single_file: bool,
+    dims: Vec<usize>,
+    datatype: i16,
+    pixdim: [f64; 8],
+    vox_offset: usize,
+    scl_slope: f64,
+    scl_inter: f64,
+    xyzt_units: u8,
+    qform_code: i32,
+    sform_code: i32,
+    quatern: [f64; 6],
+    srow: [[f64; 4]; 3],
+}
+
+impl Header {
+    /// Parse a NIfTI-1 or NIfTI-2 header
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(MriError::InvalidFile("file too short".to_string()));
+        }
+        let size_le = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let size_be = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let (size, big_endian) = if size_le == 348 || size_le == 540 {
+            (size_le as usize, false)
+        } else if size_be == 348 || size_be == 540 {
+            (size_be as usize, true)
+        } else {
+            return Err(MriError::InvalidFile("not a NIfTI file".to_string()));
+        };
+        let r = Reader::new(bytes, big_endian, size)?;
+        if size == NIFTI1_HEADER_SIZE {
+            let magic = &bytes[344..348];
+            if magic != b"n+1\0" && magic != b"ni1\0" {
+                return Err(MriError::InvalidFile("wrong NIfTI-1 magic".to_string()));
+            }
+            let mut pixdim = [0.0; 8];
+            for (i, p) in pixdim.iter_mut().enumerate() {
+                *p = f64::from(r.f32(76 + 4 * i));
+            }
+            let mut srow = [[0.0; 4]; 3];
+            for (i, row) in srow.iter_mut().enumerate() {
+                for (j, v) in row.iter_mut().enumerate() {
+                    *v = f64::from(r.f32(280 + 16 * i + 4 * j));
+                }
+            }
+            let mut quatern = [0.0; 6];
+            for (i, q) in quatern.iter_mut().enumerate() {
+                *q = f64::from(r.f32(256 + 4 * i));
+            }
+            Ok(Header {
+                big_endian,
+                single_file: magic == b"n+1\0",
+                dims: dims((0..8).map(|i| i64::from(r.i16(40 + 2 * i))).collect())?,
+                datatype: r.i16(70),
+                pixdim,
+                vox_offset: r.f32(108) as usize,
+                scl_slope: f64::from(r.f32(112)),
+                scl_inter: f64::from(r.f32(116)),
+                xyzt_units: bytes[123],
+                qform_code: i32::from(r.i16(252)),
+                sform_code: i32::from(r.i16(254)),
+                quatern,
+                srow,
+            })
+        } else {
+            let magic = &bytes[4..12];
+            if magic != b"n+2\0\r\n\x1a\n" && magic != b"ni2\0\r\n\x1a\n" {
+                return Err(MriError::InvalidFile("wrong NIfTI-2 magic".to_string()));
+            }
+            let mut pixdim = [0.0; 8];
+            for (i, p) in pixdim.iter_mut().enumerate() {
+                *p = r.f64(104 + 8 * i);
+            }
+            let mut srow = [[0.0; 4]; 3];
+            for (i, row) in srow.iter_mut().enumerate() {
+                for (j, v) in row.iter_mut().enumerate() {
+                    *v = r.f64(400 + 32 * i + 8 * j);
+                }
+            }
+            let mut quatern = [0.0; 6];
+            for (i, q) in quatern.iter_mut().enumerate() {
+                *q = r.f64(352 + 8 * i);
+            }
+            let vox_offset = r.i64(168);
+            if vox_offset < 0 {
+                return Err(MriError::InvalidFile("negative vox_offset".to_string()));
+            }
+            Ok(Header {
+                big_endian,
+                single_file: magic[1] == b'+',
+                dims: dims((0..8).map(|i| r.i64(16 + 8 * i)).collect())?,
+                datatype: r.i16(12),
+                pixdim,
+                vox_offset: vox_offset as usize,
+                scl_slope: r.f64(176),
+                scl_inter: r.f64(184),
+                xyzt_units: r.i32(500) as u8,
+                qform_code: r.i32(344),
+                sform_code: r.i32(348),
+                quatern,
+                srow,
+            })
+        }
+    }
+
+    /// Scale factor from the spatial unit of the file to m
+    fn unit(&self) -> f64 {
+        match self.xyzt_units & 0x07 {
+            1 => 1.0,
+            3 => 1e-6,
+            // mm, or unknown
+            _ => 1e-3,
+        }
+    }
+
+    /// Affine transformation from voxel indices to world coordinates (m)
+    fn affine(&self) -> Affine {
+        let mut a = [[0.0; 4]; 3];
+        if self.sform_code > 0 {
+            a = self.srow;
+        } else if self.qform_code > 0 {
+            let [b, c, d, x, y, z] = self.quatern;
+            let w = (1.0 - b * b - c * c - d * d).max(0.0).sqrt();
+            let rot = [
+                [w * w + b * b - c * c - d * d, 2.0 * (b * c - w * d), 2.0 * (b * d + w * c)],
+                [2.0 * (b * c + w * d), w * w + c * c - b * b - d * d, 2.0 * (c * d - w * b)],
+                [2.0 * (b * d - w * c), 2.0 * (c * d + w * b), w * w + d * d - c * c - b * b],
+            ];
+            let qfac = if self.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+            let scale = [self.pixdim[1], self.pixdim[2], qfac * self.pixdim[3]];
+            for (i, row) in a.iter_mut().enumerate() {
+                for j in 0..3 {
+                    row[j] = rot[i][j] * scale[j];
+                }
+            }
+            a[0][3] = x;
+            a[1][3] = y;
+            a[2][3] = z;
+        } else {
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] = self.pixdim[i + 1];
+            }
+        }
+        let unit = self.unit();
+        for row in &mut a {
+            for v in row.iter_mut() {
+                *v *= unit;
+            }
+        }
+        a
+    }
+
+    /// Read the voxels from the image bytes
+    fn image(&self, bytes: &[u8]) -> Result<NiftiImage> {
+        let num_voxels = num_voxels(&self.dims)
+            .ok_or_else(|| MriError::InvalidFile(format!("too many voxels {:?}", self.dims)))?;
+        let size = match self.datatype {
+            2 | 256 => 1,
+            4 | 512 => 2,
+            8 | 16 | 768 => 4,
+            64 | 1024 | 1280 => 8,
+            t => {
+                return Err(MriError::InvalidFile(format!(
+                    "unsupported NIfTI datatype {}",
+                    t
+                )))
+            }
+        };
+        let end = num_voxels
+            .checked_mul(size)
+            .and_then(|n| n.checked_add(self.vox_offset));
+        if end.is_none_or(|end| end > bytes.len()) {
+            return Err(MriError::InvalidFile("file too short".to_string()));
+        }
+        let r = Reader {
+            bytes,
+            big_endian: self.big_endian,
+        };
+        let (slope, inter) = if self.scl_slope != 0.0 && self.scl_slope.is_finite() {
+            (self.scl_slope, self.scl_inter)
+        } else {
+            (1.0, 0.0)
+        };
+        let data = (0..num_voxels)
+            .map(|i| {
+                let o = self.vox_offset + i * size;
+                let x = match self.datatype {
+                    2 => f64::from(bytes[o]),
+                    256 => f64::from(bytes[o] as i8),
+                    4 => f64::from(r.i16(o)),
+                    512 => f64::from(r.i16(o) as u16),
+                    8 => f64::from(r.i32(o)),
+                    768 => f64::from(r.i32(o) as u32),
+                    16 => f64::from(r.f32(o)),
+                    64 => r.f64(o),
+                    1024 => r.i64(o) as f64,
+                    _ => r.i64(o) as u64 as f64,
+                };
+                slope * x + inter
+            }).collect();
+        let unit = self.unit();
+        Ok(NiftiImage {
+            dims: self.dims.clone(),
+            voxel_size: (0..self.dims.len().min(3))
+                .map(|i| self.pixdim[i + 1].abs() * unit)
+                .collect(),
+            affine: self.affine(),
+            data,
+        })
+    }
+}
+
+/// Check the `dim` field of a header and return the used dimensions
+fn dims(dim: Vec<i64>) -> Result<Vec<usize>> {
+    let n = dim[0];
+    if !(1..=7).contains(&n) || dim[1..=n as usize].iter().any(|&d| d < 1) {
+        return Err(MriError::InvalidFile(format!("invalid dimensions {:?}", dim)));
+    }
+    Ok(dim[1..=n as usize].iter().map(|&d| d as usize).collect())
+}
+
+/// Number of voxels of an image, `None` on overflow
+fn num_voxels(dims: &[usize]) -> Option<usize> {
+    dims.iter().try_fold(1usize, |n, &d| n.checked_mul(d))
+}
+
+/// Read numbers of either byte order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    /// Reader of a header of `size` bytes
+    fn new(bytes: &'a [u8], big_endian: bool, size: usize) -> Result<Self> {
+        if bytes.len() < size {
+            return Err(MriError::InvalidFile("header too short".to_string()));
+        }
+        Ok(Reader { bytes, big_endian })
+    }
+
+    /// `N` bytes at offset `o` in little endian order
+    fn get<const N: usize>(&self, o: usize) -> [u8; N] {
+        let mut b = [0u8; N];
+        b.copy_from_slice(&self.bytes[o..o + N]);
+        if self.big_endian {
+            b.reverse();
+        }
+        b
+    }
+
+    fn i16(&self, o: usize) -> i16 {
+        i16::from_le_bytes(self.get(o))
+    }
+
+    fn i32(&self, o: usize) -> i32 {
+        i32::from_le_bytes(self.get(o))
+    }
+
+    fn i64(&self, o: usize) -> i64 {
+        i64::from_le_bytes(self.get(o))
+    }
+
+    fn f32(&self, o: usize) -> f32 {
+        f32::from_le_bytes(self.get(o))
+    }
+
+    fn f64(&self, o: usize) -> f64 {
+        f64::from_le_bytes(self.get(o))
+    }
+}
+
+/// Little endian header writer
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    /// Writer of a zeroed header of `size` bytes
+    fn new(size: usize) -> Self {
+        Writer { buf: vec![0; size] }
+    }
+
+    fn bytes(&mut self, o: usize, b: &[u8]) {
+        self.buf[o..o + b.len()].copy_from_slice(b);
+    }
+
+    fn u8(&mut self, o: usize, v: u8) {
+        self.buf[o] = v;
+    }
+
+    fn i16(&mut self, o: usize, v: i16) {
+        self.bytes(o, &v.to_le_bytes());
+    }
+
+    fn i32(&mut self, o: usize, v: i32) {
+        self.bytes(o, &v.to_le_bytes());
+    }
+
+    fn i64(&mut self, o: usize, v: i64) {
+        self.bytes(o, &v.to_le_bytes());
+    }
+
+    fn f32(&mut self, o: usize, v: f32) {
+        self.bytes(o, &v.to_le_bytes());
+    }
+
+    fn f64(&mut self, o: usize, v: f64) {
+        self.bytes(o, &v.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encodingfield::Interpolation;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// path of a temporary file
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("mri-nifti-{}-{}", std::process::id(), name))
+    }
+
+    /// write a field to a temporary file and read it back
+    fn round_trip(field: &EncodingFieldDiscrete, version: NiftiVersion) -> EncodingFieldDiscrete {
+        let path = temp_path(&format!("{:?}.nii", version));
+        field.write_nifti(&path, version).unwrap();
+        let out = EncodingFieldDiscrete::read_nifti(&path);
+        fs::remove_file(&path).unwrap();
+        out.unwrap()
+    }
+
+    fn check_affine(a: &Affine, b: &Affine, tol: f64) {
+        for (ra, rb) in a.iter().zip(b.iter()) {
+            for (x, y) in ra.iter().zip(rb.iter()) {
+                assert!((x - y).abs() < tol, "{:?} vs {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_round_trip() {
+        let fov = SpatialDims::ThreeD(0.2, 0.3, 0.1);
+        let dims = SpatialDims::ThreeD(4, 3, 2);
+        let values: Vec<f64> = (0..24).map(|i| (i as f64) * 0.5 - 3.0).collect();
+        let field = EncodingFieldDiscrete::new(values.clone(), fov.clone(), dims.clone()).unwrap();
+        for &(version, tol) in &[(NiftiVersion::Nifti1, 1e-8), (NiftiVersion::Nifti2, 1e-15)] {
+            let out = round_trip(&field, version);
+            assert_eq!(out.field, values);
+            assert_eq!(out.dimensions(), dims);
+            for (a, b) in out.fov().into_iter().zip(fov.clone()) {
+                assert!((a - b).abs() < tol);
+            }
+            check_affine(&out.affine(), &field.affine(), tol);
+        }
+    }
+
+    #[test]
+    fn affine_round_trip() {
+        // rotation about z, shear and offset
+        let (s, c) = 0.3f64.sin_cos();
+        let affine = [
+            [2e-3 * c, -3e-3 * s, 0.5e-3, 0.01],
+            [2e-3 * s, 3e-3 * c, 0.0, -0.02],
+            [0.0, 0.0, 4e-3, 0.005],
+        ];
+        let dims = SpatialDims::ThreeD(5, 4, 3);
+        let values: Vec<f64> = (0..60).map(|i| ((i * 7) % 11) as f64).collect();
+        let field =
+            EncodingFieldDiscrete::with_affine(values.clone(), dims.clone(), affine).unwrap();
+        for &(version, tol) in &[(NiftiVersion::Nifti1, 1e-8), (NiftiVersion::Nifti2, 1e-15)] {
+            let out = round_trip(&field, version);
+            assert_eq!(out.field, values);
+            check_affine(&out.affine(), &affine, tol);
+
+            // the interpolant reproduces the samples at the transformed positions
+            let f = out.to_encoding_field(Interpolation::CubicBSpline).unwrap();
+            for k in 0..3 {
+                for j in 0..4 {
+                    for i in 0..5 {
+                        let idx = [i as f64, j as f64, k as f64];
+                        let r: Vec<f64> = affine
+                            .iter()
+                            .map(|row| row[0] * idx[0] + row[1] * idx[1] + row[2] * idx[2] + row[3])
+                            .collect();
+                        let v = values[(k * 4 + j) * 5 + i];
+                        assert!((f.at(&SpatialDims::from_slice(&r)) - v).abs() < 1e-5);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn qform() {
+        // 90 degrees about z, voxel size (1, 2, 3) mm, offset (4, 5, 6) mm
+        let header = Header {
+            big_endian: false,
+            single_file: true,
+            dims: vec![2, 2, 2],
+            datatype: DATATYPE_FLOAT64,
+            pixdim: [1.0, 1.0, 2.0, 3.0, 1.0, 1.0, 1.0, 1.0],
+            vox_offset: 0,
+            scl_slope: 1.0,
+            scl_inter: 0.0,
+            xyzt_units: UNITS_MM,
+            qform_code: 1,
+            sform_code: 0,
+            quatern: [0.0, 0.0, 0.5f64.sqrt(), 4.0, 5.0, 6.0],
+            srow: [[0.0; 4]; 3],
+        };
+        let expected = [
+            [0.0, -2e-3, 0.0, 4e-3],
+            [1e-3, 0.0, 0.0, 5e-3],
+            [0.0, 0.0, 3e-3, 6e-3],
+        ];
+        check_affine(&header.affine(), &expected, 1e-15);
+    }
+
+    #[test]
+    fn unrepresentable_affine() {
+        let mut image = NiftiImage {
+            dims: vec![2, 2],
+            voxel_size: vec![1e-3, 1e-3],
+            affine: [[1e-3, 0.0, 0.0, 0.0], [0.0, 1e-3, 0.0, 0.0], [0.0, 0.0, 1e-3, 0.0]],
+            data: vec![0.0; 4],
+        };
+        assert!(image.to_field().is_ok());
+        // the plane is tilted out of xy
+        image.affine[2][1] = 1e-3;
+        assert!(image.to_field().is_err());
+        // singular
+        image.affine[2][1] = 0.0;
+        image.affine[1] = [2e-3, 0.0, 0.0, 0.0];
+        assert!(image.to_field().is_err());
+    }
+
+    #[test]
+    fn too_many_voxels() {
+        let image = NiftiImage {
+            dims: vec![usize::MAX / 2, 3],
+            voxel_size: vec![1e-3, 1e-3],
+            affine: [[1e-3, 0.0, 0.0, 0.0], [0.0, 1e-3, 0.0, 0.0], [0.0, 0.0, 1e-3, 0.0]],
+            data: vec![],
+        };
+        let path = temp_path("overflow.nii");
+        assert!(image.write(&path, NiftiVersion::Nifti2).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,597 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Non-uniform FFT
+//!
+//! Gridding based NUFFT with a Kaiser-Bessel kernel, following Beatty et al., "Rapid gridding
+//! reconstruction with a minimal oversampling ratio", IEEE TMI 24(6), 2005.
+//!
+//! The forward transform computes `s(k) = sum_r m(r) * exp(-i 2 pi k r)` for an image `m` on the
+//! voxel grid given by `SpatialDims::grid`. This is the same as `EncodingMatrix::apply` with linear
+//! encoding fields (`psi_j(r) = r_j`) and a single, homogeneous coil, but needs only
+//! `O(N log N + M)` operations instead of `O(N M)`.
+
+use error::{check_dims, check_grid, MriError, Result};
+use num::Complex;
+use offresonance::OffResonance;
+use rustfft::FftPlanner;
+use std::f64::consts::PI;
+use KSpaceThings;
+use SpatialDims;
+
+/// Default oversampling ratio of the grid
+pub const DEFAULT_OVERSAMPLING: f64 = 2.0;
+/// Default width of the Kaiser-Bessel kernel (in grid points)
+pub const DEFAULT_KERNEL_WIDTH: usize = 6;
+
+/// Non-uniform FFT for a fixed trajectory and image grid
+pub struct Nufft {
+    /// field of view
+    fov: SpatialDims<f64>,
+    /// image dimensions
+    dims: SpatialDims<usize>,
+    /// image size in x, y and z (1 for unused dimensions)
+    n: [usize; 3],
+    /// oversampled grid size in x, y and z (1 for unused dimensions)
+    g: [usize; 3],
+    /// kernel width
+    width: usize,
+    /// Kaiser-Bessel shape parameter
+    beta: f64,
+    /// grid points and kernel weights of every sample in x, y and z
+    weights: Vec<[Vec<(usize, f64)>; 3]>,
+    /// phase correction of every sample for odd image sizes
+    shift: Vec<Complex<f64>>,
+    /// deapodization (one over the kernel's Fourier transform) of every voxel
+    deapod: Vec<f64>,
+}
+
+impl Nufft {
+    /// Create a NUFFT for the samples of `k` and an image of `dims` voxels covering `fov`, with
+    /// the default oversampling ratio and kernel width.
+    pub fn new<T: KSpaceThings>(k: &T, fov: SpatialDims<f64>, dims: SpatialDims<usize>) -> Self {
+        Nufft::try_new(k, fov, dims).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a NUFFT with the default parameters, failing if the trajectory and grid do not fit
+    /// together
+    pub fn try_new<T: KSpaceThings>(
+        k: &T,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Result<Self> {
+        Nufft::try_with_params(k, fov, dims, DEFAULT_OVERSAMPLING, DEFAULT_KERNEL_WIDTH)
+    }
+
+    /// Create a NUFFT with a given oversampling ratio (> 1) and kernel width
+    pub fn with_params<T: KSpaceThings>(
+        k: &T,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+        oversampling: f64,
+        width: usize,
+    ) -> Self {
+        Nufft::try_with_params(k, fov, dims, oversampling, width)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a NUFFT with a given oversampling ratio and kernel width, failing if the trajectory
+    /// and grid do not fit together or the parameters are invalid
+    pub fn try_with_params<T: KSpaceThings>(
+        k: &T,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+        oversampling: f64,
+        width: usize,
+    ) -> Result<Self> {
+        check_grid(&fov, &dims)?;
+        check_dims(dims.len(), k.num_channels())?;
+        if !(oversampling > 1.0 && oversampling.is_finite()) {
+            return Err(MriError::InvalidParameter(format!(
+                "oversampling ratio {}",
+                oversampling
+            )));
+        }
+        if width == 0 {
+            return Err(MriError::InvalidParameter(
+                "kernel width must be positive".to_string(),
+            ));
+        }
+
+        let mut n = [1; 3];
+        let mut g = [1; 3];
+        let mut f = [1.0; 3];
+        for (d, (nd, fd)) in dims.clone().into_iter().zip(fov.clone()).enumerate() {
+            n[d] = nd;
+            // even grid size, at least as large as the kernel
+            g[d] = (2 * ((oversampling * (nd as f64) / 2.0).ceil() as usize)).max(width);
+            f[d] = fd;
+        }
+        let w = width as f64;
+        let beta = PI
+            * ((w / oversampling).powi(2) * (oversampling - 0.5).powi(2) - 0.8)
+                .max(0.0)
+                .sqrt();
+
+        let mut nufft = Nufft {
+            fov,
+            dims,
+            n,
+            g,
+            width,
+            beta,
+            weights: vec![],
+            shift: vec![],
+            deapod: vec![],
+        };
+
+        let samples = k.samples();
+        nufft.weights = samples
+            .iter()
+            .map(|s| {
+                let mut out = [vec![(0, 1.0)], vec![(0, 1.0)], vec![(0, 1.0)]];
+                for (d, kd) in s.iter().enumerate() {
+                    // position on the oversampled grid
+                    let nu = kd * f[d] * (g[d] as f64) / (n[d] as f64);
+                    out[d] = nufft.kernel_weights(nu, g[d]);
+                }
+                out
+            }).collect();
+        nufft.shift = samples
+            .iter()
+            .map(|s| {
+                let phase: f64 = s
+                    .iter()
+                    .enumerate()
+                    .map(|(d, kd)| {
+                        let delta = (n[d] as f64) / 2.0 - ((n[d] / 2) as f64);
+                        2.0 * PI * kd * f[d] * delta / (n[d] as f64)
+                    }).sum();
+                Complex::from_polar(1.0, phase)
+            }).collect();
+
+        let apod: Vec<Vec<f64>> = (0..3)
+            .map(|d| {
+                (0..n[d])
+                    .map(|j| {
+                        if g[d] == 1 {
+                            1.0
+                        } else {
+                            let p = (j as f64) - ((n[d] / 2) as f64);
+                            nufft.kernel_ft(p / (g[d] as f64))
+                        }
+                    }).collect()
+            }).collect();
+        let mut deapod = Vec::with_capacity(n[0] * n[1] * n[2]);
+        for z in 0..n[2] {
+            for y in 0..n[1] {
+                for x in 0..n[0] {
+                    deapod.push(1.0 / (apod[0][x] * apod[1][y] * apod[2][z]));
+                }
+            }
+        }
+        nufft.deapod = deapod;
+        Ok(nufft)
+    }
+
+    /// Return the FOV
+    pub fn fov(&self) -> SpatialDims<f64> {
+        self.fov.clone()
+    }
+
+    /// Return the image dimensions
+    pub fn dimensions(&self) -> SpatialDims<usize> {
+        self.dims.clone()
+    }
+
+    /// Return the number of k-space samples
+    pub fn num_samples(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Return the number of voxels
+    pub fn num_voxels(&self) -> usize {
+        self.deapod.len()
+    }
+
+    /// Kaiser-Bessel kernel
+    fn kernel(&self, t: f64) -> f64 {
+        let x = 2.0 * t / (self.width as f64);
+        if x.abs() > 1.0 {
+            0.0
+        } else {
+            bessel_i0(self.beta * (1.0 - x * x).sqrt())
+        }
+    }
+
+    /// Result of `interpolate(spread(x))` at sample `i` for a single, isolated sample with `x_i = 1`
+    pub(crate) fn self_weight(&self, i: usize) -> f64 {
+        self.weights[i]
+            .iter()
+            .map(|w| w.iter().map(|&(_, a)| a * a).sum::<f64>())
+            .product()
+    }
+
+    /// Fourier transform of the Kaiser-Bessel kernel
+    fn kernel_ft(&self, x: f64) -> f64 {
+        let w = self.width as f64;
+        let a = self.beta * self.beta - (PI * w * x).powi(2);
+        if a > 1e-12 {
+            w * a.sqrt().sinh() / a.sqrt()
+        } else if a < -1e-12 {
+            w * (-a).sqrt().sin() / (-a).sqrt()
+        } else {
+            w
+        }
+    }
+
+    /// Grid points (wrapped into `0..g`) and kernel weights around position `nu`
+    fn kernel_weights(&self, nu: f64, g: usize) -> Vec<(usize, f64)> {
+        let half = (self.width as f64) / 2.0;
+        let first = (nu - half).ceil() as i64;
+        let last = (nu + half).floor() as i64;
+        (first..=last)
+            .map(|q| {
+                (
+                    q.rem_euclid(g as i64) as usize,
+                    self.kernel(nu - (q as f64)),
+                )
+            }).collect()
+    }
+
+    /// Convolve the samples (one value per sample) onto the oversampled grid
+    pub(crate) fn spread(&self, data: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        let g = self.g;
+        let mut grid = vec![Complex::new(0.0, 0.0); g[0] * g[1] * g[2]];
+        for (d, w) in data.iter().zip(self.weights.iter()) {
+            for &(z, wz) in &w[2] {
+                for &(y, wy) in &w[1] {
+                    let wzy = wz * wy;
+                    let offset = (z * g[1] + y) * g[0];
+                    for &(x, wx) in &w[0] {
+                        grid[offset + x] += d * (wzy * wx);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// Interpolate the oversampled grid at the sample positions
+    pub(crate) fn interpolate(&self, grid: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        let g = self.g;
+        self.weights
+            .iter()
+            .map(|w| {
+                let mut acc = Complex::new(0.0, 0.0);
+                for &(z, wz) in &w[2] {
+                    for &(y, wy) in &w[1] {
+                        let wzy = wz * wy;
+                        let offset = (z * g[1] + y) * g[0];
+                        for &(x, wx) in &w[0] {
+                            acc += grid[offset + x] * (wzy * wx);
+                        }
+                    }
+                }
+                acc
+            }).collect()
+    }
+
+    /// Index of voxel `j` of dimension `d` on the oversampled grid
+    fn grid_index(&self, d: usize, j: usize) -> usize {
+        (j + self.g[d] - self.n[d] / 2) % self.g[d]
+    }
+
+    /// Forward transform: image to k-space samples (in the order of `KSpaceThings::samples`)
+    pub fn forward(&self, img: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        self.try_forward(img).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Forward transform, failing unless `img` has one value per voxel
+    pub fn try_forward(&self, img: &[Complex<f64>]) -> Result<Vec<Complex<f64>>> {
+        check_dims(self.num_voxels(), img.len())?;
+        let (n, g) = (self.n, self.g);
+        let mut grid = vec![Complex::new(0.0, 0.0); g[0] * g[1] * g[2]];
+        let mut i = 0;
+        for z in 0..n[2] {
+            let gz = self.grid_index(2, z);
+            for y in 0..n[1] {
+                let gy = self.grid_index(1, y);
+                for x in 0..n[0] {
+                    let gx = self.grid_index(0, x);
+                    grid[(gz * g[1] + gy) * g[0] + gx] = img[i] * self.deapod[i];
+                    i += 1;
+                }
+            }
+        }
+        fft(&mut grid, g, false);
+        Ok(self
+            .interpolate(&grid)
+            .iter()
+            .zip(self.shift.iter())
+            .map(|(s, p)| s * p)
+            .collect())
+    }
+
+    /// Adjoint transform: k-space samples to image
+    pub fn adjoint(&self, data: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        self.try_adjoint(data).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adjoint transform, failing unless `data` has one value per sample
+    pub fn try_adjoint(&self, data: &[Complex<f64>]) -> Result<Vec<Complex<f64>>> {
+        check_dims(self.num_samples(), data.len())?;
+        let shifted: Vec<Complex<f64>> = data
+            .iter()
+            .zip(self.shift.iter())
+            .map(|(s, p)| s * p.conj())
+            .collect();
+        let mut grid = self.spread(&shifted);
+        let (n, g) = (self.n, self.g);
+        fft(&mut grid, g, true);
+        let mut img = Vec::with_capacity(self.num_voxels());
+        for z in 0..n[2] {
+            let gz = self.grid_index(2, z);
+            for y in 0..n[1] {
+                let gy = self.grid_index(1, y);
+                for x in 0..n[0] {
+                    let gx = self.grid_index(0, x);
+                    let i = img.len();
+                    img.push(grid[(gz * g[1] + gy) * g[0] + gx] * self.deapod[i]);
+                }
+            }
+        }
+        Ok(img)
+    }
+
+    /// Forward transform with off-resonance, `s(k_m) = sum_l b_l(t_m) F(c_l m)` with the terms of
+    /// an approximated off-resonance term (see `offresonance`)
+    pub fn forward_off_resonance(
+        &self,
+        img: &[Complex<f64>],
+        off_resonance: &OffResonance,
+    ) -> Vec<Complex<f64>> {
+        self.try_forward_off_resonance(img, off_resonance)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Forward transform with off-resonance, failing if the off-resonance term is evaluated
+    /// exactly or does not fit the transform
+    pub fn try_forward_off_resonance(
+        &self,
+        img: &[Complex<f64>],
+        off_resonance: &OffResonance,
+    ) -> Result<Vec<Complex<f64>>> {
+        self.check_off_resonance(off_resonance)?;
+        check_dims(self.num_voxels(), img.len())?;
+        let mut out = vec![Complex::new(0.0, 0.0); self.num_samples()];
+        for (b, c) in off_resonance.terms() {
+            let weighted: Vec<Complex<f64>> = img.iter().zip(c).map(|(x, c)| x * c).collect();
+            for ((o, s), b) in out.iter_mut().zip(self.try_forward(&weighted)?).zip(b) {
+                *o += s * b;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Adjoint transform with off-resonance, `m(r) = sum_l conj(c_l(r)) F^H(conj(b_l) s)`
+    pub fn adjoint_off_resonance(
+        &self,
+        data: &[Complex<f64>],
+        off_resonance: &OffResonance,
+    ) -> Vec<Complex<f64>> {
+        self.try_adjoint_off_resonance(data, off_resonance)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adjoint transform with off-resonance, failing if the off-resonance term is evaluated
+    /// exactly or does not fit the transform
+    pub fn try_adjoint_off_resonance(
+        &self,
+        data: &[Complex<f64>],
+        off_resonance: &OffResonance,
+    ) -> Result<Vec<Complex<f64>>> {
+        self.check_off_resonance(off_resonance)?;
+        check_dims(self.num_samples(), data.len())?;
+        let mut out = vec![Complex::new(0.0, 0.0); self.num_voxels()];
+        for (b, c) in off_resonance.terms() {
+            let weighted: Vec<Complex<f64>> =
+                data.iter().zip(b).map(|(x, b)| x * b.conj()).collect();
+            for ((o, x), c) in out.iter_mut().zip(self.try_adjoint(&weighted)?).zip(c) {
+                *o += x * c.conj();
+            }
+        }
+        Ok(out)
+    }
+
+    /// Check that an off-resonance term is approximated and fits the transform
+    fn check_off_resonance(&self, off_resonance: &OffResonance) -> Result<()> {
+        off_resonance.check(self.num_samples(), self.num_voxels())?;
+        if off_resonance.num_terms() == 0 {
+            return Err(MriError::InvalidParameter(
+                "exact off-resonance cannot be separated into NUFFTs".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Unnormalized FFT (`inverse` uses the positive exponent) along all axes of a 3D array with x
+/// varying fastest
+fn fft(data: &mut [Complex<f64>], g: [usize; 3], inverse: bool) {
+    let mut planner = FftPlanner::new();
+    let strides = [1, g[0], g[0] * g[1]];
+    for d in 0..3 {
+        if g[d] == 1 {
+            continue;
+        }
+        let plan = if inverse {
+            planner.plan_fft_inverse(g[d])
+        } else {
+            planner.plan_fft_forward(g[d])
+        };
+        let mut line = vec![Complex::new(0.0, 0.0); g[d]];
+        for start in 0..data.len() {
+            // only start at the first element of each line
+            if !(start / strides[d]).is_multiple_of(g[d]) {
+                continue;
+            }
+            for (i, l) in line.iter_mut().enumerate() {
+                *l = data[start + i * strides[d]];
+            }
+            plan.process(&mut line);
+            for (i, l) in line.iter().enumerate() {
+                data[start + i * strides[d]] = *l;
+            }
+        }
+    }
+}
+
+/// Modified Bessel function of the first kind of order zero
+fn bessel_i0(x: f64) -> f64 {
+    let y = x * x / 4.0;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    let mut k = 1.0;
+    while term > 1e-16 * sum {
+        term *= y / (k * k);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encodingmatrix::EncodingMatrix;
+    use offresonance::OffResonanceMethod;
+    use random::Rng;
+    use std::rc::Rc;
+    use EncodingField;
+    use KSpace;
+    use RFSensitivity;
+
+    /// deterministic pseudo random numbers in [-1, 1)
+    fn random(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| 2.0 * rng.uniform() - 1.0).collect()
+    }
+
+    fn random_complex(n: usize, seed: u64) -> Vec<Complex<f64>> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| Complex::new(rng.normal(), rng.normal())).collect()
+    }
+
+    fn relative_error(a: &[Complex<f64>], b: &[Complex<f64>]) -> f64 {
+        let diff: f64 = a.iter().zip(b.iter()).map(|(x, y)| (x - y).norm_sqr()).sum();
+        let norm: f64 = b.iter().map(|y| y.norm_sqr()).sum();
+        (diff / norm).sqrt()
+    }
+
+    /// Compare forward and adjoint NUFFT against the encoding matrix with linear fields at random
+    /// sample positions within the k-space extent of the grid
+    fn check_accuracy(fov: SpatialDims<f64>, dims: SpatialDims<usize>) {
+        let d = dims.len();
+        let kmax: Vec<f64> = dims
+            .clone()
+            .into_iter()
+            .zip(fov.clone())
+            .map(|(n, f)| 0.5 * (n as f64) / f)
+            .collect();
+        let mut k = KSpace::new();
+        let r = random(40 * d, 7);
+        for s in r.chunks(d) {
+            k.add(s.iter().zip(kmax.iter()).map(|(x, m)| x * m).collect());
+        }
+        let psi: Vec<EncodingField> = (0..d)
+            .map(|c| {
+                EncodingField::new(Rc::new(move |p: &SpatialDims<f64>| {
+                    p.clone().into_iter().nth(c).unwrap()
+                }))
+            }).collect();
+        let n = dims.product();
+        let rf = vec![RFSensitivity::new(vec![Complex::new(1.0, 0.0); n])];
+        let e = EncodingMatrix::new(k.clone(), psi, rf, fov.clone(), dims.clone());
+
+        let x = random_complex(n, 11);
+        let y = random_complex(e.num_samples(), 13);
+        let ex = e.apply(&x).remove(0);
+        let ehy = e.apply_adjoint(std::slice::from_ref(&y));
+        for &width in &[4, 6] {
+            let nufft = Nufft::with_params(&k, fov.clone(), dims.clone(), 2.0, width);
+            let tol = 10f64.powi(1 - width as i32);
+            assert!(relative_error(&nufft.forward(&x), &ex) < tol);
+            assert!(relative_error(&nufft.adjoint(&y), &ehy) < tol);
+        }
+    }
+
+    #[test]
+    fn accuracy_1d() {
+        check_accuracy(SpatialDims::OneD(0.2), SpatialDims::OneD(15));
+    }
+
+    #[test]
+    fn accuracy_2d() {
+        check_accuracy(SpatialDims::TwoD(0.2, 0.25), SpatialDims::TwoD(8, 7));
+    }
+
+    #[test]
+    fn accuracy_3d() {
+        check_accuracy(SpatialDims::ThreeD(0.2, 0.2, 0.1), SpatialDims::ThreeD(6, 5, 4));
+    }
+
+    #[test]
+    fn off_resonance() {
+        let fov = SpatialDims::TwoD(0.2, 0.25);
+        let dims = SpatialDims::TwoD(8, 7);
+        let n = dims.product();
+        let mut k = KSpace::new();
+        for s in random(80, 5).chunks(2) {
+            k.add(vec![s[0] * 20.0, s[1] * 14.0]);
+        }
+        let psi = vec![
+            EncodingField::new(Rc::new(|p: &SpatialDims<f64>| p.x().unwrap())),
+            EncodingField::new(Rc::new(|p: &SpatialDims<f64>| p.y().unwrap())),
+        ];
+        let rf = vec![RFSensitivity::new(vec![Complex::new(1.0, 0.0); n])];
+        let freq: Vec<f64> = random(n, 17).iter().map(|x| 100.0 * x).collect();
+        let times: Vec<f64> = (0..40).map(|m| 2e-4 * m as f64).collect();
+        let x = random_complex(n, 11);
+        let y = random_complex(40, 13);
+        let nufft = Nufft::new(&k, fov.clone(), dims.clone());
+
+        for &method in &[
+            OffResonanceMethod::TimeSegmentation(8),
+            OffResonanceMethod::MultiFrequency(8),
+        ] {
+            let o = OffResonance::new(freq.clone(), times.clone(), method).unwrap();
+            let mut e =
+                EncodingMatrix::new(k.clone(), psi.clone(), rf.clone(), fov.clone(), dims.clone());
+            e.off_resonance(o.clone()).unwrap();
+            let ex = e.apply(&x).remove(0);
+            let ehy = e.apply_adjoint(std::slice::from_ref(&y));
+            assert!(relative_error(&nufft.forward_off_resonance(&x, &o), &ex) < 1e-4);
+            assert!(relative_error(&nufft.adjoint_off_resonance(&y, &o), &ehy) < 1e-4);
+        }
+
+        let exact = OffResonance::new(freq, times, OffResonanceMethod::Exact).unwrap();
+        assert!(nufft.try_forward_off_resonance(&x, &exact).is_err());
+        assert!(nufft.try_adjoint_off_resonance(&y, &exact).is_err());
+    }
+
+    #[test]
+    fn invalid_input() {
+        let k = KSpace::cartesian(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(4, 4));
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        assert!(Nufft::try_new(&k, SpatialDims::OneD(0.2), SpatialDims::OneD(4)).is_err());
+        assert!(Nufft::try_with_params(&k, fov.clone(), SpatialDims::TwoD(4, 4), 1.0, 4).is_err());
+        let nufft = Nufft::try_new(&k, fov, SpatialDims::TwoD(4, 4)).unwrap();
+        assert!(nufft.try_forward(&[Complex::new(0.0, 0.0); 3]).is_err());
+        assert!(nufft.try_adjoint(&[Complex::new(0.0, 0.0); 3]).is_err());
+    }
+}
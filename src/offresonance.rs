@@ -0,0 +1,380 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Off-resonance
+//!
+//! A B0 field map `df(r)` (Hz) and the readout time `t` (s) of every k-space sample add the
+//! phase `exp(-i 2 pi df(r) t)` to the encoding model. Besides exact evaluation, the term can
+//! be approximated by a sum of `L` separable terms `sum_l b_l(t) c_l(r)`:
+//!
+//! * time segmentation (Sutton et al., "Fast, iterative image reconstruction for MRI in the
+//!   presence of field inhomogeneities", IEEE TMI 22(2), 2003) with `c_l(r) = exp(-i 2 pi df(r)
+//!   tau_l)` at equally spaced times `tau_l` and least squares interpolators `b_l(t)`
+//! * multi-frequency interpolation (Man et al., "Multifrequency interpolation for fast
+//!   off-resonance correction", MRM 37(5), 1997) with `b_l(t) = exp(-i 2 pi f_l t)` at equally
+//!   spaced frequencies `f_l` and least squares coefficients `c_l(r)`
+//!
+//! Every term is an off-resonance free encoding operator between two diagonal weightings, such
+//! that fast operators like `Nufft` can be applied `L` times instead of evaluating the full
+//! model, see `Nufft::forward_off_resonance`. The dense `EncodingMatrix` evaluates the sum for
+//! every element, which is slower than exact evaluation and only useful to check an
+//! approximation.
+
+use encodingfield::{grid_affine, EncodingFieldDiscrete, Interpolation};
+use error::{check_dims, check_grid, MriError, Result};
+use num::Complex;
+use std::f64::consts::PI;
+use SpatialDims;
+
+/// Number of histogram bins summarizing field maps and readout times in the least squares fits
+const HISTOGRAM_BINS: usize = 256;
+/// Relative Tikhonov regularization of the least squares fits
+const REGULARIZATION: f64 = 1e-9;
+
+/// A term `(b_l, c_l)` of an approximation: temporal interpolator (one value per sample) and
+/// spatial coefficients (one value per voxel)
+pub type Term<'a> = (&'a [Complex<f64>], &'a [Complex<f64>]);
+
+/// Evaluation of the off-resonance term
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffResonanceMethod {
+    /// exact evaluation
+    Exact,
+    /// time segmentation with the given number of segments
+    TimeSegmentation(usize),
+    /// multi-frequency interpolation with the given number of frequencies
+    MultiFrequency(usize),
+}
+
+/// Off-resonance frequencies of the voxels and readout times of the samples
+#[derive(Debug, Clone)]
+pub struct OffResonance {
+    /// off-resonance frequency of every voxel (Hz)
+    freq: Vec<f64>,
+    /// readout time of every sample (s)
+    times: Vec<f64>,
+    /// method
+    method: OffResonanceMethod,
+    /// temporal interpolators (`[term][sample]`)
+    b: Vec<Vec<Complex<f64>>>,
+    /// spatial coefficients (`[term][voxel]`)
+    c: Vec<Vec<Complex<f64>>>,
+}
+
+impl OffResonance {
+    /// Create the off-resonance term from the frequency of every voxel (Hz) and the readout
+    /// time of every sample (s)
+    pub fn new(freq: Vec<f64>, times: Vec<f64>, method: OffResonanceMethod) -> Result<Self> {
+        if freq.is_empty() || times.is_empty() {
+            return Err(MriError::InvalidParameter(
+                "off-resonance needs at least one voxel and one sample".to_string(),
+            ));
+        }
+        if !freq.iter().chain(times.iter()).all(|x| x.is_finite()) {
+            return Err(MriError::InvalidParameter(
+                "off-resonance frequencies and readout times must be finite".to_string(),
+            ));
+        }
+        let (b, c) = match method {
+            OffResonanceMethod::Exact => (vec![], vec![]),
+            OffResonanceMethod::TimeSegmentation(l) => {
+                let tau = nodes(&times, l)?;
+                let c = tau
+                    .iter()
+                    .map(|&t| freq.iter().map(|&f| phasor(f, t)).collect())
+                    .collect();
+                (interpolators(&tau, &freq, &times), c)
+            }
+            OffResonanceMethod::MultiFrequency(l) => {
+                let f = nodes(&freq, l)?;
+                let b = f
+                    .iter()
+                    .map(|&fl| times.iter().map(|&t| phasor(fl, t)).collect())
+                    .collect();
+                (b, interpolators(&f, &times, &freq))
+            }
+        };
+        Ok(OffResonance {
+            freq,
+            times,
+            method,
+            b,
+            c,
+        })
+    }
+
+    /// Create the off-resonance term from a B0 field map (Hz) for a grid with `dims` voxels
+    /// covering `fov`. The map is interpolated linearly if its grid differs.
+    pub fn from_field_map(
+        b0: &EncodingFieldDiscrete,
+        fov: &SpatialDims<f64>,
+        dims: &SpatialDims<usize>,
+        times: Vec<f64>,
+        method: OffResonanceMethod,
+    ) -> Result<Self> {
+        check_grid(fov, dims)?;
+        let freq = if b0.dimensions() == *dims && b0.affine() == grid_affine(fov, dims) {
+            b0.field.clone()
+        } else {
+            let f = b0.to_encoding_field(Interpolation::Linear)?;
+            fov.grid(dims).iter().map(|pos| f.at(pos)).collect()
+        };
+        OffResonance::new(freq, times, method)
+    }
+
+    /// Return the method
+    pub fn method(&self) -> OffResonanceMethod {
+        self.method
+    }
+
+    /// Return the off-resonance frequencies of the voxels (Hz)
+    pub fn frequencies(&self) -> &[f64] {
+        &self.freq
+    }
+
+    /// Return the readout times of the samples (s)
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Return the number of voxels
+    pub fn num_voxels(&self) -> usize {
+        self.freq.len()
+    }
+
+    /// Return the number of samples
+    pub fn num_samples(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Return the temporal interpolators `b_l(t)` of an approximation (`[term][sample]`), empty
+    /// for exact evaluation
+    pub fn interpolators(&self) -> &[Vec<Complex<f64>>] {
+        &self.b
+    }
+
+    /// Return the spatial coefficients `c_l(r)` of an approximation (`[term][voxel]`), empty
+    /// for exact evaluation
+    pub fn coefficients(&self) -> &[Vec<Complex<f64>>] {
+        &self.c
+    }
+
+    /// Return the number of terms `L` of an approximation, zero for exact evaluation
+    pub fn num_terms(&self) -> usize {
+        self.b.len()
+    }
+
+    /// Return the terms of an approximation, empty for exact evaluation
+    pub fn terms(&self) -> Vec<Term<'_>> {
+        self.b
+            .iter()
+            .zip(self.c.iter())
+            .map(|(b, c)| (b.as_slice(), c.as_slice()))
+            .collect()
+    }
+
+    /// Off-resonance term of voxel `r` at sample `m`
+    pub fn at(&self, m: usize, r: usize) -> Complex<f64> {
+        match self.method {
+            OffResonanceMethod::Exact => phasor(self.freq[r], self.times[m]),
+            _ => self
+                .b
+                .iter()
+                .zip(self.c.iter())
+                .map(|(b, c)| b[m] * c[r])
+                .sum(),
+        }
+    }
+
+    /// Check that the term fits an encoding operator
+    pub(crate) fn check(&self, num_samples: usize, num_voxels: usize) -> Result<()> {
+        check_dims(num_samples, self.num_samples())?;
+        check_dims(num_voxels, self.num_voxels())
+    }
+}
+
+/// `exp(-i 2 pi f t)`
+fn phasor(f: f64, t: f64) -> Complex<f64> {
+    Complex::from_polar(1.0, -2.0 * PI * f * t)
+}
+
+/// `num` equally spaced nodes covering the range of `x`
+fn nodes(x: &[f64], num: usize) -> Result<Vec<f64>> {
+    if num == 0 {
+        return Err(MriError::InvalidParameter(
+            "number of off-resonance terms must be positive".to_string(),
+        ));
+    }
+    let (min, max) = range(x);
+    if num == 1 {
+        return Ok(vec![0.5 * (min + max)]);
+    }
+    let step = (max - min) / ((num - 1) as f64);
+    Ok((0..num).map(|l| min + (l as f64) * step).collect())
+}
+
+/// Minimum and maximum
+fn range(x: &[f64]) -> (f64, f64) {
+    x.iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), &v| (a.min(v), b.max(v)))
+}
+
+/// Histogram of `x` as (mean of the bin, number of values) for all nonempty bins
+fn histogram(x: &[f64]) -> Vec<(f64, f64)> {
+    let (min, max) = range(x);
+    let width = (max - min) / (HISTOGRAM_BINS as f64);
+    let mut bins = vec![(0.0, 0.0); HISTOGRAM_BINS];
+    for &v in x {
+        let i = if width > 0.0 {
+            (((v - min) / width) as usize).min(HISTOGRAM_BINS - 1)
+        } else {
+            0
+        };
+        bins[i].0 += v;
+        bins[i].1 += 1.0;
+    }
+    bins.into_iter()
+        .filter(|b| b.1 > 0.0)
+        .map(|(s, n)| (s / n, n))
+        .collect()
+}
+
+/// Least squares interpolators `a_l(y)` for every `y` in `targets` such that
+/// `exp(-i 2 pi x y) ~ sum_l a_l(y) exp(-i 2 pi x nodes_l)`, weighted by the distribution of `x`
+/// in `support`. Returns `[term][target]`.
+fn interpolators(nodes: &[f64], support: &[f64], targets: &[f64]) -> Vec<Vec<Complex<f64>>> {
+    let hist = histogram(support);
+    let l = nodes.len();
+    // Gram matrix G_lj = sum_x w(x) conj(e(x, n_l)) e(x, n_j)
+    let mut gram: Vec<Vec<Complex<f64>>> = (0..l)
+        .map(|i| {
+            (0..l)
+                .map(|j| {
+                    hist.iter()
+                        .map(|&(x, w)| phasor(x, nodes[j] - nodes[i]) * w)
+                        .sum()
+                }).collect()
+        }).collect();
+    let trace: f64 = (0..l).map(|i| gram[i][i].re).sum();
+    for (i, g) in gram.iter_mut().enumerate() {
+        g[i] += REGULARIZATION * trace / (l as f64);
+    }
+    let inv = invert(gram);
+    let mut out = vec![Vec::with_capacity(targets.len()); l];
+    for &y in targets {
+        let rhs: Vec<Complex<f64>> = nodes
+            .iter()
+            .map(|&n| hist.iter().map(|&(x, w)| phasor(x, y - n) * w).sum())
+            .collect();
+        for (o, row) in out.iter_mut().zip(inv.iter()) {
+            o.push(row.iter().zip(rhs.iter()).map(|(a, b)| a * b).sum());
+        }
+    }
+    out
+}
+
+/// Inverse of a regular matrix with Gauss-Jordan elimination and partial pivoting
+fn invert(mut a: Vec<Vec<Complex<f64>>>) -> Vec<Vec<Complex<f64>>> {
+    let n = a.len();
+    let mut inv: Vec<Vec<Complex<f64>>> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| Complex::new(if i == j { 1.0 } else { 0.0 }, 0.0))
+                .collect()
+        }).collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].norm().partial_cmp(&a[j][col].norm()).unwrap())
+            .unwrap();
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let p = a[col][col];
+        for j in 0..n {
+            a[col][j] /= p;
+            inv[col][j] /= p;
+        }
+        for i in 0..n {
+            if i != col {
+                let f = a[i][col];
+                for j in 0..n {
+                    let (ac, ic) = (a[col][j], inv[col][j]);
+                    a[i][j] -= f * ac;
+                    inv[i][j] -= f * ic;
+                }
+            }
+        }
+    }
+    inv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::Rng;
+
+    /// deterministic pseudo random numbers in [-1, 1)
+    fn random(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| 2.0 * rng.uniform() - 1.0).collect()
+    }
+
+    /// Largest deviation of an approximation from the exact off-resonance term
+    fn max_error(freq: &[f64], times: &[f64], method: OffResonanceMethod) -> f64 {
+        let o = OffResonance::new(freq.to_vec(), times.to_vec(), method).unwrap();
+        let mut err: f64 = 0.0;
+        for (m, &t) in times.iter().enumerate() {
+            for (r, &f) in freq.iter().enumerate() {
+                err = err.max((o.at(m, r) - phasor(f, t)).norm());
+            }
+        }
+        err
+    }
+
+    #[test]
+    fn error_falls_with_terms() {
+        // +-150 Hz during a 10 ms readout
+        let freq: Vec<f64> = random(100, 3).iter().map(|x| 150.0 * x).collect();
+        let times: Vec<f64> = (0..200).map(|m| 5e-5 * m as f64).collect();
+        for &approximation in &[
+            OffResonanceMethod::TimeSegmentation as fn(usize) -> OffResonanceMethod,
+            OffResonanceMethod::MultiFrequency,
+        ] {
+            let errors: Vec<f64> = [2, 4, 6, 8, 12]
+                .iter()
+                .map(|&l| max_error(&freq, &times, approximation(l)))
+                .collect();
+            for w in errors.windows(2) {
+                assert!(w[1] < w[0], "{:?}", errors);
+            }
+            assert!(errors[4] < 1e-3, "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn terms() {
+        let freq = vec![-20.0, 0.0, 35.0];
+        let times = vec![0.0, 1e-3, 2e-3, 3e-3];
+        let exact = OffResonance::new(freq.clone(), times.clone(), OffResonanceMethod::Exact);
+        assert_eq!(exact.unwrap().num_terms(), 0);
+        let o = OffResonance::new(freq, times, OffResonanceMethod::TimeSegmentation(3)).unwrap();
+        assert_eq!(o.num_terms(), 3);
+        for (b, c) in o.terms() {
+            assert_eq!((b.len(), c.len()), (4, 3));
+        }
+        let sum: Complex<f64> = o.terms().iter().map(|(b, c)| b[2] * c[1]).sum();
+        assert!((sum - o.at(2, 1)).norm() < 1e-15);
+    }
+
+    #[test]
+    fn invalid_input() {
+        let method = OffResonanceMethod::MultiFrequency(0);
+        assert!(OffResonance::new(vec![1.0], vec![0.0], method).is_err());
+        let method = OffResonanceMethod::Exact;
+        assert!(OffResonance::new(vec![], vec![0.0], method).is_err());
+        assert!(OffResonance::new(vec![f64::NAN], vec![0.0], method).is_err());
+    }
+}
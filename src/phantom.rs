@@ -0,0 +1,468 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Numerical phantoms
+//!
+//! Phantoms are sums of ellipses (2D) or ellipsoids (3D) with constant intensity. They can be
+//! rasterized on a voxel grid and their continuous Fourier transform
+//! `F(k) = int m(r) exp(-i 2 pi k r) dr` can be evaluated analytically at arbitrary k-space
+//! samples. Note that this is an integral: the sum over the voxels of a rasterized phantom
+//! (as computed by `EncodingMatrix`) approximates `F(k)` divided by the voxel volume.
+//!
+//! The ellipsoids are defined in normalized coordinates, where the standard phantoms fit into
+//! `[-1, 1]`, and are scaled to physical positions with `Phantom::scale`.
+
+use error::{check_dims, MriError, Result};
+use num::Complex;
+use std::f64::consts::{FRAC_2_PI, FRAC_PI_4, PI};
+use KSpaceThings;
+use SpatialDims;
+
+/// A single ellipse or ellipsoid of constant intensity
+#[derive(Debug, Clone)]
+pub struct Ellipsoid {
+    /// intensity (added to overlapping ellipsoids)
+    pub intensity: f64,
+    /// center `(x, y, z)`
+    pub center: [f64; 3],
+    /// semi-axes `(a, b, c)`
+    pub axes: [f64; 3],
+    /// Euler angles `(phi, theta, psi)` in rad, z-x-z convention
+    pub angles: [f64; 3],
+}
+
+impl Ellipsoid {
+    /// Constructor
+    pub fn new(intensity: f64, center: [f64; 3], axes: [f64; 3], angles: [f64; 3]) -> Self {
+        Ellipsoid {
+            intensity,
+            center,
+            axes,
+            angles,
+        }
+    }
+
+    /// An ellipse in the xy-plane, rotated counterclockwise by `phi` (in rad)
+    pub fn ellipse(intensity: f64, center: [f64; 2], axes: [f64; 2], phi: f64) -> Self {
+        Ellipsoid::new(
+            intensity,
+            [center[0], center[1], 0.0],
+            [axes[0], axes[1], 1.0],
+            [phi, 0.0, 0.0],
+        )
+    }
+
+    /// Rotation from the phantom into the ellipsoid frame
+    fn rotation(&self) -> [[f64; 3]; 3] {
+        let (sphi, cphi) = self.angles[0].sin_cos();
+        let (stheta, ctheta) = self.angles[1].sin_cos();
+        let (spsi, cpsi) = self.angles[2].sin_cos();
+        [
+            [
+                cpsi * cphi - ctheta * sphi * spsi,
+                cpsi * sphi + ctheta * cphi * spsi,
+                spsi * stheta,
+            ],
+            [
+                -spsi * cphi - ctheta * sphi * cpsi,
+                -spsi * sphi + ctheta * cphi * cpsi,
+                cpsi * stheta,
+            ],
+            [stheta * sphi, -stheta * cphi, ctheta],
+        ]
+    }
+
+    /// Position relative to the center in the ellipsoid frame, in units of the semi-axes
+    fn normalize(&self, r: &[f64; 3], num_dims: usize) -> [f64; 3] {
+        let rot = self.rotation();
+        let d = [
+            r[0] - self.center[0],
+            r[1] - self.center[1],
+            r[2] - self.center[2],
+        ];
+        let mut u = [0.0; 3];
+        for i in 0..num_dims {
+            u[i] = (0..num_dims).map(|j| rot[i][j] * d[j]).sum::<f64>() / self.axes[i];
+        }
+        u
+    }
+
+    /// true if `r` is inside the ellipsoid
+    fn contains(&self, r: &[f64; 3], num_dims: usize) -> bool {
+        let u = self.normalize(r, num_dims);
+        u.iter().map(|x| x * x).sum::<f64>() <= 1.0
+    }
+
+    /// Fourier transform of the ellipsoid at `k`
+    fn transform(&self, k: &[f64; 3], num_dims: usize) -> Complex<f64> {
+        let rot = self.rotation();
+        // |D R k| with the semi-axes D
+        let q = rot
+            .iter()
+            .zip(self.axes.iter())
+            .take(num_dims)
+            .map(|(row, a)| {
+                let ki: f64 = row.iter().zip(k.iter()).take(num_dims).map(|(r, x)| r * x).sum();
+                (ki * a).powi(2)
+            }).sum::<f64>()
+            .sqrt();
+        let volume: f64 = self.axes[..num_dims].iter().product();
+        let kc: f64 = (0..num_dims).map(|i| k[i] * self.center[i]).sum();
+        let shape = if num_dims == 2 {
+            unit_disc_transform(q)
+        } else {
+            unit_ball_transform(q)
+        };
+        Complex::from_polar(self.intensity * volume * shape, -2.0 * PI * kc)
+    }
+}
+
+/// A phantom made of ellipses (2D) or ellipsoids (3D)
+#[derive(Debug, Clone)]
+pub struct Phantom {
+    /// ellipses or ellipsoids
+    ellipsoids: Vec<Ellipsoid>,
+    /// number of spatial dimensions (2 or 3)
+    num_dims: usize,
+    /// physical length of one normalized unit
+    scale: f64,
+}
+
+impl Phantom {
+    /// Create an empty phantom with 2 or 3 spatial dimensions
+    pub fn new(num_dims: usize) -> Self {
+        Phantom::try_new(num_dims).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create an empty phantom, failing unless `num_dims` is 2 or 3
+    pub fn try_new(num_dims: usize) -> Result<Self> {
+        if num_dims != 2 && num_dims != 3 {
+            return Err(MriError::InvalidParameter(format!(
+                "phantoms need 2 or 3 dimensions, found {}",
+                num_dims
+            )));
+        }
+        Ok(Phantom {
+            ellipsoids: vec![],
+            num_dims,
+            scale: 1.0,
+        })
+    }
+
+    /// Create a 2D phantom from a table of `[a, b, x0, y0, phi]` rows, with `phi` in degrees
+    fn from_ellipses(intensities: &[f64], table: &[[f64; 5]]) -> Self {
+        let mut out = Phantom::new(2);
+        for (&a, e) in intensities.iter().zip(table.iter()) {
+            out.add(Ellipsoid::ellipse(a, [e[2], e[3]], [e[0], e[1]], e[4].to_radians()));
+        }
+        out
+    }
+
+    /// Create a 3D phantom from a table of `[a, b, c, x0, y0, z0, phi, theta, psi]` rows, with
+    /// angles in degrees
+    fn from_ellipsoids(intensities: &[f64], table: &[[f64; 9]]) -> Self {
+        let mut out = Phantom::new(3);
+        for (&a, e) in intensities.iter().zip(table.iter()) {
+            out.add(Ellipsoid::new(
+                a,
+                [e[3], e[4], e[5]],
+                [e[0], e[1], e[2]],
+                [e[6].to_radians(), e[7].to_radians(), e[8].to_radians()],
+            ));
+        }
+        out
+    }
+
+    /// 2D Shepp-Logan phantom with the original intensities
+    pub fn shepp_logan_2d() -> Self {
+        Phantom::from_ellipses(&SHEPP_LOGAN_INTENSITIES, &SHEPP_LOGAN_2D)
+    }
+
+    /// 2D Shepp-Logan phantom with the higher contrast intensities of Toft
+    pub fn modified_shepp_logan_2d() -> Self {
+        Phantom::from_ellipses(&MODIFIED_SHEPP_LOGAN_INTENSITIES, &SHEPP_LOGAN_2D)
+    }
+
+    /// 3D Shepp-Logan phantom with the original intensities
+    pub fn shepp_logan_3d() -> Self {
+        Phantom::from_ellipsoids(&SHEPP_LOGAN_INTENSITIES, &SHEPP_LOGAN_3D)
+    }
+
+    /// 3D Shepp-Logan phantom with the higher contrast intensities of Toft
+    pub fn modified_shepp_logan_3d() -> Self {
+        Phantom::from_ellipsoids(&MODIFIED_SHEPP_LOGAN_INTENSITIES, &SHEPP_LOGAN_3D)
+    }
+
+    /// 3D phantom of Yu, Ye and Wang, "Katsevich-type algorithms for variable radius spiral
+    /// cone-beam CT", Proc. SPIE 5535, 2004.
+    pub fn yu_ye_wang() -> Self {
+        Phantom::from_ellipsoids(&YU_YE_WANG_INTENSITIES, &YU_YE_WANG)
+    }
+
+    /// Add an ellipse or ellipsoid
+    pub fn add(&mut self, ellipsoid: Ellipsoid) -> &mut Self {
+        self.ellipsoids.push(ellipsoid);
+        self
+    }
+
+    /// Set the physical length of one normalized unit, for instance half the FOV
+    pub fn scale(&mut self, scale: f64) -> &mut Self {
+        assert!(scale > 0.0);
+        self.scale = scale;
+        self
+    }
+
+    /// Return the ellipses or ellipsoids
+    pub fn ellipsoids(&self) -> &[Ellipsoid] {
+        &self.ellipsoids
+    }
+
+    /// Return the number of spatial dimensions
+    pub fn num_dims(&self) -> usize {
+        self.num_dims
+    }
+
+    /// Intensity at a position
+    pub fn at(&self, pos: &SpatialDims<f64>) -> Result<f64> {
+        check_dims(self.num_dims, pos.len())?;
+        let mut r = [0.0; 3];
+        for (ri, p) in r.iter_mut().zip(pos.clone()) {
+            *ri = p / self.scale;
+        }
+        Ok(self
+            .ellipsoids
+            .iter()
+            .filter(|e| e.contains(&r, self.num_dims))
+            .map(|e| e.intensity)
+            .sum())
+    }
+
+    /// Intensity at the points of a grid with `dims` voxels covering `fov`, as given by
+    /// `SpatialDims::grid`
+    pub fn rasterize(&self, fov: &SpatialDims<f64>, dims: &SpatialDims<usize>) -> Result<Vec<f64>> {
+        check_dims(self.num_dims, dims.len())?;
+        check_dims(self.num_dims, fov.len())?;
+        fov.grid(dims).iter().map(|pos| self.at(pos)).collect()
+    }
+
+    /// Analytic Fourier transform at the samples of a trajectory, in the order of
+    /// `KSpaceThings::samples`
+    pub fn kspace<T: KSpaceThings>(&self, k: &T) -> Result<Vec<Complex<f64>>> {
+        check_dims(self.num_dims, k.num_channels())?;
+        let volume = self.scale.powi(self.num_dims as i32);
+        Ok(k
+            .samples()
+            .iter()
+            .map(|s| {
+                let mut kn = [0.0; 3];
+                for (ki, x) in kn.iter_mut().zip(s.iter()) {
+                    *ki = x * self.scale;
+                }
+                self.ellipsoids
+                    .iter()
+                    .map(|e| e.transform(&kn, self.num_dims))
+                    .sum::<Complex<f64>>()
+                    * volume
+            }).collect())
+    }
+}
+
+/// Intensities of the original Shepp-Logan phantom
+const SHEPP_LOGAN_INTENSITIES: [f64; 10] = [
+    1.0, -0.98, -0.02, -0.02, 0.01, 0.01, 0.01, 0.01, 0.01, 0.01,
+];
+
+/// Intensities of the modified Shepp-Logan phantom
+const MODIFIED_SHEPP_LOGAN_INTENSITIES: [f64; 10] =
+    [1.0, -0.8, -0.2, -0.2, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1];
+
+/// Ellipses of the 2D Shepp-Logan phantom, `[a, b, x0, y0, phi]`
+const SHEPP_LOGAN_2D: [[f64; 5]; 10] = [
+    [0.69, 0.92, 0.0, 0.0, 0.0],
+    [0.6624, 0.874, 0.0, -0.0184, 0.0],
+    [0.11, 0.31, 0.22, 0.0, -18.0],
+    [0.16, 0.41, -0.22, 0.0, 18.0],
+    [0.21, 0.25, 0.0, 0.35, 0.0],
+    [0.046, 0.046, 0.0, 0.1, 0.0],
+    [0.046, 0.046, 0.0, -0.1, 0.0],
+    [0.046, 0.023, -0.08, -0.605, 0.0],
+    [0.023, 0.023, 0.0, -0.606, 0.0],
+    [0.023, 0.046, 0.06, -0.605, 0.0],
+];
+
+/// Ellipsoids of the 3D Shepp-Logan phantom, `[a, b, c, x0, y0, z0, phi, theta, psi]`
+const SHEPP_LOGAN_3D: [[f64; 9]; 10] = [
+    [0.69, 0.92, 0.81, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [0.6624, 0.874, 0.78, 0.0, -0.0184, 0.0, 0.0, 0.0, 0.0],
+    [0.11, 0.31, 0.22, 0.22, 0.0, 0.0, -18.0, 0.0, 10.0],
+    [0.16, 0.41, 0.28, -0.22, 0.0, 0.0, 18.0, 0.0, 10.0],
+    [0.21, 0.25, 0.41, 0.0, 0.35, -0.15, 0.0, 0.0, 0.0],
+    [0.046, 0.046, 0.05, 0.0, 0.1, 0.25, 0.0, 0.0, 0.0],
+    [0.046, 0.046, 0.05, 0.0, -0.1, 0.25, 0.0, 0.0, 0.0],
+    [0.046, 0.023, 0.05, -0.08, -0.605, 0.0, 0.0, 0.0, 0.0],
+    [0.023, 0.023, 0.02, 0.0, -0.606, 0.0, 0.0, 0.0, 0.0],
+    [0.023, 0.046, 0.02, 0.06, -0.605, 0.0, 0.0, 0.0, 0.0],
+];
+
+/// Intensities of the Yu-Ye-Wang phantom
+const YU_YE_WANG_INTENSITIES: [f64; 10] = [1.0, -0.8, -0.2, -0.2, 0.2, 0.2, 0.1, 0.1, 0.2, -0.2];
+
+/// Ellipsoids of the Yu-Ye-Wang phantom, `[a, b, c, x0, y0, z0, phi, theta, psi]`
+const YU_YE_WANG: [[f64; 9]; 10] = [
+    [0.69, 0.92, 0.9, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [0.6624, 0.874, 0.88, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [0.41, 0.16, 0.21, -0.22, 0.0, -0.25, 108.0, 0.0, 0.0],
+    [0.31, 0.11, 0.22, 0.22, 0.0, -0.25, 72.0, 0.0, 0.0],
+    [0.21, 0.25, 0.5, 0.0, 0.35, -0.25, 0.0, 0.0, 0.0],
+    [0.046, 0.046, 0.046, 0.0, 0.1, -0.25, 0.0, 0.0, 0.0],
+    [0.046, 0.023, 0.02, -0.08, -0.65, -0.25, 0.0, 0.0, 0.0],
+    [0.046, 0.023, 0.02, 0.06, -0.65, -0.25, 90.0, 0.0, 0.0],
+    [0.056, 0.04, 0.1, 0.06, -0.105, 0.625, 90.0, 0.0, 0.0],
+    [0.056, 0.056, 0.1, 0.0, 0.1, 0.625, 0.0, 0.0, 0.0],
+];
+
+/// Fourier transform of the unit disc, `J1(2 pi q) / q`
+fn unit_disc_transform(q: f64) -> f64 {
+    if q < 1e-8 {
+        PI
+    } else {
+        bessel_j1(2.0 * PI * q) / q
+    }
+}
+
+/// Fourier transform of the unit ball, `(sin(x) - x cos(x)) / (2 pi^2 q^3)` with `x = 2 pi q`
+fn unit_ball_transform(q: f64) -> f64 {
+    let x = 2.0 * PI * q;
+    if x < 1e-3 {
+        // Taylor expansion, avoids cancellation
+        4.0 * PI / 3.0 * (1.0 - x * x / 10.0)
+    } else {
+        (x.sin() - x * x.cos()) / (2.0 * PI * PI * q * q * q)
+    }
+}
+
+/// Bessel function of the first kind of order one (Numerical Recipes, `bessj1`)
+fn bessel_j1(x: f64) -> f64 {
+    let ax = x.abs();
+    if ax < 8.0 {
+        let y = x * x;
+        let num = x * (72_362_614_232.0
+            + y * (-7_895_059_235.0
+                + y * (242_396_853.1
+                    + y * (-2_972_611.439 + y * (15_704.482_60 + y * (-30.160_366_06))))));
+        let den = 144_725_228_442.0
+            + y * (2_300_535_178.0
+                + y * (18_583_304.74 + y * (99_447.433_94 + y * (376.999_139_7 + y))));
+        num / den
+    } else {
+        let z = 8.0 / ax;
+        let y = z * z;
+        let xx = ax - 3.0 * FRAC_PI_4;
+        let p = 1.0
+            + y * (0.183_105e-2
+                + y * (-0.351_639_649_6e-4 + y * (0.245_752_017_4e-5 + y * (-0.240_337_019e-6))));
+        let q = 0.046_874_999_95
+            + y * (-0.200_269_087_3e-3
+                + y * (0.844_919_909_6e-5 + y * (-0.882_289_87e-6 + y * 0.105_787_412e-6)));
+        let ans = (FRAC_2_PI / ax).sqrt() * (xx.cos() * p - z * xx.sin() * q);
+        if x < 0.0 {
+            -ans
+        } else {
+            ans
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KSpace;
+
+    /// Check the analytic Fourier transform of a phantom against a DFT of the phantom rasterized
+    /// on `n` points per dimension covering `[-1, 1]`
+    fn check_transform(phantom: &Phantom, n: usize, samples: &[Vec<f64>], tol: f64) {
+        let d = phantom.num_dims();
+        let (fov, dims) = if d == 2 {
+            (SpatialDims::TwoD(2.0, 2.0), SpatialDims::TwoD(n, n))
+        } else {
+            (SpatialDims::ThreeD(2.0, 2.0, 2.0), SpatialDims::ThreeD(n, n, n))
+        };
+        let img = phantom.rasterize(&fov, &dims).unwrap();
+        let grid = fov.grid(&dims);
+        let voxel = (2.0 / (n as f64)).powi(d as i32);
+
+        let mut k = KSpace::new();
+        for s in samples {
+            k.add(s.clone());
+        }
+        let analytic = phantom.kspace(&k).unwrap();
+        let scale = analytic[0].norm();
+        for (s, a) in samples.iter().zip(analytic.iter()) {
+            let dft: Complex<f64> = grid
+                .iter()
+                .zip(img.iter())
+                .filter(|&(_, &m)| m != 0.0)
+                .map(|(pos, &m)| {
+                    let kr: f64 = pos.clone().into_iter().zip(s.iter()).map(|(p, k)| p * k).sum();
+                    Complex::from_polar(m * voxel, -2.0 * PI * kr)
+                }).sum();
+            assert!(
+                (dft - a).norm() < tol * scale,
+                "k = {:?}: {} vs {}",
+                s,
+                dft,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn shepp_logan_2d_transform() {
+        let samples = vec![
+            vec![0.0, 0.0],
+            vec![0.5, 0.0],
+            vec![0.0, -1.0],
+            vec![1.5, 2.0],
+            vec![-3.0, 1.0],
+            vec![4.0, -5.0],
+        ];
+        check_transform(&Phantom::modified_shepp_logan_2d(), 512, &samples, 2e-3);
+    }
+
+    #[test]
+    fn shepp_logan_3d_transform() {
+        let samples = vec![
+            vec![0.0, 0.0, 0.0],
+            vec![0.5, 0.0, 0.0],
+            vec![0.0, -1.0, 0.5],
+            vec![1.5, 0.0, -2.0],
+            vec![-1.0, 2.0, 1.0],
+        ];
+        check_transform(&Phantom::modified_shepp_logan_3d(), 64, &samples, 1e-2);
+    }
+
+    #[test]
+    fn invalid_dimensions() {
+        assert!(Phantom::try_new(1).is_err());
+        assert!(Phantom::try_new(4).is_err());
+        assert_eq!(Phantom::new(3).num_dims(), 3);
+    }
+
+    #[test]
+    fn scaled_transform() {
+        // scaling the phantom by `s` scales the transform by `s^d` and the k axes by `1 / s`
+        let mut scaled = Phantom::shepp_logan_2d();
+        scaled.scale(0.1);
+        let mut k = KSpace::new();
+        k.add(vec![10.0, -20.0]);
+        let mut k1 = KSpace::new();
+        k1.add(vec![1.0, -2.0]);
+        let a = scaled.kspace(&k).unwrap()[0];
+        let b = Phantom::shepp_logan_2d().kspace(&k1).unwrap()[0] * 0.01;
+        assert!((a - b).norm() < 1e-12 * b.norm().max(1.0));
+    }
+}
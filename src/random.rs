@@ -0,0 +1,99 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Reproducible pseudo random numbers
+
+use std::f64::consts::PI;
+
+/// SplitMix64 generator. Not suitable for cryptography, but fast, small and reproducible across
+/// platforms.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+    /// second normal deviate of the last Box-Muller transform
+    spare: Option<f64>,
+}
+
+impl Rng {
+    /// Create a generator from a seed
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng {
+            state: seed,
+            spare: None,
+        }
+    }
+
+    /// Next 64 random bits
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `0..n`, without the bias of `next_u64() % n` (rejection
+    /// sampling). Panics if `n` is 0.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "empty range");
+        // reject the lowest 2^64 mod n values, such that every residue is equally likely
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold {
+                return x % n;
+            }
+        }
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    pub(crate) fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / ((1u64 << 53) as f64)
+    }
+
+    /// Standard normal distribution (Box-Muller transform)
+    pub(crate) fn normal(&mut self) -> f64 {
+        if let Some(x) = self.spare.take() {
+            return x;
+        }
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        let r = (-2.0 * u.ln()).sqrt();
+        let (s, c) = (2.0 * PI * v).sin_cos();
+        self.spare = Some(r * s);
+        r * c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn below() {
+        let mut rng = Rng::new(3);
+        assert!((0..1000).all(|_| rng.below(1) == 0));
+
+        // chi-squared test of the counts of a range that does not divide 2^64
+        let (n, draws) = (7, 70_000);
+        let mut counts = vec![0usize; n];
+        for _ in 0..draws {
+            counts[rng.below(n as u64) as usize] += 1;
+        }
+        let expected = (draws / n) as f64;
+        let chi2: f64 = counts
+            .iter()
+            .map(|&c| (c as f64 - expected).powi(2) / expected)
+            .sum();
+        // 99.9% quantile for 6 degrees of freedom
+        assert!(chi2 < 22.46);
+
+        // the largest ranges are rejected about half of the time and stay in range
+        let big = (1u64 << 63) + 1;
+        assert!((0..1000).all(|_| rng.below(big) < big));
+    }
+}
@@ -5,11 +5,14 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-//! MRI
+//! RF sensitivities
 
-/// todo
+use num::Complex;
+
+/// Sensitivities of an array of RF coils
+#[derive(Debug, Clone, Default)]
 pub struct RFSensitivityArray {
-    /// todo
+    /// sensitivity of every coil
     pub array: Vec<RFSensitivity>,
 }
 
@@ -24,17 +27,189 @@ impl RFSensitivityArray {
         self.array.push(rf);
         self
     }
+
+    /// Return the number of coils
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// true if there are no coils
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty()
+    }
+
+    /// Root sum of squares of the sensitivity magnitudes in every voxel
+    pub fn sum_of_squares(&self) -> Vec<f64> {
+        let maps: Vec<Vec<Complex<f64>>> = self.array.iter().map(|s| s.sens.clone()).collect();
+        sum_of_squares(&maps)
+    }
 }
 
-/// todo
+/// Complex sensitivity of a single RF coil, one value per voxel
+#[derive(Debug, Clone)]
 pub struct RFSensitivity {
-    /// todo
-    pub sens: Vec<(f64, f64)>,
+    /// sensitivity in every voxel
+    pub sens: Vec<Complex<f64>>,
 }
 
 impl RFSensitivity {
     /// Constructor
-    pub fn new(sens: Vec<(f64, f64)>) -> Self {
+    pub fn new(sens: Vec<Complex<f64>>) -> Self {
         RFSensitivity { sens }
     }
+
+    /// Create sensitivity from `(re, im)` tuples
+    pub fn from_tuples(sens: Vec<(f64, f64)>) -> Self {
+        RFSensitivity {
+            sens: sens
+                .into_iter()
+                .map(|(re, im)| Complex::new(re, im))
+                .collect(),
+        }
+    }
+
+    /// Create sensitivity from magnitude and phase (in rad) maps
+    pub fn from_polar(magnitude: &[f64], phase: &[f64]) -> Self {
+        assert!(magnitude.len() == phase.len());
+        RFSensitivity {
+            sens: magnitude
+                .iter()
+                .zip(phase.iter())
+                .map(|(&m, &p)| Complex::from_polar(m, p))
+                .collect(),
+        }
+    }
+
+    /// Return the number of voxels
+    pub fn len(&self) -> usize {
+        self.sens.len()
+    }
+
+    /// true if the sensitivity has no voxels
+    pub fn is_empty(&self) -> bool {
+        self.sens.is_empty()
+    }
+
+    /// Magnitude in every voxel
+    pub fn magnitude(&self) -> Vec<f64> {
+        self.sens.iter().map(|s| s.norm()).collect()
+    }
+
+    /// Phase (in rad) in every voxel
+    pub fn phase(&self) -> Vec<f64> {
+        self.sens.iter().map(|s| s.arg()).collect()
+    }
+
+    /// Multiply an image voxel-wise with the sensitivity
+    pub fn mul(&self, img: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        assert!(img.len() == self.sens.len());
+        self.sens.iter().zip(img.iter()).map(|(s, x)| s * x).collect()
+    }
+
+    /// Multiply an image voxel-wise with the complex conjugate of the sensitivity
+    pub fn conj_mul(&self, img: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        assert!(img.len() == self.sens.len());
+        self.sens
+            .iter()
+            .zip(img.iter())
+            .map(|(s, x)| s.conj() * x)
+            .collect()
+    }
+}
+
+impl From<Vec<(f64, f64)>> for RFSensitivity {
+    /// Interprets the tuples as `(re, im)`
+    fn from(sens: Vec<(f64, f64)>) -> Self {
+        RFSensitivity::from_tuples(sens)
+    }
+}
+
+impl From<Vec<Complex<f64>>> for RFSensitivity {
+    fn from(sens: Vec<Complex<f64>>) -> Self {
+        RFSensitivity::new(sens)
+    }
+}
+
+/// Root sum of squares combination of coil images (`[coil][voxel]`)
+pub fn sum_of_squares(images: &[Vec<Complex<f64>>]) -> Vec<f64> {
+    let num_voxels = images.first().map_or(0, |i| i.len());
+    assert!(images.iter().all(|i| i.len() == num_voxels));
+    (0..num_voxels)
+        .map(|r| {
+            images
+                .iter()
+                .map(|i| i[r].norm_sqr())
+                .sum::<f64>()
+                .sqrt()
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn close(a: Complex<f64>, b: Complex<f64>) -> bool {
+        (a - b).norm() < 1e-12
+    }
+
+    #[test]
+    fn constructors() {
+        let s = RFSensitivity::from_tuples(vec![(1.0, 2.0), (-3.0, 0.5)]);
+        assert_eq!(s.sens, vec![Complex::new(1.0, 2.0), Complex::new(-3.0, 0.5)]);
+        assert_eq!(s.len(), 2);
+        assert!(!s.is_empty());
+
+        let t: RFSensitivity = vec![(1.0, 2.0), (-3.0, 0.5)].into();
+        assert_eq!(t.sens, s.sens);
+        let u: RFSensitivity = s.sens.clone().into();
+        assert_eq!(u.sens, s.sens);
+
+        let p = RFSensitivity::from_polar(&[2.0, 1.0, 0.5], &[0.0, FRAC_PI_2, PI]);
+        assert!(close(p.sens[0], Complex::new(2.0, 0.0)));
+        assert!(close(p.sens[1], Complex::new(0.0, 1.0)));
+        assert!(close(p.sens[2], Complex::new(-0.5, 0.0)));
+    }
+
+    #[test]
+    fn magnitude_and_phase() {
+        let s = RFSensitivity::from_tuples(vec![(3.0, 4.0), (0.0, -2.0), (-1.0, 0.0)]);
+        assert_eq!(s.magnitude(), vec![5.0, 2.0, 1.0]);
+        let phase = s.phase();
+        assert!((phase[0] - (4.0f64).atan2(3.0)).abs() < 1e-12);
+        assert!((phase[1] + FRAC_PI_2).abs() < 1e-12);
+        assert!((phase[2] - PI).abs() < 1e-12);
+
+        // magnitude and phase reproduce the sensitivity
+        let p = RFSensitivity::from_polar(&s.magnitude(), &phase);
+        for (a, &b) in p.sens.iter().zip(s.sens.iter()) {
+            assert!(close(*a, b));
+        }
+    }
+
+    #[test]
+    fn multiplication() {
+        let s = RFSensitivity::from_tuples(vec![(1.0, 1.0), (0.0, 2.0)]);
+        let img = vec![Complex::new(2.0, -1.0), Complex::new(1.0, 3.0)];
+        let m = s.mul(&img);
+        assert!(close(m[0], Complex::new(3.0, 1.0)));
+        assert!(close(m[1], Complex::new(-6.0, 2.0)));
+        let c = s.conj_mul(&img);
+        assert!(close(c[0], Complex::new(1.0, -3.0)));
+        assert!(close(c[1], Complex::new(6.0, -2.0)));
+    }
+
+    #[test]
+    fn root_sum_of_squares() {
+        let mut array = RFSensitivityArray::new();
+        assert!(array.is_empty());
+        array
+            .push(RFSensitivity::from_tuples(vec![(3.0, 0.0), (0.0, 1.0)]))
+            .push(RFSensitivity::from_tuples(vec![(0.0, 4.0), (1.0, 0.0)]));
+        assert_eq!(array.len(), 2);
+        let rss = array.sum_of_squares();
+        assert!((rss[0] - 5.0).abs() < 1e-12);
+        assert!((rss[1] - 2f64.sqrt()).abs() < 1e-12);
+        assert!(sum_of_squares(&[]).is_empty());
+    }
 }
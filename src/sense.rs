@@ -0,0 +1,335 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Conjugate gradient SENSE
+//!
+//! Solves `(E^H E + lambda I) x = E^H y` with the conjugate gradient method, where `E` is the
+//! encoding matrix and `y` the multi-coil k-space data. See Pruessmann et al., "Advances in
+//! sensitivity encoding with arbitrary k-space trajectories", MRM 46(4), 2001.
+
+use encodingmatrix::EncodingMatrix;
+use error::{check_dims, MriError, Result};
+use linalg::{dot, norm_sqr};
+use num::Complex;
+use rf::RFSensitivityArray;
+use std::rc::Rc;
+use EncodingField;
+use KSpaceThings;
+use SpatialDims;
+
+/// Called after every iteration with the iteration number, the current image and the relative
+/// residual `|r| / |E^H y|`
+pub type CgCallback = Rc<dyn Fn(usize, &[Complex<f64>], f64)>;
+
+/// Default maximum number of iterations
+pub const DEFAULT_MAX_ITER: usize = 50;
+/// Default relative residual at which the iterations are stopped
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+/// CG-SENSE reconstruction
+pub struct CgSense<T>
+where
+    T: KSpaceThings,
+{
+    /// encoding matrix
+    e: EncodingMatrix<T>,
+    /// Tikhonov regularization parameter
+    lambda: f64,
+    /// maximum number of iterations
+    max_iter: usize,
+    /// relative residual tolerance
+    tol: f64,
+    /// per-iteration callback
+    callback: Option<CgCallback>,
+}
+
+impl<T: KSpaceThings> CgSense<T> {
+    /// Create a reconstruction for trajectory `k`, encoding fields `psi` and coil sensitivities
+    /// `rf` on a grid of `dims` voxels covering `fov`
+    pub fn new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Self {
+        CgSense::from_encoding_matrix(EncodingMatrix::new(k, psi, rf.array, fov, dims))
+    }
+
+    /// Create a reconstruction, failing if the trajectory, fields, sensitivities and grid do not
+    /// fit together
+    pub fn try_new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Result<Self> {
+        EncodingMatrix::try_new(k, psi, rf.array, fov, dims).map(CgSense::from_encoding_matrix)
+    }
+
+    /// Create a reconstruction for an existing encoding matrix
+    pub fn from_encoding_matrix(e: EncodingMatrix<T>) -> Self {
+        CgSense {
+            e,
+            lambda: 0.0,
+            max_iter: DEFAULT_MAX_ITER,
+            tol: DEFAULT_TOLERANCE,
+            callback: None,
+        }
+    }
+
+    /// Set the Tikhonov regularization parameter
+    pub fn lambda(&mut self, lambda: f64) -> &mut Self {
+        assert!(lambda >= 0.0);
+        self.lambda = lambda;
+        self
+    }
+
+    /// Set the maximum number of iterations
+    pub fn max_iter(&mut self, max_iter: usize) -> &mut Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Set the relative residual at which the iterations are stopped
+    pub fn tolerance(&mut self, tol: f64) -> &mut Self {
+        self.tol = tol;
+        self
+    }
+
+    /// Set a callback which is called after every iteration
+    pub fn callback(&mut self, callback: CgCallback) -> &mut Self {
+        self.callback = Some(callback);
+        self
+    }
+
+    /// Return the encoding matrix
+    pub fn encoding_matrix(&self) -> &EncodingMatrix<T> {
+        &self.e
+    }
+
+    /// Apply `E^H E + lambda I`
+    fn normal(&self, x: &[Complex<f64>]) -> Vec<Complex<f64>> {
+        self.e
+            .apply_adjoint(&self.e.apply(x))
+            .iter()
+            .zip(x.iter())
+            .map(|(a, b)| a + b * self.lambda)
+            .collect()
+    }
+
+    /// Reconstruct an image from multi-coil k-space data (`[coil][sample]`, with the samples in
+    /// the order of `KSpaceThings::samples`)
+    pub fn solve(&self, data: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+        self.try_solve(data).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Reconstruct an image, failing if the data does not fit the encoding matrix or is not
+    /// finite
+    pub fn try_solve(&self, data: &[Vec<Complex<f64>>]) -> Result<Vec<Complex<f64>>> {
+        check_dims(self.e.num_coils(), data.len())?;
+        for d in data {
+            check_dims(self.e.num_samples(), d.len())?;
+        }
+        if !data.iter().flatten().all(|x| x.re.is_finite() && x.im.is_finite()) {
+            return Err(MriError::InvalidParameter(
+                "k-space data must be finite".to_string(),
+            ));
+        }
+        let b = self.e.apply_adjoint(data);
+        let norm_b = norm_sqr(&b).sqrt();
+        let mut x = vec![Complex::new(0.0, 0.0); b.len()];
+        if norm_b == 0.0 {
+            return Ok(x);
+        }
+        let mut r = b.clone();
+        let mut p = r.clone();
+        let mut rr = norm_sqr(&r);
+        for iter in 0..self.max_iter {
+            let q = self.normal(&p);
+            // `E^H E + lambda I` is positive semidefinite, a nonpositive curvature means that the
+            // search direction vanished or the encoding matrix is not finite
+            let pq = dot(&p, &q).re;
+            if !(pq > 0.0 && pq.is_finite()) {
+                break;
+            }
+            let alpha = rr / pq;
+            for ((xi, pi), (ri, qi)) in x.iter_mut().zip(p.iter()).zip(r.iter_mut().zip(q.iter())) {
+                *xi += pi * alpha;
+                *ri -= qi * alpha;
+            }
+            let rr_new = norm_sqr(&r);
+            let residual = rr_new.sqrt() / norm_b;
+            if let Some(ref callback) = self.callback {
+                callback(iter, &x, residual);
+            }
+            if residual < self.tol {
+                break;
+            }
+            let beta = rr_new / rr;
+            for (pi, ri) in p.iter_mut().zip(r.iter()) {
+                *pi = ri + *pi * beta;
+            }
+            rr = rr_new;
+        }
+        Ok(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rf::RFSensitivity;
+    use std::cell::RefCell;
+    use KSpace;
+
+    /// fully sampled Cartesian single coil problem and its image
+    fn cartesian() -> (CgSense<KSpace>, Vec<Complex<f64>>) {
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(4, 4);
+        let k = KSpace::cartesian(fov.clone(), dims.clone());
+        let psi = vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap())),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap())),
+        ];
+        let mut rf = RFSensitivityArray::new();
+        rf.push(RFSensitivity::new(vec![Complex::new(1.0, 0.0); dims.product()]));
+        let img = (0..dims.product())
+            .map(|i| Complex::new((i % 3) as f64, (i % 5) as f64 - 2.0))
+            .collect();
+        (CgSense::new(k, psi, rf, fov, dims), img)
+    }
+
+    #[test]
+    fn converged_iterations_stay_finite() {
+        let (mut sense, img) = cartesian();
+        // iterating beyond convergence makes the search direction vanish
+        sense.tolerance(0.0).max_iter(100);
+        let data = sense.encoding_matrix().apply(&img);
+        let x = sense.solve(&data);
+        for (a, b) in x.iter().zip(img.iter()) {
+            assert!((a - b).norm() < 1e-9, "{} vs {}", a, b);
+        }
+    }
+
+    /// Cartesian 8x8 problem with every second phase encoding line and four coils centered at
+    /// the corners of the field of view (or only the first of them), and its image
+    fn undersampled(num_coils: usize) -> (CgSense<KSpace>, Vec<Complex<f64>>) {
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(8, 8);
+        let mask: Vec<bool> = (0..dims.product()).map(|i| (i / 8) % 2 == 0).collect();
+        let k = KSpace::cartesian(fov.clone(), dims.clone())
+            .select(&mask)
+            .unwrap();
+        let psi = vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap())),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap())),
+        ];
+        let grid = fov.grid(&dims);
+        let mut rf = RFSensitivityArray::new();
+        for (c, &(cx, cy)) in [(-0.1, -0.1), (0.1, -0.1), (-0.1, 0.1), (0.1, 0.1)]
+            .iter()
+            .enumerate()
+            .take(num_coils)
+        {
+            let sens = grid
+                .iter()
+                .map(|p| {
+                    let d2 = (p.x().unwrap() - cx).powi(2) + (p.y().unwrap() - cy).powi(2);
+                    Complex::from_polar((-d2 / 0.02).exp(), 0.5 * c as f64)
+                }).collect();
+            rf.push(RFSensitivity::new(sens));
+        }
+        let img = (0..dims.product())
+            .map(|i| Complex::new(((i * 7) % 11) as f64, (i % 4) as f64 - 1.5))
+            .collect();
+        (CgSense::new(k, psi, rf, fov, dims), img)
+    }
+
+    /// relative error of an image
+    fn error(x: &[Complex<f64>], img: &[Complex<f64>]) -> f64 {
+        let diff: Vec<Complex<f64>> = x.iter().zip(img.iter()).map(|(a, b)| a - b).collect();
+        (norm_sqr(&diff) / norm_sqr(img)).sqrt()
+    }
+
+    #[test]
+    fn undersampled_multi_coil() {
+        let (mut sense, img) = undersampled(4);
+        sense.tolerance(1e-12).max_iter(200);
+        let data = sense.encoding_matrix().apply(&img);
+        assert!(error(&sense.solve(&data), &img) < 1e-6);
+
+        // a single coil cannot resolve the aliasing
+        let (mut single, img) = undersampled(1);
+        single.tolerance(1e-12).max_iter(200);
+        let data = single.encoding_matrix().apply(&img);
+        assert!(error(&single.solve(&data), &img) > 0.1);
+    }
+
+    #[test]
+    fn tikhonov() {
+        let (mut sense, img) = undersampled(4);
+        let data = sense.encoding_matrix().apply(&img);
+        let b = sense.encoding_matrix().apply_adjoint(&data);
+        sense.tolerance(1e-12).max_iter(200);
+        let mut previous = f64::INFINITY;
+        for &lambda in &[0.0, 0.1, 10.0] {
+            let x = sense.lambda(lambda).solve(&data);
+            // x solves the regularized normal equations
+            assert!(error(&sense.normal(&x), &b) < 1e-9);
+            // and shrinks with growing lambda
+            let norm = norm_sqr(&x);
+            assert!(norm < previous);
+            previous = norm;
+        }
+    }
+
+    #[test]
+    fn callback() {
+        let (mut sense, img) = undersampled(4);
+        let data = sense.encoding_matrix().apply(&img);
+        let calls = Rc::new(RefCell::new(vec![]));
+        let c = calls.clone();
+        sense
+            .tolerance(1e-3)
+            .callback(Rc::new(move |iter, x: &[Complex<f64>], residual| {
+                assert_eq!(x.len(), 64);
+                c.borrow_mut().push((iter, residual));
+            }));
+        sense.solve(&data);
+        let converged = calls.borrow().clone();
+        assert!(!converged.is_empty() && converged.len() < DEFAULT_MAX_ITER);
+        for (i, &(iter, residual)) in converged.iter().enumerate() {
+            assert_eq!(iter, i);
+            assert_eq!(residual < 1e-3, i + 1 == converged.len());
+        }
+
+        calls.borrow_mut().clear();
+        sense.tolerance(0.0).max_iter(3).solve(&data);
+        assert_eq!(calls.borrow().len(), 3);
+    }
+
+    #[test]
+    fn non_finite_data() {
+        let (sense, img) = cartesian();
+        let mut data = sense.encoding_matrix().apply(&img);
+        data[0][3] = Complex::new(f64::NAN, 0.0);
+        assert!(matches!(
+            sense.try_solve(&data),
+            Err(MriError::InvalidParameter(_))
+        ));
+        data[0][3] = Complex::new(0.0, f64::INFINITY);
+        assert!(sense.try_solve(&data).is_err());
+        data[0].pop();
+        assert!(matches!(
+            sense.try_solve(&data),
+            Err(MriError::DimensionMismatch { .. })
+        ));
+        assert!(sense.try_solve(&data[1..]).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Simulation of multi-coil acquisitions
+//!
+//! The noise free signal is computed with the `EncodingMatrix`. Complex Gaussian noise with a
+//! given covariance between the coils is added, independently for every sample.
+
+use encodingmatrix::EncodingMatrix;
+use error::{check_dims, MriError, Result};
+use num::Complex;
+use phantom::Phantom;
+use random::Rng;
+use rf::RFSensitivityArray;
+use EncodingField;
+use KSpaceThings;
+use SpatialDims;
+
+/// Default seed of the noise generator
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Simulator of multi-coil k-space data
+pub struct Simulator<T>
+where
+    T: KSpaceThings,
+{
+    /// encoding matrix
+    e: EncodingMatrix<T>,
+    /// lower triangular Cholesky factor of the noise covariance, if noise is added
+    noise: Option<Vec<Vec<Complex<f64>>>>,
+    /// seed of the noise generator
+    seed: u64,
+}
+
+impl<T: KSpaceThings> Simulator<T> {
+    /// Create a simulator for trajectory `k`, encoding fields `psi` and coil sensitivities `rf` on
+    /// a grid of `dims` voxels covering `fov`
+    pub fn new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Self {
+        Simulator::try_new(k, psi, rf, fov, dims).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a simulator, failing if the trajectory, fields, sensitivities and grid do not fit
+    /// together
+    pub fn try_new(
+        k: T,
+        psi: Vec<EncodingField>,
+        rf: RFSensitivityArray,
+        fov: SpatialDims<f64>,
+        dims: SpatialDims<usize>,
+    ) -> Result<Self> {
+        EncodingMatrix::try_new(k, psi, rf.array, fov, dims).map(Simulator::from_encoding_matrix)
+    }
+
+    /// Create a simulator for an existing encoding matrix
+    pub fn from_encoding_matrix(e: EncodingMatrix<T>) -> Self {
+        Simulator {
+            e,
+            noise: None,
+            seed: DEFAULT_SEED,
+        }
+    }
+
+    /// Set the noise covariance matrix between the coils (`[coil][coil]`), which has to be
+    /// Hermitian and positive definite. Without a covariance no noise is added.
+    pub fn noise_covariance(&mut self, cov: &[Vec<Complex<f64>>]) -> Result<&mut Self> {
+        check_dims(self.e.num_coils(), cov.len())?;
+        for row in cov {
+            check_dims(cov.len(), row.len())?;
+        }
+        self.noise = Some(cholesky(cov)?);
+        Ok(self)
+    }
+
+    /// Set the seed of the noise generator
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Return the encoding matrix
+    pub fn encoding_matrix(&self) -> &EncodingMatrix<T> {
+        &self.e
+    }
+
+    /// Simulate the k-space data of an image on the voxel grid.
+    ///
+    /// Returns the signal of every coil (`[coil][sample]`) in the order of
+    /// `KSpaceThings::samples`. The same seed always results in the same noise.
+    pub fn simulate(&self, img: &[Complex<f64>]) -> Result<Vec<Vec<Complex<f64>>>> {
+        check_dims(self.e.num_voxels(), img.len())?;
+        let mut data = self.e.apply(img);
+        if let Some(ref l) = self.noise {
+            let mut rng = Rng::new(self.seed);
+            let scale = 0.5f64.sqrt();
+            let mut z = vec![Complex::new(0.0, 0.0); l.len()];
+            for m in 0..self.e.num_samples() {
+                for zi in &mut z {
+                    *zi = Complex::new(rng.normal(), rng.normal()) * scale;
+                }
+                for (d, row) in data.iter_mut().zip(l.iter()) {
+                    d[m] += row.iter().zip(z.iter()).map(|(a, b)| a * b).sum::<Complex<f64>>();
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Simulate the k-space data of a phantom, rasterized on the voxel grid
+    pub fn simulate_phantom(&self, phantom: &Phantom) -> Result<Vec<Vec<Complex<f64>>>> {
+        let img: Vec<Complex<f64>> = phantom
+            .rasterize(&self.e.fov(), &self.e.dimensions())?
+            .iter()
+            .map(|&x| Complex::new(x, 0.0))
+            .collect();
+        self.simulate(&img)
+    }
+}
+
+/// Cholesky decomposition `A = L L^H` of a Hermitian positive definite matrix
+fn cholesky(a: &[Vec<Complex<f64>>]) -> Result<Vec<Vec<Complex<f64>>>> {
+    let n = a.len();
+    let scale = (0..n).map(|i| a[i][i].norm()).fold(0.0, f64::max);
+    let tol = 1e-12 * scale.max(f64::MIN_POSITIVE);
+    let hermitian = (0..n).all(|i| (0..=i).all(|j| (a[i][j] - a[j][i].conj()).norm() <= tol));
+    if !hermitian {
+        return Err(MriError::InvalidParameter(
+            "noise covariance is not Hermitian".to_string(),
+        ));
+    }
+    let mut l = vec![vec![Complex::new(0.0, 0.0); n]; n];
+    for j in 0..n {
+        let d = a[j][j].re - l[j][..j].iter().map(|x| x.norm_sqr()).sum::<f64>();
+        if d <= tol {
+            return Err(MriError::InvalidParameter(
+                "noise covariance is not positive definite".to_string(),
+            ));
+        }
+        let d = d.sqrt();
+        l[j][j] = Complex::new(d, 0.0);
+        for i in (j + 1)..n {
+            let s: Complex<f64> = (0..j).map(|k| l[i][k] * l[j][k].conj()).sum();
+            l[i][j] = (a[i][j] - s) / d;
+        }
+    }
+    Ok(l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rf::RFSensitivity;
+    use std::rc::Rc;
+    use KSpace;
+
+    /// two coil simulator on a fully sampled Cartesian grid
+    fn simulator() -> Simulator<KSpace> {
+        let fov = SpatialDims::TwoD(0.2, 0.2);
+        let dims = SpatialDims::TwoD(8, 8);
+        let k = KSpace::cartesian(fov.clone(), dims.clone());
+        let psi = vec![
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.x().unwrap())),
+            EncodingField::new(Rc::new(|pos: &SpatialDims<f64>| pos.y().unwrap())),
+        ];
+        let mut rf = RFSensitivityArray::new();
+        rf.push(RFSensitivity::new(vec![Complex::new(1.0, 0.0); dims.product()]));
+        rf.push(RFSensitivity::new(vec![Complex::new(0.0, 0.5); dims.product()]));
+        Simulator::new(k, psi, rf, fov, dims)
+    }
+
+    #[test]
+    fn noise_free() {
+        let sim = simulator();
+        let img: Vec<Complex<f64>> = (0..64).map(|i| Complex::new(i as f64, 1.0)).collect();
+        assert_eq!(sim.simulate(&img).unwrap(), sim.encoding_matrix().apply(&img));
+        assert!(sim.simulate(&img[1..]).is_err());
+    }
+
+    #[test]
+    fn noise_covariance() {
+        let cov = vec![
+            vec![Complex::new(2.0, 0.0), Complex::new(0.5, 0.5)],
+            vec![Complex::new(0.5, -0.5), Complex::new(1.0, 0.0)],
+        ];
+        let mut sim = simulator();
+        sim.noise_covariance(&cov).unwrap().seed(3);
+        let img = vec![Complex::new(0.0, 0.0); 64];
+        let noise = sim.simulate(&img).unwrap();
+        assert_eq!(noise, sim.simulate(&img).unwrap());
+        assert_ne!(noise, sim.seed(4).simulate(&img).unwrap());
+
+        // sample covariance of many realisations
+        let mut estimate = vec![vec![Complex::new(0.0, 0.0); 2]; 2];
+        let mut count = 0.0;
+        for seed in 0..100 {
+            let noise = sim.seed(seed).simulate(&img).unwrap();
+            for (a, b) in noise[0].iter().zip(noise[1].iter()) {
+                let n = [a, b];
+                for i in 0..2 {
+                    for j in 0..2 {
+                        estimate[i][j] += n[i] * n[j].conj();
+                    }
+                }
+                count += 1.0;
+            }
+        }
+        for i in 0..2 {
+            for j in 0..2 {
+                let e = estimate[i][j] / count;
+                assert!((e - cov[i][j]).norm() < 0.05, "{} vs {}", e, cov[i][j]);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_covariance() {
+        let mut sim = simulator();
+        let one = Complex::new(1.0, 0.0);
+        let zero = Complex::new(0.0, 0.0);
+        assert!(sim.noise_covariance(&[vec![one]]).is_err());
+        assert!(sim
+            .noise_covariance(&[vec![one, one], vec![zero, one]])
+            .is_err());
+        assert!(sim
+            .noise_covariance(&[vec![one, one * 2.0], vec![one * 2.0, one]])
+            .is_err());
+    }
+}
@@ -7,10 +7,11 @@
 
 //! Spatial Dimensions
 
+use error::{MriError, Result};
 use std;
 
 /// spatial dimensions
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SpatialDims<T> {
     /// One dimension
     OneD(T),
@@ -31,6 +32,46 @@ impl SpatialDims<f64> {
     }
 }
 
+impl SpatialDims<f64> {
+    /// positions `fov * (i / n - 1/2)` of a grid with `dims` voxels covering this field of view.
+    ///
+    /// These are the lower corners of the voxels, such that the point `i = n / 2` lies at the
+    /// origin like the center of a DFT. x varies fastest.
+    pub fn grid(&self, dims: &SpatialDims<usize>) -> Vec<SpatialDims<f64>> {
+        let pos = |fov: f64, n: usize, i: usize| fov * (-0.5 + (i as f64) / (n as f64));
+        match (self, dims) {
+            (&SpatialDims::OneD(fx), &SpatialDims::OneD(nx)) => {
+                (0..nx).map(|x| SpatialDims::OneD(pos(fx, nx, x))).collect()
+            }
+            (&SpatialDims::TwoD(fx, fy), &SpatialDims::TwoD(nx, ny)) => {
+                let mut out = Vec::with_capacity(nx * ny);
+                for y in 0..ny {
+                    for x in 0..nx {
+                        out.push(SpatialDims::TwoD(pos(fx, nx, x), pos(fy, ny, y)));
+                    }
+                }
+                out
+            }
+            (&SpatialDims::ThreeD(fx, fy, fz), &SpatialDims::ThreeD(nx, ny, nz)) => {
+                let mut out = Vec::with_capacity(nx * ny * nz);
+                for z in 0..nz {
+                    for y in 0..ny {
+                        for x in 0..nx {
+                            out.push(SpatialDims::ThreeD(
+                                pos(fx, nx, x),
+                                pos(fy, ny, y),
+                                pos(fz, nz, z),
+                            ));
+                        }
+                    }
+                }
+                out
+            }
+            _ => panic!("FOV and grid dimensions do not match"),
+        }
+    }
+}
+
 impl<T> SpatialDims<T>
 where
     T: std::ops::Mul<Output = T> + std::clone::Clone,
@@ -55,12 +96,35 @@ impl<T> SpatialDims<T> {
             SpatialDims::ThreeD(_, _, _) => 3,
         }
     }
+
+    /// there is always at least one dimension
+    pub fn is_empty(&self) -> bool {
+        false
+    }
 }
 
 impl<T> SpatialDims<T>
 where
     T: std::clone::Clone,
 {
+    /// create from a slice with one to three elements
+    pub fn from_slice(v: &[T]) -> Self {
+        SpatialDims::try_from_slice(v).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create from a slice, failing unless it has one to three elements
+    pub fn try_from_slice(v: &[T]) -> Result<Self> {
+        match v.len() {
+            1 => Ok(SpatialDims::OneD(v[0].clone())),
+            2 => Ok(SpatialDims::TwoD(v[0].clone(), v[1].clone())),
+            3 => Ok(SpatialDims::ThreeD(v[0].clone(), v[1].clone(), v[2].clone())),
+            n => Err(MriError::InvalidParameter(format!(
+                "SpatialDims needs one to three elements, found {}",
+                n
+            ))),
+        }
+    }
+
     /// return x
     pub fn x(&self) -> Option<T> {
         match *self {
@@ -0,0 +1,232 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Real regular solid harmonics
+//!
+//! The harmonics `R_lm = sqrt(4 pi / (2l + 1)) r^l Y_lm` are Schmidt semi-normalized (Racah
+//! normalized) real spherical harmonics times `r^l`, such that `R_10 = z`, `R_11 = x`,
+//! `R_1,-1 = y`, `R_20 = (3z^2 - r^2) / 2`, `R_22 = sqrt(3) (x^2 - y^2) / 2` and
+//! `R_2,-2 = sqrt(3) xy`. They are computed with the recurrences of Helgaker, Joergensen and
+//! Olsen, "Molecular Electronic-Structure Theory", Wiley, 2000, section 6.4.
+//!
+//! Harmonics up to order `L` are ordered by `l` and then by `m = -l, ..., l`, see `index`.
+
+use error::{MriError, Result};
+use num::Integer;
+use std::f64::consts::SQRT_2;
+
+/// Position of `R_lm` in a list of harmonics
+pub fn index(l: usize, m: i64) -> Result<usize> {
+    if m.unsigned_abs() as usize > l {
+        return Err(MriError::InvalidParameter(format!(
+            "order {} of a solid harmonic of degree {}",
+            m, l
+        )));
+    }
+    Ok(((l * l + l) as i64 + m) as usize)
+}
+
+/// Number of harmonics up to order `max_order`
+pub fn num_harmonics(max_order: usize) -> usize {
+    (max_order + 1) * (max_order + 1)
+}
+
+/// Value and gradient `(f, df/dx, df/dy, df/dz)`
+type Dual = [f64; 4];
+
+/// `a * b` with the product rule
+fn mul(a: &Dual, b: &Dual) -> Dual {
+    [
+        a[0] * b[0],
+        a[1] * b[0] + a[0] * b[1],
+        a[2] * b[0] + a[0] * b[2],
+        a[3] * b[0] + a[0] * b[3],
+    ]
+}
+
+/// `s * a + t * b`
+fn lin(s: f64, a: &Dual, t: f64, b: &Dual) -> Dual {
+    [
+        s * a[0] + t * b[0],
+        s * a[1] + t * b[1],
+        s * a[2] + t * b[2],
+        s * a[3] + t * b[3],
+    ]
+}
+
+/// All harmonics up to `max_order` and their gradients at `r`
+pub(crate) fn evaluate(max_order: usize, r: [f64; 3]) -> Vec<Dual> {
+    let x: Dual = [r[0], 1.0, 0.0, 0.0];
+    let y: Dual = [r[1], 0.0, 1.0, 0.0];
+    let z: Dual = [r[2], 0.0, 0.0, 1.0];
+    let r2: Dual = [
+        r[0] * r[0] + r[1] * r[1] + r[2] * r[2],
+        2.0 * r[0],
+        2.0 * r[1],
+        2.0 * r[2],
+    ];
+    let zero: Dual = [0.0; 4];
+
+    // complex-like harmonics C_lm (real part) and S_lm (imaginary part) for m >= 0
+    let n = max_order + 1;
+    let mut c = vec![vec![zero; n]; n];
+    let mut s = vec![vec![zero; n]; n];
+    c[0][0] = [1.0, 0.0, 0.0, 0.0];
+    for l in 0..max_order {
+        let lf = l as f64;
+        // diagonal
+        let f = -((2.0 * lf + 1.0) / (2.0 * lf + 2.0)).sqrt();
+        c[l + 1][l + 1] = lin(f, &mul(&x, &c[l][l]), -f, &mul(&y, &s[l][l]));
+        s[l + 1][l + 1] = lin(f, &mul(&y, &c[l][l]), f, &mul(&x, &s[l][l]));
+        // vertical
+        for m in 0..=l {
+            let mf = m as f64;
+            let a = (2.0 * lf + 1.0) / ((lf + mf + 1.0) * (lf - mf + 1.0)).sqrt();
+            let b = -((lf + mf) * (lf - mf) / ((lf + mf + 1.0) * (lf - mf + 1.0))).sqrt();
+            let (c_prev, s_prev) = if m < l {
+                (mul(&r2, &c[l - 1][m]), mul(&r2, &s[l - 1][m]))
+            } else {
+                (zero, zero)
+            };
+            c[l + 1][m] = lin(a, &mul(&z, &c[l][m]), b, &c_prev);
+            s[l + 1][m] = lin(a, &mul(&z, &s[l][m]), b, &s_prev);
+        }
+    }
+
+    // real harmonics, R_lm = (-1)^m sqrt(2) C_lm and R_l,-m = (-1)^m sqrt(2) S_lm
+    let sign = |m: usize| if m.is_even() { SQRT_2 } else { -SQRT_2 };
+    let mut out = Vec::with_capacity(num_harmonics(max_order));
+    for l in 0..n {
+        out.extend((1..=l).rev().map(|m| lin(sign(m), &s[l][m], 0.0, &zero)));
+        out.push(c[l][0]);
+        out.extend((1..=l).map(|m| lin(sign(m), &c[l][m], 0.0, &zero)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Gauss-Legendre nodes and weights on [-1, 1]
+    fn gauss_legendre(n: usize) -> Vec<(f64, f64)> {
+        (0..n)
+            .map(|i| {
+                let mut x = (PI * (i as f64 + 0.75) / (n as f64 + 0.5)).cos();
+                let mut dp = 0.0;
+                for _ in 0..100 {
+                    // Legendre polynomial P_n and its derivative by the three term recurrence
+                    let (mut p0, mut p1) = (1.0, x);
+                    for k in 2..=n {
+                        let kf = k as f64;
+                        let p2 = ((2.0 * kf - 1.0) * x * p1 - (kf - 1.0) * p0) / kf;
+                        p0 = p1;
+                        p1 = p2;
+                    }
+                    dp = (n as f64) * (x * p1 - p0) / (x * x - 1.0);
+                    let dx = p1 / dp;
+                    x -= dx;
+                    if dx.abs() < 1e-15 {
+                        break;
+                    }
+                }
+                (x, 2.0 / ((1.0 - x * x) * dp * dp))
+            }).collect()
+    }
+
+    #[test]
+    fn orthogonality() {
+        // integral of R_lm R_l'm' over the unit sphere is 4 pi / (2l + 1) for equal indices
+        let max_order = 6;
+        let n = num_harmonics(max_order);
+        let num_phi = 32;
+        let mut gram = vec![vec![0.0; n]; n];
+        for (ct, w) in gauss_legendre(16) {
+            let st = (1.0 - ct * ct).sqrt();
+            for j in 0..num_phi {
+                let phi = 2.0 * PI * (j as f64) / (num_phi as f64);
+                let r = [st * phi.cos(), st * phi.sin(), ct];
+                let h = evaluate(max_order, r);
+                let weight = w * 2.0 * PI / (num_phi as f64);
+                for a in 0..n {
+                    for b in 0..n {
+                        gram[a][b] += weight * h[a][0] * h[b][0];
+                    }
+                }
+            }
+        }
+        for l in 0..=max_order {
+            for m in -(l as i64)..=(l as i64) {
+                let a = index(l, m).unwrap();
+                for (b, g) in gram[a].iter().enumerate() {
+                    let expected = if a == b {
+                        4.0 * PI / (2.0 * (l as f64) + 1.0)
+                    } else {
+                        0.0
+                    };
+                    assert!((g - expected).abs() < 1e-12, "({}, {}), {}: {}", l, m, b, g);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn low_orders() {
+        let [x, y, z] = [0.3, -0.7, 0.4];
+        let r2 = x * x + y * y + z * z;
+        let h = evaluate(2, [x, y, z]);
+        let s3 = 3f64.sqrt();
+        let expected = [
+            (0, 0, 1.0),
+            (1, -1, y),
+            (1, 0, z),
+            (1, 1, x),
+            (2, -2, s3 * x * y),
+            (2, -1, s3 * y * z),
+            (2, 0, (3.0 * z * z - r2) / 2.0),
+            (2, 1, s3 * x * z),
+            (2, 2, s3 * (x * x - y * y) / 2.0),
+        ];
+        for &(l, m, v) in &expected {
+            assert!((h[index(l, m).unwrap()][0] - v).abs() < 1e-14, "({}, {})", l, m);
+        }
+    }
+
+    #[test]
+    fn gradients_and_laplacian() {
+        // the gradients match finite differences and the harmonics solve Laplace's equation
+        let max_order = 5;
+        let r = [0.2, 0.5, -0.3];
+        let eps = 1e-5;
+        let h = evaluate(max_order, r);
+        let shifted = |d: usize, s: f64| {
+            let mut p = r;
+            p[d] += s;
+            evaluate(max_order, p)
+        };
+        let mut laplacian = vec![0.0; h.len()];
+        for d in 0..3 {
+            let (plus, minus) = (shifted(d, eps), shifted(d, -eps));
+            for i in 0..h.len() {
+                let fd = (plus[i][0] - minus[i][0]) / (2.0 * eps);
+                assert!((fd - h[i][d + 1]).abs() < 1e-8, "{} {}", i, d);
+                laplacian[i] += (plus[i][d + 1] - minus[i][d + 1]) / (2.0 * eps);
+            }
+        }
+        for l in laplacian {
+            assert!(l.abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn invalid_order() {
+        assert!(index(2, 3).is_err());
+        assert!(index(2, -3).is_err());
+        assert_eq!(index(3, -3).unwrap(), 9);
+    }
+}
@@ -0,0 +1,525 @@
+// Copyright 2018 Stefan Kroboth
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Undersampling of Cartesian trajectories
+//!
+//! Masks are defined on the Cartesian grid in the order of `KSpace::cartesian` (x fastest). The
+//! center of k-space is at index `n / 2` along every dimension. The calibration region around the
+//! center is always sampled and counts towards the acceleration.
+
+use error::{check_dims, MriError, Result};
+use num::Integer;
+use random::Rng;
+use std::iter::repeat_n;
+use KSpace;
+use KSpaceThings;
+use SampleTiming;
+use SpatialDims;
+
+/// Number of bisection steps to find the Poisson-disc radius for the target acceleration
+const RADIUS_BISECTION_STEPS: usize = 32;
+/// Relative number of samples above the target at which the bisection of the Poisson-disc radius
+/// stops
+const POISSON_DISC_TOLERANCE: f64 = 0.01;
+
+/// Random sampling pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplingPattern {
+    /// Poisson-disc sampling: no two samples are closer than a radius (in grid points), which
+    /// grows linearly from the center to `1 + density` times its central value at the edge of
+    /// k-space
+    PoissonDisc {
+        /// relative growth of the radius towards the edge of k-space, 0 for uniform density
+        density: f64,
+    },
+    /// exactly as many random samples as required for the acceleration, drawn without
+    /// replacement with weights `(1 - r)^power`, where `r` is the normalized distance to the
+    /// center of k-space (Efraimidis-Spirakis weighted sampling)
+    VariableDensity {
+        /// decay of the sampling density towards the edge of k-space
+        power: f64,
+    },
+}
+
+/// Random undersampling of Cartesian trajectories
+#[derive(Debug, Clone)]
+pub struct Undersampling {
+    /// sampling pattern
+    pattern: SamplingPattern,
+    /// target acceleration
+    acceleration: f64,
+    /// size of the fully sampled calibration region
+    calibration: Option<SpatialDims<usize>>,
+    /// fully sample along x
+    readout: bool,
+    /// seed of the random numbers
+    seed: u64,
+}
+
+impl Undersampling {
+    /// Undersampling with the given pattern, without acceleration or calibration region
+    pub fn new(pattern: SamplingPattern) -> Self {
+        Undersampling {
+            pattern,
+            acceleration: 1.0,
+            calibration: None,
+            readout: false,
+            seed: 0,
+        }
+    }
+
+    /// Target acceleration, the ratio of the number of grid points to the number of samples
+    pub fn acceleration(&mut self, acceleration: f64) -> &mut Self {
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Size of the fully sampled calibration region around the center of k-space
+    pub fn calibration(&mut self, calibration: SpatialDims<usize>) -> &mut Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Fully sample along x (the readout) and undersample only the phase encoding dimensions
+    pub fn fully_sampled_readout(&mut self, readout: bool) -> &mut Self {
+        self.readout = readout;
+        self
+    }
+
+    /// Seed of the random numbers
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sampling mask on a grid with `dims` points
+    pub fn mask(&self, dims: &SpatialDims<usize>) -> Result<Vec<bool>> {
+        let n: Vec<usize> = dims.clone().into_iter().collect();
+        if n.contains(&0) {
+            return Err(MriError::InvalidParameter(
+                "grid dimensions must be positive".to_string(),
+            ));
+        }
+        if !(self.acceleration >= 1.0 && self.acceleration.is_finite()) {
+            return Err(MriError::InvalidParameter(format!(
+                "acceleration {}",
+                self.acceleration
+            )));
+        }
+        let calibration: Vec<usize> = match self.calibration {
+            Some(ref c) => {
+                let c: Vec<usize> = c.clone().into_iter().collect();
+                check_dims(n.len(), c.len())?;
+                c
+            }
+            None => vec![0; n.len()],
+        };
+
+        // undersampled dimensions
+        let skip = if self.readout { 1 } else { 0 };
+        if skip == n.len() {
+            return Err(MriError::InvalidParameter(
+                "no dimension left to undersample".to_string(),
+            ));
+        }
+        let grid = Grid::new(&n[skip..], &calibration[skip..]);
+        let target = ((grid.len() as f64) / self.acceleration).ceil() as usize;
+
+        let mut rng = Rng::new(self.seed);
+        let sub = match self.pattern {
+            SamplingPattern::PoissonDisc { density } => {
+                if !(density >= 0.0 && density.is_finite()) {
+                    return Err(MriError::InvalidParameter(format!("density {}", density)));
+                }
+                grid.poisson_disc(density, target, &mut rng)
+            }
+            SamplingPattern::VariableDensity { power } => {
+                if !(power >= 0.0 && power.is_finite()) {
+                    return Err(MriError::InvalidParameter(format!("power {}", power)));
+                }
+                grid.variable_density(power, target, &mut rng)
+            }
+        };
+
+        // expand along the readout
+        let nx = if self.readout { n[0] } else { 1 };
+        Ok(sub
+            .iter()
+            .flat_map(|&m| repeat_n(m, nx))
+            .collect())
+    }
+}
+
+impl KSpace {
+    /// Return the samples where `mask` is true, including their acquisition times
+    pub fn select(&self, mask: &[bool]) -> Result<KSpace> {
+        check_dims(self.kspace().len(), mask.len())?;
+        let mut out = KSpace::new();
+        let times = self.sample_times();
+        for (i, s) in self.kspace().iter().enumerate().filter(|&(i, _)| mask[i]) {
+            match times {
+                Some(ref t) => {
+                    out.try_add_timed(s.clone(), SampleTiming::PerSample(vec![t[i]]))?
+                }
+                None => out.try_add(s.clone())?,
+            };
+        }
+        Ok(out)
+    }
+
+    /// Undersample a Cartesian trajectory with `dims` points, as created by `KSpace::cartesian`.
+    /// Returns the subset trajectory and the sampling mask.
+    pub fn undersample(
+        &self,
+        dims: &SpatialDims<usize>,
+        undersampling: &Undersampling,
+    ) -> Result<(KSpace, Vec<bool>)> {
+        check_dims(dims.product(), self.kspace().len())?;
+        let mask = undersampling.mask(dims)?;
+        Ok((self.select(&mask)?, mask))
+    }
+}
+
+/// Grid of the undersampled dimensions
+struct Grid {
+    /// number of points along every dimension
+    n: Vec<usize>,
+    /// points of the calibration region
+    calibration: Vec<bool>,
+    /// normalized distance of every point to the center, in `[0, 1]`
+    radius: Vec<f64>,
+}
+
+impl Grid {
+    fn new(n: &[usize], calibration: &[usize]) -> Self {
+        let len = n.iter().product();
+        let center: Vec<usize> = n
+            .iter()
+            .map(|&ni| if ni.is_even() { ni / 2 } else { (ni - 1) / 2 })
+            .collect();
+        let mut grid = Grid {
+            n: n.to_vec(),
+            calibration: Vec::with_capacity(len),
+            radius: Vec::with_capacity(len),
+        };
+        for i in 0..len {
+            let idx = grid.index(i);
+            let mut r2 = 0.0;
+            let mut calib = true;
+            for d in 0..n.len() {
+                let offset = idx[d] as f64 - center[d] as f64;
+                let half = (n[d] as f64 / 2.0).max(1.0);
+                r2 += (offset / half) * (offset / half);
+                let start = center[d] as i64 - (calibration[d] / 2) as i64;
+                let j = idx[d] as i64;
+                calib &= j >= start && j < start + calibration[d] as i64;
+            }
+            grid.radius
+                .push((r2 / (n.len() as f64)).sqrt().min(1.0));
+            grid.calibration.push(calib);
+        }
+        grid
+    }
+
+    fn len(&self) -> usize {
+        self.radius.len()
+    }
+
+    /// Grid index of point `i` (first dimension fastest)
+    fn index(&self, mut i: usize) -> Vec<usize> {
+        self.n
+            .iter()
+            .map(|&ni| {
+                let (q, r) = i.div_rem(&ni);
+                i = q;
+                r
+            }).collect()
+    }
+
+    /// Variable density random mask with exactly `target` samples (or the calibration region,
+    /// if it is larger), using weighted sampling without replacement (Efraimidis and Spirakis,
+    /// "Weighted random sampling with a reservoir", Inf. Process. Lett. 97(5), 2006)
+    fn variable_density(&self, power: f64, target: usize, rng: &mut Rng) -> Vec<bool> {
+        let mut mask = self.calibration.clone();
+        let num_calib = mask.iter().filter(|&&m| m).count();
+        let mut keys: Vec<(f64, usize)> = (0..self.len())
+            .filter(|&i| !mask[i])
+            .map(|i| {
+                let w = (1.0 - self.radius[i]).powf(power).max(1e-12);
+                ((1.0 - rng.uniform()).ln() / w, i)
+            }).collect();
+        keys.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        for &(_, i) in keys.iter().take(target.saturating_sub(num_calib)) {
+            mask[i] = true;
+        }
+        mask
+    }
+
+    /// Poisson-disc mask with at least `target` samples, and at most `POISSON_DISC_TOLERANCE`
+    /// more if the bisection of the central radius converges. Every trial uses the same random
+    /// order of the points.
+    fn poisson_disc(&self, density: f64, target: usize, rng: &mut Rng) -> Vec<bool> {
+        // random order of the points (Fisher-Yates)
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        for i in (1..order.len()).rev() {
+            let j = rng.below(i as u64 + 1) as usize;
+            order.swap(i, j);
+        }
+        let count = |mask: &[bool]| mask.iter().filter(|&&m| m).count();
+        let tolerance = ((target as f64) * POISSON_DISC_TOLERANCE) as usize;
+
+        // Samples are at least the central radius apart, such that disjoint balls of half that
+        // radius around them fit into the grid. This bounds the radius for `target` samples.
+        let dims = self.n.len() as f64;
+        let r_max = self.n.iter().cloned().max().unwrap_or(1) as f64;
+        let bound = 2.0 * (self.len() as f64 / target.max(1) as f64).powf(1.0 / dims) + 1.0;
+        let (mut lo, mut hi) = (0.0, bound.min(r_max));
+        let mut best = vec![true; self.len()];
+        let mask = self.dart_throwing(hi, density, &order);
+        if count(&mask) >= target {
+            // only possible on small grids, where the boundary dominates
+            lo = hi;
+            hi = r_max;
+            best = mask;
+        }
+        for _ in 0..RADIUS_BISECTION_STEPS {
+            let mid = 0.5 * (lo + hi);
+            let mask = self.dart_throwing(mid, density, &order);
+            let c = count(&mask);
+            if c >= target {
+                lo = mid;
+                best = mask;
+                if c <= target + tolerance {
+                    break;
+                }
+            } else {
+                hi = mid;
+            }
+        }
+        best
+    }
+
+    /// Grid index of point `i`, padded with zeros to three dimensions
+    fn coords(&self, i: usize) -> [i64; 3] {
+        let mut out = [0; 3];
+        for (o, x) in out.iter_mut().zip(self.index(i)) {
+            *o = x as i64;
+        }
+        out
+    }
+
+    /// Accept points in the given order if no accepted point is closer than the radius at the
+    /// candidate. Accepted points are stored in a background grid with cells of at least the
+    /// largest radius, such that only the neighbouring cells need to be checked.
+    fn dart_throwing(&self, r0: f64, density: f64, order: &[usize]) -> Vec<bool> {
+        let mut mask = self.calibration.clone();
+        let size = (r0 * (1.0 + density)).ceil().max(1.0) as i64;
+        let mut cells = [1i64; 3];
+        for (c, &ni) in cells.iter_mut().zip(self.n.iter()) {
+            *c = (ni as i64 + size - 1) / size;
+        }
+        let cell = |p: &[i64; 3]| -> [i64; 3] { [p[0] / size, p[1] / size, p[2] / size] };
+        let flat = |c: &[i64; 3]| -> Option<usize> {
+            if (0..3).all(|d| c[d] >= 0 && c[d] < cells[d]) {
+                Some((c[0] + cells[0] * (c[1] + cells[1] * c[2])) as usize)
+            } else {
+                None
+            }
+        };
+        let mut accepted: Vec<Vec<[i64; 3]>> =
+            vec![vec![]; (cells[0] * cells[1] * cells[2]) as usize];
+        for i in (0..self.len()).filter(|&i| mask[i]) {
+            let p = self.coords(i);
+            accepted[flat(&cell(&p)).unwrap()].push(p);
+        }
+
+        let reach = |d: usize| if d < self.n.len() { 1 } else { 0 };
+        for &i in order {
+            if mask[i] {
+                continue;
+            }
+            let r = r0 * (1.0 + density * self.radius[i]);
+            let r2 = r * r;
+            let p = self.coords(i);
+            let c = cell(&p);
+            let mut free = true;
+            'cells: for dz in -reach(2)..=reach(2) {
+                for dy in -reach(1)..=reach(1) {
+                    for dx in -1..=1 {
+                        let neighbour = match flat(&[c[0] + dx, c[1] + dy, c[2] + dz]) {
+                            Some(j) => &accepted[j],
+                            None => continue,
+                        };
+                        let close = neighbour.iter().any(|q| {
+                            let d2: i64 = (0..3).map(|d| (p[d] - q[d]) * (p[d] - q[d])).sum();
+                            (d2 as f64) < r2
+                        });
+                        if close {
+                            free = false;
+                            break 'cells;
+                        }
+                    }
+                }
+            }
+            if free {
+                mask[i] = true;
+                accepted[flat(&c).unwrap()].push(p);
+            }
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KSample;
+
+    fn count(mask: &[bool]) -> usize {
+        mask.iter().filter(|&&m| m).count()
+    }
+
+    #[test]
+    fn poisson_disc_acceleration() {
+        let dims = SpatialDims::TwoD(96, 64);
+        let len = dims.product();
+        let achieved = |density: f64, acceleration: f64| {
+            let mask = Undersampling::new(SamplingPattern::PoissonDisc { density })
+                .acceleration(acceleration)
+                .calibration(SpatialDims::TwoD(8, 6))
+                .seed(1)
+                .mask(&dims)
+                .unwrap();
+            (len as f64) / (count(&mask) as f64)
+        };
+        for &density in &[0.5, 1.0, 2.0] {
+            for &acceleration in &[3.0, 4.0, 6.0, 8.0, 12.0] {
+                let a = achieved(density, acceleration);
+                assert!(a <= acceleration);
+                assert!(a >= acceleration / (1.0 + 2.0 * POISSON_DISC_TOLERANCE));
+            }
+        }
+        // a uniform radius can only realize a few accelerations on the grid
+        for &acceleration in &[3.0, 8.0] {
+            assert!(achieved(0.0, acceleration) <= acceleration);
+        }
+
+        // large 3D grids
+        let dims = SpatialDims::ThreeD(128, 128, 96);
+        let mask = Undersampling::new(SamplingPattern::PoissonDisc { density: 1.0 })
+            .acceleration(6.0)
+            .fully_sampled_readout(true)
+            .mask(&dims)
+            .unwrap();
+        let a = (dims.product() as f64) / (count(&mask) as f64);
+        assert!(a <= 6.0);
+        assert!(a >= 6.0 / (1.0 + 2.0 * POISSON_DISC_TOLERANCE));
+    }
+
+    #[test]
+    fn calibration_is_sampled() {
+        let dims = SpatialDims::ThreeD(16, 20, 12);
+        for &pattern in &[
+            SamplingPattern::PoissonDisc { density: 0.5 },
+            SamplingPattern::VariableDensity { power: 2.0 },
+        ] {
+            let mask = Undersampling::new(pattern)
+                .acceleration(6.0)
+                .calibration(SpatialDims::ThreeD(4, 5, 3))
+                .mask(&dims)
+                .unwrap();
+            let grid = Grid::new(&[16, 20, 12], &[4, 5, 3]);
+            assert_eq!(count(&grid.calibration), 4 * 5 * 3);
+            for (&m, &c) in mask.iter().zip(grid.calibration.iter()) {
+                assert!(m || !c);
+            }
+            // the calibration region is centered
+            assert!(grid.calibration[8 + 16 * (10 + 20 * 6)]);
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        let dims = SpatialDims::TwoD(48, 40);
+        for &pattern in &[
+            SamplingPattern::PoissonDisc { density: 1.0 },
+            SamplingPattern::VariableDensity { power: 1.0 },
+        ] {
+            let mut undersampling = Undersampling::new(pattern);
+            undersampling.acceleration(3.0);
+            let a = undersampling.seed(7).mask(&dims).unwrap();
+            let b = undersampling.seed(7).mask(&dims).unwrap();
+            let c = undersampling.seed(8).mask(&dims).unwrap();
+            assert_eq!(a, b);
+            assert!(a != c);
+        }
+    }
+
+    #[test]
+    fn poisson_disc_distance() {
+        let grid = Grid::new(&[40, 30], &[0, 0]);
+        let order: Vec<usize> = (0..grid.len()).rev().collect();
+        let (r0, density) = (2.5, 1.0);
+        let mask = grid.dart_throwing(r0, density, &order);
+        let points: Vec<usize> = (0..grid.len()).filter(|&i| mask[i]).collect();
+        assert!(points.len() > 20);
+        for (a, &i) in points.iter().enumerate() {
+            for &j in &points[a + 1..] {
+                let (p, q) = (grid.coords(i), grid.coords(j));
+                let d2: i64 = (0..3).map(|d| (p[d] - q[d]) * (p[d] - q[d])).sum();
+                // the later of the two points was checked against its own radius
+                let r = r0 * (1.0 + density * grid.radius[i.min(j)]);
+                assert!((d2 as f64) >= r * r);
+            }
+        }
+        // the radius grows towards the edge
+        let center = grid.index(grid.len() / 2 + 20);
+        assert_eq!(center, vec![20, 15]);
+        assert_eq!(grid.radius[grid.len() / 2 + 20], 0.0);
+    }
+
+    #[test]
+    fn variable_density_count() {
+        let dims = SpatialDims::TwoD(50, 30);
+        let mask = Undersampling::new(SamplingPattern::VariableDensity { power: 3.0 })
+            .acceleration(4.0)
+            .calibration(SpatialDims::TwoD(6, 6))
+            .mask(&dims)
+            .unwrap();
+        assert_eq!(count(&mask), 375);
+
+        // the readout is fully sampled
+        let mask = Undersampling::new(SamplingPattern::VariableDensity { power: 3.0 })
+            .acceleration(4.0)
+            .fully_sampled_readout(true)
+            .mask(&dims)
+            .unwrap();
+        assert_eq!(count(&mask), 50 * 8);
+        for line in mask.chunks(50) {
+            assert!(line.iter().all(|&m| m == line[0]));
+        }
+    }
+
+    #[test]
+    fn select_keeps_times() {
+        let mut k = KSpace::cartesian(SpatialDims::TwoD(0.2, 0.2), SpatialDims::TwoD(4, 2));
+        let mask = [true, false, false, true, false, true, true, false];
+        let s = k.select(&mask).unwrap();
+        let expected: Vec<KSample> = [0, 3, 5, 6].iter().map(|&i| k.kspace()[i].clone()).collect();
+        assert_eq!(s.kspace(), &expected[..]);
+        assert_eq!(s.sample_times(), None);
+
+        k.set_times(SampleTiming::Dwell {
+            start: 1.0,
+            dwell: 0.5,
+        });
+        let s = k.select(&mask).unwrap();
+        assert_eq!(s.num_samples(), 4);
+        assert_eq!(s.sample_times(), Some(vec![1.0, 2.5, 3.5, 4.0]));
+        assert!(k.select(&mask[1..]).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+/*
+ * Copyright 2018 Stefan Kroboth
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ *
+ * Writes ismrmrd_cartesian.h5, a small Cartesian ISMRMRD dataset, with the HDF5 types and
+ * layout of the dataset writer of the ISMRMRD reference library (packed headers, ASCII XML
+ * string, chunked acquisitions with variable length trajectory and data).
+ *
+ *     cc ismrmrd_cartesian.c -lhdf5 && ./a.out
+ *
+ * The dataset has one noise measurement (2 channels, 8 samples) and four phase encoding lines
+ * of 10 samples with 2 channels, a dwell time of 2.5 us, one discarded sample at either end
+ * of the readout and the center of the readout at sample 5. Sample `s` of channel `c` of line
+ * `l` is `(100 c + 10 l + s) - i s`.
+ */
+
+#include <hdf5.h>
+#include <stdint.h>
+#include <string.h>
+
+#pragma pack(push, 2)
+typedef struct {
+    uint16_t kspace_encode_step_1;
+    uint16_t kspace_encode_step_2;
+    uint16_t average;
+    uint16_t slice;
+    uint16_t contrast;
+    uint16_t phase;
+    uint16_t repetition;
+    uint16_t set;
+    uint16_t segment;
+    uint16_t user[8];
+} EncodingCounters;
+
+typedef struct {
+    uint16_t version;
+    uint64_t flags;
+    uint32_t measurement_uid;
+    uint32_t scan_counter;
+    uint32_t acquisition_time_stamp;
+    uint32_t physiology_time_stamp[3];
+    uint16_t number_of_samples;
+    uint16_t available_channels;
+    uint16_t active_channels;
+    uint64_t channel_mask[16];
+    uint16_t discard_pre;
+    uint16_t discard_post;
+    uint16_t center_sample;
+    uint16_t encoding_space_ref;
+    uint16_t trajectory_dimensions;
+    float sample_time_us;
+    float position[3];
+    float read_dir[3];
+    float phase_dir[3];
+    float slice_dir[3];
+    float patient_table_position[3];
+    EncodingCounters idx;
+    int32_t user_int[8];
+    float user_float[8];
+} AcquisitionHeader;
+#pragma pack(pop)
+
+typedef struct {
+    AcquisitionHeader head;
+    hvl_t traj;
+    hvl_t data;
+} Acquisition;
+
+static const char *XML =
+    "<?xml version=\"1.0\"?>\n"
+    "<ismrmrdHeader xmlns=\"http://www.ismrm.org/ISMRMRD\">\n"
+    "  <encoding>\n"
+    "    <encodedSpace>\n"
+    "      <matrixSize><x>8</x><y>4</y><z>1</z></matrixSize>\n"
+    "      <fieldOfView_mm><x>200</x><y>100</y><z>5</z></fieldOfView_mm>\n"
+    "    </encodedSpace>\n"
+    "    <reconSpace>\n"
+    "      <matrixSize><x>4</x><y>4</y><z>1</z></matrixSize>\n"
+    "      <fieldOfView_mm><x>100</x><y>100</y><z>5</z></fieldOfView_mm>\n"
+    "    </reconSpace>\n"
+    "    <encodingLimits>\n"
+    "      <kspace_encoding_step_1><minimum>0</minimum><maximum>3</maximum>"
+    "<center>2</center></kspace_encoding_step_1>\n"
+    "    </encodingLimits>\n"
+    "    <trajectory>cartesian</trajectory>\n"
+    "  </encoding>\n"
+    "</ismrmrdHeader>\n";
+
+static hid_t array_type(hid_t base, hsize_t n) { return H5Tarray_create2(base, 1, &n); }
+
+static hid_t counters_type(void) {
+    hid_t t = H5Tcreate(H5T_COMPOUND, sizeof(EncodingCounters));
+    H5Tinsert(t, "kspace_encode_step_1", HOFFSET(EncodingCounters, kspace_encode_step_1),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "kspace_encode_step_2", HOFFSET(EncodingCounters, kspace_encode_step_2),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "average", HOFFSET(EncodingCounters, average), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "slice", HOFFSET(EncodingCounters, slice), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "contrast", HOFFSET(EncodingCounters, contrast), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "phase", HOFFSET(EncodingCounters, phase), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "repetition", HOFFSET(EncodingCounters, repetition), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "set", HOFFSET(EncodingCounters, set), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "segment", HOFFSET(EncodingCounters, segment), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "user", HOFFSET(EncodingCounters, user), array_type(H5T_NATIVE_UINT16, 8));
+    return t;
+}
+
+static hid_t header_type(void) {
+    hid_t t = H5Tcreate(H5T_COMPOUND, sizeof(AcquisitionHeader));
+    hid_t f3 = array_type(H5T_NATIVE_FLOAT, 3);
+    H5Tinsert(t, "version", HOFFSET(AcquisitionHeader, version), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "flags", HOFFSET(AcquisitionHeader, flags), H5T_NATIVE_UINT64);
+    H5Tinsert(t, "measurement_uid", HOFFSET(AcquisitionHeader, measurement_uid),
+              H5T_NATIVE_UINT32);
+    H5Tinsert(t, "scan_counter", HOFFSET(AcquisitionHeader, scan_counter), H5T_NATIVE_UINT32);
+    H5Tinsert(t, "acquisition_time_stamp", HOFFSET(AcquisitionHeader, acquisition_time_stamp),
+              H5T_NATIVE_UINT32);
+    H5Tinsert(t, "physiology_time_stamp", HOFFSET(AcquisitionHeader, physiology_time_stamp),
+              array_type(H5T_NATIVE_UINT32, 3));
+    H5Tinsert(t, "number_of_samples", HOFFSET(AcquisitionHeader, number_of_samples),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "available_channels", HOFFSET(AcquisitionHeader, available_channels),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "active_channels", HOFFSET(AcquisitionHeader, active_channels),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "channel_mask", HOFFSET(AcquisitionHeader, channel_mask),
+              array_type(H5T_NATIVE_UINT64, 16));
+    H5Tinsert(t, "discard_pre", HOFFSET(AcquisitionHeader, discard_pre), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "discard_post", HOFFSET(AcquisitionHeader, discard_post), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "center_sample", HOFFSET(AcquisitionHeader, center_sample), H5T_NATIVE_UINT16);
+    H5Tinsert(t, "encoding_space_ref", HOFFSET(AcquisitionHeader, encoding_space_ref),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "trajectory_dimensions", HOFFSET(AcquisitionHeader, trajectory_dimensions),
+              H5T_NATIVE_UINT16);
+    H5Tinsert(t, "sample_time_us", HOFFSET(AcquisitionHeader, sample_time_us), H5T_NATIVE_FLOAT);
+    H5Tinsert(t, "position", HOFFSET(AcquisitionHeader, position), f3);
+    H5Tinsert(t, "read_dir", HOFFSET(AcquisitionHeader, read_dir), f3);
+    H5Tinsert(t, "phase_dir", HOFFSET(AcquisitionHeader, phase_dir), f3);
+    H5Tinsert(t, "slice_dir", HOFFSET(AcquisitionHeader, slice_dir), f3);
+    H5Tinsert(t, "patient_table_position", HOFFSET(AcquisitionHeader, patient_table_position),
+              f3);
+    H5Tinsert(t, "idx", HOFFSET(AcquisitionHeader, idx), counters_type());
+    H5Tinsert(t, "user_int", HOFFSET(AcquisitionHeader, user_int),
+              array_type(H5T_NATIVE_INT32, 8));
+    H5Tinsert(t, "user_float", HOFFSET(AcquisitionHeader, user_float),
+              array_type(H5T_NATIVE_FLOAT, 8));
+    return t;
+}
+
+static hid_t acquisition_type(void) {
+    hid_t t = H5Tcreate(H5T_COMPOUND, sizeof(Acquisition));
+    H5Tinsert(t, "head", HOFFSET(Acquisition, head), header_type());
+    H5Tinsert(t, "traj", HOFFSET(Acquisition, traj), H5Tvlen_create(H5T_NATIVE_FLOAT));
+    H5Tinsert(t, "data", HOFFSET(Acquisition, data), H5Tvlen_create(H5T_NATIVE_FLOAT));
+    return t;
+}
+
+int main(void) {
+    enum { LINES = 4, SAMPLES = 10, NOISE_SAMPLES = 8, CHANNELS = 2 };
+    static float data[LINES + 1][2 * CHANNELS * SAMPLES];
+    Acquisition acq[LINES + 1];
+    memset(acq, 0, sizeof(acq));
+
+    for (int a = 0; a <= LINES; a++) {
+        AcquisitionHeader *h = &acq[a].head;
+        int noise = a == 0;
+        int line = a - 1;
+        int ns = noise ? NOISE_SAMPLES : SAMPLES;
+        h->version = 1;
+        h->scan_counter = a;
+        h->number_of_samples = ns;
+        h->available_channels = CHANNELS;
+        h->active_channels = CHANNELS;
+        h->channel_mask[0] = 3;
+        h->read_dir[0] = 1.0f;
+        h->phase_dir[1] = 1.0f;
+        h->slice_dir[2] = 1.0f;
+        if (noise) {
+            h->flags = 1ull << 18; /* ACQ_IS_NOISE_MEASUREMENT */
+            h->sample_time_us = 5.0f;
+        } else {
+            h->discard_pre = 1;
+            h->discard_post = 1;
+            h->center_sample = 5;
+            h->sample_time_us = 2.5f;
+            h->idx.kspace_encode_step_1 = line;
+            if (line == LINES - 1) {
+                h->flags = 1ull << 24; /* ACQ_LAST_IN_MEASUREMENT */
+            }
+        }
+        for (int c = 0; c < CHANNELS; c++) {
+            for (int s = 0; s < ns; s++) {
+                float *x = &data[a][2 * (c * ns + s)];
+                x[0] = noise ? 0.5f * c - 0.25f * s : 100.0f * c + 10.0f * line + s;
+                x[1] = noise ? 0.125f * s : -(float)s;
+            }
+        }
+        acq[a].traj.len = 0;
+        acq[a].traj.p = NULL;
+        acq[a].data.len = 2 * CHANNELS * ns;
+        acq[a].data.p = data[a];
+    }
+
+    hid_t file = H5Fcreate("ismrmrd_cartesian.h5", H5F_ACC_TRUNC, H5P_DEFAULT, H5P_DEFAULT);
+    hid_t group = H5Gcreate2(file, "dataset", H5P_DEFAULT, H5P_DEFAULT, H5P_DEFAULT);
+
+    hid_t xml_type = H5Tcopy(H5T_C_S1);
+    H5Tset_size(xml_type, H5T_VARIABLE);
+    hsize_t one = 1;
+    hid_t space = H5Screate_simple(1, &one, NULL);
+    hid_t xml = H5Dcreate2(group, "xml", xml_type, space, H5P_DEFAULT, H5P_DEFAULT, H5P_DEFAULT);
+    H5Dwrite(xml, xml_type, H5S_ALL, H5S_ALL, H5P_DEFAULT, &XML);
+    H5Dclose(xml);
+    H5Sclose(space);
+
+    hsize_t n = LINES + 1, max = H5S_UNLIMITED;
+    space = H5Screate_simple(1, &n, &max);
+    hid_t props = H5Pcreate(H5P_DATASET_CREATE);
+    H5Pset_chunk(props, 1, &one);
+    hid_t type = acquisition_type();
+    hid_t dset = H5Dcreate2(group, "data", type, space, H5P_DEFAULT, props, H5P_DEFAULT);
+    H5Dwrite(dset, type, H5S_ALL, H5S_ALL, H5P_DEFAULT, acq);
+    H5Dclose(dset);
+    H5Pclose(props);
+    H5Sclose(space);
+    H5Gclose(group);
+    H5Fclose(file);
+    return 0;
+}